@@ -0,0 +1,657 @@
+//! A command-line tool for inspecting and running schemerz migrations against
+//! a SQLite file or a PostgreSQL database.
+//!
+//! The `schemerz` binary provided by this crate registers the migrations in a
+//! directory of SQL files, laid out as described in `schemerz::sql`:
+//!
+//! ```text
+//! schemerz --database app.db --migrations migrations status
+//! schemerz --database postgresql://postgres@localhost/app --migrations migrations up
+//! ```
+//!
+//! Applications whose migrations are written in Rust instead expose them
+//! through a small binary of their own, which behaves the same way:
+//!
+//! ```no_run
+//! # extern crate rusqlite;
+//! # extern crate schemerz_cli;
+//! # extern crate schemerz_rusqlite;
+//! use std::process::ExitCode;
+//!
+//! use schemerz_cli::Cli;
+//! use schemerz_rusqlite::RusqliteMigration;
+//!
+//! fn migrations() -> Vec<Box<dyn RusqliteMigration<Error = rusqlite::Error>>> {
+//!     // Return the application's migrations here.
+//!     vec![]
+//! }
+//!
+//! fn main() -> ExitCode {
+//!     Cli::new().with_sqlite_migrations(migrations()).run()
+//! }
+//! ```
+#![warn(clippy::all)]
+#![forbid(unsafe_code)]
+
+use std::cell::RefCell;
+use std::error::Error as _;
+use std::ffi::OsString;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::rc::Rc;
+use std::time::Duration;
+
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use postgres::{Client, Error as PostgresError, NoTls};
+use rusqlite::{Connection, Error as RusqliteError, OpenFlags};
+use thiserror::Error;
+use uuid::Uuid;
+
+use schemerz::sql::LoadError;
+use schemerz::{
+    Adapter, MigrationDirection, MigrationObserver, MigrationStatus, Migrator, MigratorError,
+    PlannedMigration, TableName, TableNameError,
+};
+use schemerz_postgres::{PostgresAdapter, PostgresMigration};
+use schemerz_rusqlite::{RusqliteAdapter, RusqliteMigration};
+
+use crate::scaffold::{NameError, Scaffold, ScaffoldError};
+
+pub mod scaffold;
+
+/// A migration that can be run by [`RusqliteAdapter`].
+pub type SqliteMigration = Box<dyn RusqliteMigration<Error = RusqliteError>>;
+
+/// Error resulting from running the command-line tool.
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Args(#[from] clap::Error),
+    #[error("Failed to load migrations")]
+    Load(#[from] LoadError),
+    #[error("Invalid metadata table name")]
+    TableName(#[from] TableNameError),
+    #[error(transparent)]
+    Sqlite(#[from] MigratorError<Uuid, RusqliteError>),
+    #[error(transparent)]
+    Postgres(#[from] MigratorError<Uuid, PostgresError>),
+    #[error(transparent)]
+    Name(#[from] NameError),
+    #[error("Failed to write output")]
+    Io(#[from] io::Error),
+}
+
+impl<E: std::error::Error + 'static> From<ScaffoldError<E>> for Error
+where
+    Error: From<MigratorError<Uuid, E>>,
+{
+    fn from(e: ScaffoldError<E>) -> Self {
+        match e {
+            ScaffoldError::Name(e) => e.into(),
+            ScaffoldError::Migrator(e) => e.into(),
+        }
+    }
+}
+
+/// Inspect and run schemerz migrations.
+#[derive(Debug, Parser)]
+#[command(version)]
+struct Args {
+    /// The database: a path to a SQLite file, optionally prefixed with
+    /// `sqlite:`, or a `postgres://` or `postgresql://` URL.
+    #[arg(short, long, env = "SCHEMERZ_DATABASE")]
+    database: String,
+
+    /// A directory of SQL migrations to register.
+    #[arg(short, long)]
+    migrations: Option<PathBuf>,
+
+    /// The name of the metadata table, as written in SQL, optionally qualified
+    /// by its schema, such as `app.schemerz`.
+    #[arg(long)]
+    table: Option<String>,
+
+    /// The schema, or attached SQLite database, the metadata table is in,
+    /// instead of any given by `--table`.
+    #[arg(long, requires = "table")]
+    schema: Option<String>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Show which migrations are applied and which are pending.
+    Status,
+    /// Show the migrations `up` or `down` would run, without running them.
+    Plan {
+        /// Show the migrations `down` would revert.
+        #[arg(long)]
+        down: bool,
+        /// The target migrations. If there are none, every migration is
+        /// targeted.
+        targets: Vec<Uuid>,
+    },
+    /// Apply the target migrations and their dependencies.
+    Up {
+        /// The target migrations. If there are none, every migration is
+        /// applied.
+        targets: Vec<Uuid>,
+    },
+    /// Revert the migrations that depend on the target migrations.
+    Down {
+        /// The target migrations, which are themselves left applied.
+        #[arg(required_unless_present = "all")]
+        targets: Vec<Uuid>,
+        /// Revert every migration.
+        #[arg(long, conflicts_with = "targets")]
+        all: bool,
+    },
+    /// Print the dependency graph, with applied migrations highlighted.
+    Graph {
+        #[arg(long, value_enum, default_value_t = GraphFormat::Dot)]
+        format: GraphFormat,
+    },
+    /// Record migrations as applied without running them.
+    MarkApplied {
+        /// The migrations, whose dependencies must already be applied.
+        #[arg(required = true)]
+        ids: Vec<Uuid>,
+    },
+    /// Create the skeleton of a new migration, with a fresh ID.
+    New {
+        /// The description of the migration, from which its name is derived.
+        description: String,
+        /// The migrations it depends on, instead of the leaves of the
+        /// dependency graph.
+        #[arg(long, value_delimiter = ',')]
+        depends_on: Vec<Uuid>,
+        /// Print a Rust definition instead of writing SQL files into the
+        /// migrations directory.
+        #[arg(long)]
+        rust: bool,
+    },
+}
+
+impl Command {
+    /// Whether this command only reads the database.
+    fn is_read_only(&self) -> bool {
+        matches!(
+            self,
+            Command::Status | Command::Plan { .. } | Command::Graph { .. } | Command::New { .. }
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum GraphFormat {
+    /// The Graphviz DOT language.
+    Dot,
+    /// A Mermaid flowchart.
+    Mermaid,
+}
+
+/// A migration that was run, and how long it took.
+type Run = (PlannedMigration<Uuid>, Duration);
+
+/// Records the migrations run by a `Migrator`, to report once it returns.
+#[derive(Clone, Default)]
+struct Progress(Rc<RefCell<Vec<Run>>>);
+
+impl MigrationObserver<Uuid> for Progress {
+    fn after_apply(&mut self, migration: &PlannedMigration<Uuid>, duration: Duration) {
+        self.0.borrow_mut().push((migration.clone(), duration));
+    }
+
+    fn after_revert(&mut self, migration: &PlannedMigration<Uuid>, duration: Duration) {
+        self.0.borrow_mut().push((migration.clone(), duration));
+    }
+}
+
+/// The command-line tool, with the migrations it registers.
+///
+/// Migrations are registered for each kind of database separately, since
+/// they are run by different adapters. Those loaded from the directory given
+/// by the `--migrations` option are registered in addition to them.
+#[derive(Default)]
+pub struct Cli {
+    sqlite_migrations: Vec<SqliteMigration>,
+    postgres_migrations: Vec<Box<dyn PostgresMigration>>,
+}
+
+impl Cli {
+    /// Construct the command-line tool, without any migrations.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register migrations to run against SQLite databases.
+    pub fn with_sqlite_migrations(
+        mut self,
+        migrations: impl IntoIterator<Item = SqliteMigration>,
+    ) -> Self {
+        self.sqlite_migrations.extend(migrations);
+        self
+    }
+
+    /// Register migrations to run against PostgreSQL databases.
+    pub fn with_postgres_migrations(
+        mut self,
+        migrations: impl IntoIterator<Item = Box<dyn PostgresMigration>>,
+    ) -> Self {
+        self.postgres_migrations.extend(migrations);
+        self
+    }
+
+    /// Run the command given by the process's arguments, printing its output
+    /// and any error.
+    pub fn run(self) -> ExitCode {
+        match self.run_with_args(std::env::args_os(), &mut io::stdout().lock()) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(Error::Args(e)) => e.exit(),
+            Err(e) => {
+                eprintln!("error: {}", e);
+                let mut source = e.source();
+                while let Some(e) = source {
+                    eprintln!("  caused by: {}", e);
+                    source = e.source();
+                }
+                ExitCode::FAILURE
+            }
+        }
+    }
+
+    /// Run the command given by `args`, whose first element is the name of the
+    /// program, writing its output to `out`.
+    ///
+    /// Commands that only read the database, such as `status`, run in a
+    /// transaction that is rolled back, so they never modify it, and fail if a
+    /// SQLite file does not exist rather than creating it.
+    pub fn run_with_args(
+        self,
+        args: impl IntoIterator<Item = impl Into<OsString> + Clone>,
+        out: &mut dyn Write,
+    ) -> Result<(), Error> {
+        let args = Args::try_parse_from(args)?;
+        let table_name = match (&args.schema, &args.table) {
+            (_, None) => None,
+            (None, Some(table)) => Some(TableName::parse(table)?),
+            (Some(schema), Some(table)) => Some(TableName::parse(table)?.with_schema(schema)?),
+        };
+
+        if args.database.starts_with("postgres://") || args.database.starts_with("postgresql://") {
+            let mut migrations = self.postgres_migrations;
+            if let Some(dir) = &args.migrations {
+                migrations.extend(schemerz_postgres::load_sql_migrations(dir)?);
+            }
+
+            let mut client =
+                Client::connect(&args.database, NoTls).map_err(MigratorError::Adapter)?;
+            let mut adapter = if args.command.is_read_only() {
+                PostgresAdapter::new_transactional(&mut client, table_name)
+                    .map_err(MigratorError::Adapter)?
+            } else {
+                PostgresAdapter::new(&mut client, table_name)
+            };
+            adapter.init().map_err(MigratorError::Adapter)?;
+            execute(adapter, migrations, &args, out)
+        } else {
+            let mut migrations = self.sqlite_migrations;
+            if let Some(dir) = &args.migrations {
+                migrations.extend(schemerz_rusqlite::load_sql_migrations(dir)?);
+            }
+
+            let path = args
+                .database
+                .strip_prefix("sqlite:")
+                .unwrap_or(&args.database);
+            // Read-only commands fail on a database that does not exist, rather
+            // than creating an empty one.
+            let flags = if args.command.is_read_only() {
+                OpenFlags::default().difference(OpenFlags::SQLITE_OPEN_CREATE)
+            } else {
+                OpenFlags::default()
+            };
+            let mut conn =
+                Connection::open_with_flags(path, flags).map_err(MigratorError::Adapter)?;
+            let adapter = if args.command.is_read_only() {
+                RusqliteAdapter::new_transactional(&mut conn, table_name)
+                    .map_err(MigratorError::Adapter)?
+            } else {
+                RusqliteAdapter::new(&mut conn, table_name)
+            };
+            adapter.init().map_err(MigratorError::Adapter)?;
+            execute(adapter, migrations, &args, out)
+        }
+    }
+}
+
+/// Run the command given by `args` with a migrator using `adapter`, with
+/// `migrations` registered.
+fn execute<A>(
+    adapter: A,
+    migrations: Vec<A::MigrationType>,
+    args: &Args,
+    out: &mut dyn Write,
+) -> Result<(), Error>
+where
+    A: Adapter<Uuid>,
+    Error: From<MigratorError<Uuid, A::Error>>,
+{
+    let progress = Progress::default();
+    let mut migrator = Migrator::new(adapter).with_observer(progress.clone());
+    migrator.register_multiple(migrations.into_iter())?;
+
+    let result = match &args.command {
+        Command::Status => return write_status(migrator.status()?, out),
+        Command::Plan {
+            down: false,
+            targets,
+        } if targets.is_empty() => return write_plan(migrator.plan_up(None)?, out),
+        Command::Plan {
+            down: false,
+            targets,
+        } => return write_plan(migrator.plan_up_many(targets.iter().copied())?, out),
+        Command::Plan {
+            down: true,
+            targets,
+        } if targets.is_empty() => return write_plan(migrator.plan_down(None)?, out),
+        Command::Plan {
+            down: true,
+            targets,
+        } => return write_plan(migrator.plan_down_many(targets.iter().copied())?, out),
+        Command::Graph { format } => {
+            let graph = match format {
+                GraphFormat::Dot => migrator.to_dot(true)?,
+                GraphFormat::Mermaid => migrator.to_mermaid(true)?,
+            };
+            return Ok(writeln!(out, "{}", graph)?);
+        }
+        Command::MarkApplied { ids } => {
+            for id in ids {
+                migrator.mark_applied(*id)?;
+                writeln!(out, "{:<10} {}", "recorded", id)?;
+            }
+            return Ok(());
+        }
+        Command::New {
+            description,
+            depends_on,
+            rust,
+        } => {
+            let mut scaffold = Scaffold::new(&mut migrator, description)?;
+            if !depends_on.is_empty() {
+                scaffold = scaffold.with_dependencies(depends_on.iter().copied());
+            }
+            if *rust {
+                write!(out, "{}", scaffold.to_rust())?;
+            } else {
+                let dir = args.migrations.as_ref().ok_or_else(|| {
+                    Args::command().error(
+                        ErrorKind::MissingRequiredArgument,
+                        "--migrations is required to write SQL files",
+                    )
+                })?;
+                for path in scaffold.write_sql(dir)? {
+                    writeln!(out, "{:<10} {}", "created", path.display())?;
+                }
+            }
+            return Ok(());
+        }
+        Command::Up { targets } if targets.is_empty() => migrator.up(None),
+        Command::Up { targets } => migrator.up_many(targets.iter().copied()),
+        Command::Down { all: true, .. } => migrator.down(None),
+        Command::Down { targets, .. } => migrator.down_many(targets.iter().copied()),
+    };
+
+    // Report the migrations that were run, even if a later one failed.
+    for (migration, duration) in progress.0.borrow().iter() {
+        let verb = match (migration.direction, migration.recorded_only) {
+            (MigrationDirection::Up, false) if migration.reapply => "reapplied",
+            (MigrationDirection::Up, false) => "applied",
+            (MigrationDirection::Down, false) => "reverted",
+            (MigrationDirection::Up, true) => "recorded",
+            (MigrationDirection::Down, true) => "unrecorded",
+        };
+        writeln!(
+            out,
+            "{:<10} {}  {} ({} ms)",
+            verb,
+            migration.id,
+            migration.description,
+            duration.as_millis()
+        )?;
+    }
+    Ok(result?)
+}
+
+fn write_status(status: MigrationStatus<Uuid>, out: &mut dyn Write) -> Result<(), Error> {
+    for migration in &status.applied {
+        let state = if status.drifted.contains(&migration.id) {
+            "drifted"
+        } else if status.outdated.contains(&migration.id) {
+            "outdated"
+        } else {
+            "applied"
+        };
+        writeln!(
+            out,
+            "{:<10} {}  {}",
+            state, migration.id, migration.description
+        )?;
+    }
+    for migration in &status.pending {
+        writeln!(
+            out,
+            "{:<10} {}  {}",
+            "pending", migration.id, migration.description
+        )?;
+    }
+    for id in &status.unknown {
+        writeln!(out, "{:<10} {}", "unknown", id)?;
+    }
+    Ok(())
+}
+
+fn write_plan(plan: Vec<PlannedMigration<Uuid>>, out: &mut dyn Write) -> Result<(), Error> {
+    if plan.is_empty() {
+        writeln!(out, "Nothing to do.")?;
+    }
+    for migration in plan {
+        let verb = match (migration.direction, migration.recorded_only) {
+            (MigrationDirection::Up, false) if migration.reapply => "reapply",
+            (MigrationDirection::Up, false) => "apply",
+            (MigrationDirection::Down, false) => "revert",
+            (MigrationDirection::Up, true) => "record",
+            (MigrationDirection::Down, true) => "unrecord",
+        };
+        writeln!(
+            out,
+            "{:<10} {}  {}",
+            verb, migration.id, migration.description
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use schemerz::Migration;
+
+    use super::*;
+
+    const FIRST: &str = "4885e8ab-dafa-4d76-a565-2dee8b04ef60";
+    const SECOND: &str = "0b0a4ca6-4a5b-4b8e-9fd4-29b0bd4d48ee";
+
+    /// Write two migrations into a fresh directory, creating tables whose
+    /// names end with `suffix`.
+    fn write_migrations(dir: &Path, suffix: &str) {
+        fs::create_dir_all(dir).unwrap();
+        fs::write(
+            dir.join(format!("{}_users.up.sql", FIRST)),
+            format!(
+                "-- description: Create users\nCREATE TABLE users{} (id INTEGER PRIMARY KEY);",
+                suffix
+            ),
+        )
+        .unwrap();
+        fs::write(
+            dir.join(format!("{}_users.down.sql", FIRST)),
+            format!("DROP TABLE users{};", suffix),
+        )
+        .unwrap();
+        fs::write(
+            dir.join(format!("{}_posts.up.sql", SECOND)),
+            format!(
+                "-- description: Create posts\n-- depends_on: {}\nCREATE TABLE posts{} (id INTEGER PRIMARY KEY);",
+                FIRST, suffix
+            ),
+        )
+        .unwrap();
+        fs::write(
+            dir.join(format!("{}_posts.down.sql", SECOND)),
+            format!("DROP TABLE IF EXISTS posts{};", suffix),
+        )
+        .unwrap();
+    }
+
+    fn run(database: &str, migrations: &Path, args: &[&str]) -> Result<String, Error> {
+        let mut out = Vec::new();
+        Cli::new().run_with_args(
+            ["schemerz", "--database", database, "--migrations"]
+                .into_iter()
+                .map(OsString::from)
+                .chain([migrations.as_os_str().to_owned()])
+                .chain(args.iter().map(OsString::from)),
+            &mut out,
+        )?;
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn test_sqlite() {
+        let dir = std::env::temp_dir().join(format!("schemerz-cli-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let migrations = dir.join("migrations");
+        write_migrations(&migrations, "");
+        let path = dir.join("test.db");
+        let database = format!("sqlite:{}", path.display());
+
+        // Read-only commands do not create the database.
+        assert!(matches!(
+            run(&database, &migrations, &["status"]),
+            Err(Error::Sqlite(MigratorError::Adapter(_)))
+        ));
+        assert!(!path.exists());
+        let tables = |conn: &Connection| -> i64 {
+            conn.query_row("SELECT count(*) FROM sqlite_master", [], |row| row.get(0))
+                .unwrap()
+        };
+        let conn = Connection::open(&path).unwrap();
+
+        let status = run(&database, &migrations, &["status"]).unwrap();
+        assert!(status.contains(&format!("pending    {}  Create users", FIRST)));
+        assert!(status.contains(&format!("pending    {}  Create posts", SECOND)));
+
+        let plan = run(&database, &migrations, &["plan", FIRST]).unwrap();
+        assert_eq!(plan, format!("apply      {}  Create users\n", FIRST));
+
+        // Read-only commands leave the database untouched, including the
+        // metadata tables that initializing the adapter creates.
+        assert_eq!(tables(&conn), 0);
+
+        let up = run(&database, &migrations, &["up", FIRST]).unwrap();
+        assert!(up.starts_with(&format!("applied    {}  Create users", FIRST)));
+
+        let graph = run(&database, &migrations, &["graph", "--format", "mermaid"]).unwrap();
+        assert!(graph.starts_with("graph TD"));
+
+        let status = run(&database, &migrations, &["status"]).unwrap();
+        assert!(status.contains(&format!("applied    {}  Create users", FIRST)));
+        assert!(status.contains(&format!("pending    {}  Create posts", SECOND)));
+        assert_ne!(tables(&conn), 0);
+
+        let marked = run(&database, &migrations, &["mark-applied", SECOND]).unwrap();
+        assert_eq!(marked, format!("recorded   {}\n", SECOND));
+        let plan = run(&database, &migrations, &["plan"]).unwrap();
+        assert_eq!(plan, "Nothing to do.\n");
+
+        let down = run(&database, &migrations, &["down", "--all"]).unwrap();
+        assert!(down.starts_with(&format!("reverted   {}  Create posts", SECOND)));
+        assert!(down.contains(&format!("reverted   {}  Create users", FIRST)));
+
+        assert!(matches!(
+            run(&database, &migrations, &["down"]),
+            Err(Error::Args(_))
+        ));
+        assert!(matches!(
+            run(&database, &migrations, &["mark-applied", SECOND]),
+            Err(Error::Sqlite(MigratorError::Dependency(_)))
+        ));
+
+        // New migrations depend on the leaves of the graph by default.
+        let rust = run(
+            &database,
+            &migrations,
+            &["new", "--rust", "Create comments"],
+        )
+        .unwrap();
+        assert!(rust.contains("pub struct CreateComments;"));
+        assert!(rust.contains("#[derive(Migration)]"));
+        assert!(rust.contains(&format!("depends_on = [\"{}\"],", SECOND)));
+        let created = run(&database, &migrations, &["new", "Create comments"]).unwrap();
+        assert_eq!(created.lines().count(), 2);
+        let loaded = schemerz::sql::load_dir(&migrations).unwrap();
+        let comments = loaded
+            .iter()
+            .find(|m| m.name() == "create_comments")
+            .unwrap();
+        assert_eq!(comments.description(), "Create comments");
+        assert_eq!(
+            comments.dependencies(),
+            [SECOND.parse().unwrap()].into_iter().collect()
+        );
+        let rust = run(
+            &database,
+            &migrations,
+            &["new", "--rust", "--depends-on", FIRST, "Create likes"],
+        )
+        .unwrap();
+        assert!(rust.contains(&format!("depends_on = [\"{}\"],", FIRST)));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_postgres() {
+        let suffix = format!("_schemerz_cli_{}", std::process::id());
+        let dir = std::env::temp_dir().join(format!("schemerz-cli-pg-{}", std::process::id()));
+        write_migrations(&dir, &suffix);
+        let database = "postgresql://postgres@localhost";
+        let table = format!("_schemerz{}", suffix);
+        let run = |args: &[&str]| run(database, &dir, &[&["--table", &table], args].concat());
+
+        assert!(run(&["status"]).unwrap().starts_with("pending"));
+        let up = run(&["up"]).unwrap();
+        assert_eq!(up.lines().count(), 2);
+        let status = run(&["status"]).unwrap();
+        assert!(status.lines().all(|line| line.starts_with("applied")));
+        run(&["down", "--all"]).unwrap();
+        assert!(run(&["status"]).unwrap().starts_with("pending"));
+
+        let mut client = Client::connect(database, NoTls).unwrap();
+        client
+            .batch_execute(&format!(
+                "DROP TABLE {0}, {0}_history, {0}_dirty, {0}_layout;",
+                table
+            ))
+            .unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,264 @@
+//! Skeletons of new migrations.
+
+use std::fmt::Write as _;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+use uuid::Uuid;
+
+use schemerz::{Adapter, Migration, Migrator, MigratorError};
+
+/// Error resulting from naming a migration after a description with no ASCII
+/// letters or digits, from which [`Scaffold::new`] cannot derive a name.
+#[derive(Debug, Error)]
+#[error("Cannot name a migration after {0:?}, which has no ASCII letters or digits")]
+pub struct NameError(pub String);
+
+/// Error resulting from constructing a [`Scaffold`].
+#[derive(Debug, Error)]
+pub enum ScaffoldError<E: std::error::Error + 'static> {
+    #[error(transparent)]
+    Name(#[from] NameError),
+    #[error(transparent)]
+    Migrator(#[from] MigratorError<Uuid, E>),
+}
+
+/// The skeleton of a new migration, with a fresh ID.
+///
+/// By default the migration depends on the current leaves of the dependency
+/// graph, so that it is ordered after every registered migration.
+///
+/// ```
+/// # extern crate rusqlite;
+/// # extern crate schemerz;
+/// # extern crate schemerz_cli;
+/// # extern crate schemerz_rusqlite;
+/// use schemerz::Migrator;
+/// use schemerz_cli::scaffold::Scaffold;
+/// use schemerz_rusqlite::RusqliteAdapter;
+///
+/// let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+/// let mut migrator: Migrator<_, RusqliteAdapter<rusqlite::Error>> =
+///     Migrator::new(RusqliteAdapter::new(&mut conn, None));
+/// // Register migrations here.
+///
+/// let scaffold = Scaffold::new(&mut migrator, "Add users email").unwrap();
+/// assert_eq!(scaffold.name(), "add_users_email");
+/// assert!(scaffold.to_rust().contains("pub struct AddUsersEmail;"));
+/// ```
+#[derive(Clone, Debug)]
+pub struct Scaffold {
+    id: Uuid,
+    name: String,
+    description: String,
+    dependencies: Vec<Uuid>,
+}
+
+impl Scaffold {
+    /// Construct the skeleton of a migration described by `description`, that
+    /// depends on the leaves of `migrator`'s dependency graph.
+    ///
+    /// The migration's name, used for its files and type, is `description` in
+    /// `snake_case`, keeping only ASCII letters and digits. Fails if that
+    /// leaves nothing to name the migration by.
+    pub fn new<A: Adapter<Uuid>>(
+        migrator: &mut Migrator<Uuid, A>,
+        description: &str,
+    ) -> Result<Self, ScaffoldError<A::Error>> {
+        let name = snake_case(description);
+        if name.is_empty() {
+            return Err(NameError(description.to_owned()).into());
+        }
+        let graph = migrator.graph()?;
+        let dependencies = graph.leaves().map(|m| m.id()).collect();
+        Ok(Scaffold {
+            id: Uuid::new_v4(),
+            name,
+            description: description.to_owned(),
+            dependencies,
+        })
+    }
+
+    /// Replace the migration's dependencies.
+    pub fn with_dependencies(mut self, dependencies: impl IntoIterator<Item = Uuid>) -> Self {
+        self.dependencies = dependencies.into_iter().collect();
+        self
+    }
+
+    /// Returns the ID of the migration.
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    /// Returns the name of the migration.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the dependencies of the migration.
+    pub fn dependencies(&self) -> &[Uuid] {
+        &self.dependencies
+    }
+
+    /// Returns a Rust definition of the migration, using
+    /// `#[derive(schemerz_macros::Migration)]`.
+    ///
+    /// The adapter's migration trait is left to be implemented.
+    pub fn to_rust(&self) -> String {
+        let mut rust = String::from("use schemerz_macros::Migration;\n\n");
+        writeln!(rust, "#[derive(Migration)]").unwrap();
+        writeln!(rust, "#[migration(").unwrap();
+        writeln!(rust, "    id = \"{}\",", self.id).unwrap();
+        if !self.dependencies.is_empty() {
+            let dependencies: Vec<_> = self
+                .dependencies
+                .iter()
+                .map(|id| format!("\"{}\"", id))
+                .collect();
+            writeln!(rust, "    depends_on = [{}],", dependencies.join(", ")).unwrap();
+        }
+        writeln!(rust, "    description = {:?}", self.description).unwrap();
+        writeln!(rust, ")]").unwrap();
+        writeln!(rust, "pub struct {};", camel_case(&self.name)).unwrap();
+        rust
+    }
+
+    /// Write the migration into `dir` as SQL files, laid out as described in
+    /// `schemerz::sql`, returning their paths.
+    ///
+    /// The `.down.sql` file is empty, and should be deleted if the migration is
+    /// irreversible. Existing files are never overwritten, and if either file
+    /// cannot be created, neither is left behind.
+    pub fn write_sql(&self, dir: impl AsRef<Path>) -> io::Result<Vec<PathBuf>> {
+        let stem = format!("{}_{}", self.id, self.name);
+        let up_path = dir.as_ref().join(format!("{}.up.sql", stem));
+        let down_path = dir.as_ref().join(format!("{}.down.sql", stem));
+
+        let mut up = format!("-- description: {}\n", self.description);
+        if !self.dependencies.is_empty() {
+            let dependencies: Vec<_> = self.dependencies.iter().map(Uuid::to_string).collect();
+            writeln!(up, "-- depends_on: {}", dependencies.join(", ")).unwrap();
+        }
+
+        let create = |path: &Path, contents: &str| {
+            OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(path)?
+                .write_all(contents.as_bytes())
+        };
+        create(&up_path, &up)?;
+        if let Err(e) = create(&down_path, "") {
+            // The original error is more useful than one from removing the
+            // file.
+            let _ = fs::remove_file(&up_path);
+            return Err(e);
+        }
+        Ok(vec![up_path, down_path])
+    }
+}
+
+/// Convert a description to a `snake_case` name, dropping punctuation.
+fn snake_case(description: &str) -> String {
+    description
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_ascii_lowercase)
+        .collect::<Vec<_>>()
+        .join("_")
+}
+
+/// Convert a `snake_case` name to a `CamelCase` type name.
+fn camel_case(name: &str) -> String {
+    let mut type_name: String = name
+        .split('_')
+        .flat_map(|word| {
+            let mut chars = word.chars();
+            chars
+                .next()
+                .map(|c| c.to_ascii_uppercase())
+                .into_iter()
+                .chain(chars)
+        })
+        .collect();
+    if !type_name.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        type_name.insert(0, 'M');
+    }
+    type_name
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process;
+
+    use schemerz_rusqlite::RusqliteAdapter;
+
+    use super::*;
+
+    fn scaffold(description: &str) -> Scaffold {
+        Scaffold {
+            id: Uuid::new_v4(),
+            name: snake_case(description),
+            description: description.to_owned(),
+            dependencies: vec![],
+        }
+    }
+
+    #[test]
+    fn test_names() {
+        assert_eq!(snake_case("Add users' email"), "add_users_email");
+        assert_eq!(camel_case("add_users_email"), "AddUsersEmail");
+
+        // Type names cannot start with a digit.
+        assert_eq!(snake_case("2FA secrets"), "2fa_secrets");
+        assert_eq!(camel_case("2fa_secrets"), "M2faSecrets");
+
+        // Only ASCII letters and digits are kept.
+        assert_eq!(snake_case("Créer les élèves"), "cr_er_les_l_ves");
+        assert_eq!(camel_case("cr_er_les_l_ves"), "CrErLesLVes");
+        assert_eq!(snake_case("ユーザーを追加"), "");
+        assert_eq!(snake_case("-- !!! --"), "");
+    }
+
+    #[test]
+    fn test_empty_name() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        let mut migrator: Migrator<_, RusqliteAdapter<rusqlite::Error>> =
+            Migrator::new(RusqliteAdapter::new(&mut conn, None));
+
+        for description in ["ユーザーを追加", "-- !!! --", ""] {
+            assert!(matches!(
+                Scaffold::new(&mut migrator, description),
+                Err(ScaffoldError::Name(NameError(d))) if d == description
+            ));
+        }
+        assert_eq!(
+            Scaffold::new(&mut migrator, "2FA secrets").unwrap().name(),
+            "2fa_secrets"
+        );
+    }
+
+    #[test]
+    fn test_write_sql_cleans_up() {
+        let dir = std::env::temp_dir().join(format!("schemerz-scaffold-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir).unwrap();
+
+        // If the `.down.sql` file cannot be created, the `.up.sql` file is
+        // removed again.
+        let scaffold = scaffold("Create users");
+        let stem = format!("{}_create_users", scaffold.id());
+        fs::write(dir.join(format!("{}.down.sql", stem)), "DROP TABLE users;").unwrap();
+        let error = scaffold.write_sql(&dir).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::AlreadyExists);
+        assert!(!dir.join(format!("{}.up.sql", stem)).exists());
+        assert_eq!(
+            fs::read_to_string(dir.join(format!("{}.down.sql", stem))).unwrap(),
+            "DROP TABLE users;"
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,5 @@
+fn main() {
+    // Rebuild the tests that embed this directory when a migration is added to
+    // it, as `embed_migrations!` documents.
+    println!("cargo:rerun-if-changed=tests/migrations");
+}
@@ -0,0 +1,470 @@
+//! Procedural macros for the schemerz schema migration library.
+#![warn(clippy::all)]
+#![forbid(unsafe_code)]
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::punctuated::Punctuated;
+use syn::{bracketed, parse_macro_input, DeriveInput, LitStr, Token};
+use uuid::Uuid;
+
+use schemerz::{sql, Migration, MigrationGraph};
+
+/// Derive `schemerz::Migration<uuid::Uuid>` from a `#[migration(...)]`
+/// attribute.
+///
+/// The attribute takes the following arguments, of which only `id` and
+/// `description` are required:
+///
+/// - `id = "..."`: the UUID of the migration.
+/// - `depends_on = ["...", ...]`: the UUIDs of the migrations it depends on.
+/// - `description = "..."`: its description.
+/// - `replaces = ["...", ...]`: the UUIDs of the migrations it replaces, if
+///   it is a baseline.
+/// - `irreversible`: marks it as irreversible.
+/// - `fingerprint = "..."`: a version of its content, recorded when it is
+///   applied.
+/// - `repeatable`: marks it as repeatable, so that it is applied again whenever
+///   its fingerprint changes. Repeatable migrations require a `fingerprint`.
+///
+/// UUIDs are checked when the migration is compiled. Unlike
+/// `schemerz::migration!`, the migration may be any type, including generic
+/// types and types with fields.
+///
+/// ```rust
+/// # extern crate schemerz;
+/// # extern crate schemerz_macros;
+/// # extern crate uuid;
+/// use schemerz::Migration;
+/// use schemerz_macros::Migration;
+/// use uuid::uuid;
+///
+/// #[derive(Migration)]
+/// #[migration(
+///     id = "bc960dc8-0e4a-4182-a62a-8e776d1e2b30",
+///     description = "Parent migration in a DAG"
+/// )]
+/// struct ParentMigration;
+///
+/// #[derive(Migration)]
+/// #[migration(
+///     id = "4885e8ab-dafa-4d76-a565-2dee8b04ef60",
+///     depends_on = ["bc960dc8-0e4a-4182-a62a-8e776d1e2b30"],
+///     description = "Child migration, with parameters chosen at runtime",
+///     irreversible
+/// )]
+/// struct ChildMigration<P> {
+///     params: P,
+/// }
+///
+/// #[derive(Migration)]
+/// #[migration(
+///     id = "0b0a4ca6-4a5b-4b8e-9fd4-29b0bd4d48ee",
+///     depends_on = ["bc960dc8-0e4a-4182-a62a-8e776d1e2b30"],
+///     description = "View kept in sync with the code",
+///     fingerprint = "3",
+///     repeatable
+/// )]
+/// struct ViewMigration;
+///
+/// # fn main() {
+/// let child = ChildMigration { params: 42 };
+/// assert_eq!(child.id(), uuid!("4885e8ab-dafa-4d76-a565-2dee8b04ef60"));
+/// assert!(child.dependencies().contains(&ParentMigration.id()));
+/// assert!(!child.is_reversible());
+/// assert!(ViewMigration.is_repeatable());
+/// # }
+/// ```
+#[proc_macro_derive(Migration, attributes(migration))]
+pub fn derive_migration(input: TokenStream) -> TokenStream {
+    derive(parse_macro_input!(input as DeriveInput))
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn derive(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let mut id = None;
+    let mut dependencies = vec![];
+    let mut description = None;
+    let mut replaces = vec![];
+    let mut irreversible = false;
+    let mut fingerprint = None;
+    let mut repeatable = false;
+    for attr in input
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("migration"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("id") {
+                id = Some(parse_uuid(&meta.value()?.parse()?)?);
+            } else if meta.path.is_ident("depends_on") {
+                dependencies.extend(parse_uuids(meta.value()?)?);
+            } else if meta.path.is_ident("description") {
+                description = Some(meta.value()?.parse::<LitStr>()?);
+            } else if meta.path.is_ident("replaces") {
+                replaces.extend(parse_uuids(meta.value()?)?);
+            } else if meta.path.is_ident("irreversible") {
+                irreversible = true;
+            } else if meta.path.is_ident("fingerprint") {
+                fingerprint = Some(meta.value()?.parse::<LitStr>()?);
+            } else if meta.path.is_ident("repeatable") {
+                repeatable = true;
+            } else {
+                return Err(meta.error("unknown migration attribute"));
+            }
+            Ok(())
+        })?;
+    }
+
+    let missing = |argument| {
+        syn::Error::new_spanned(
+            &input.ident,
+            format!("missing `#[migration({} = \"...\")]` attribute", argument),
+        )
+    };
+    let id = id.ok_or_else(|| missing("id"))?;
+    let description = description.ok_or_else(|| missing("description"))?;
+    if dependencies.contains(&id) {
+        return Err(syn::Error::new_spanned(
+            &input.ident,
+            format!("migration {} depends on itself", Uuid::from_u128(id)),
+        ));
+    }
+
+    if repeatable && fingerprint.is_none() {
+        return Err(syn::Error::new_spanned(
+            &input.ident,
+            "repeatable migrations require a `#[migration(fingerprint = \"...\")]` attribute",
+        ));
+    }
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let is_reversible = irreversible.then(|| {
+        quote! {
+            fn is_reversible(&self) -> bool {
+                false
+            }
+        }
+    });
+    let fingerprint = fingerprint.map(|fingerprint| {
+        quote! {
+            fn fingerprint(&self) -> ::std::option::Option<::std::vec::Vec<u8>> {
+                ::std::option::Option::Some(#fingerprint.as_bytes().to_vec())
+            }
+        }
+    });
+    let is_repeatable = repeatable.then(|| {
+        quote! {
+            fn is_repeatable(&self) -> bool {
+                true
+            }
+        }
+    });
+    let replaces = (!replaces.is_empty()).then(|| {
+        quote! {
+            fn replaces(&self) -> ::std::collections::HashSet<::uuid::Uuid> {
+                ::std::collections::HashSet::from([
+                    #(::uuid::Uuid::from_u128(#replaces),)*
+                ])
+            }
+        }
+    });
+    Ok(quote! {
+        impl #impl_generics ::schemerz::Migration<::uuid::Uuid> for #name #ty_generics #where_clause {
+            fn id(&self) -> ::uuid::Uuid {
+                ::uuid::Uuid::from_u128(#id)
+            }
+
+            fn dependencies(&self) -> ::std::collections::HashSet<::uuid::Uuid> {
+                ::std::collections::HashSet::from([
+                    #(::uuid::Uuid::from_u128(#dependencies),)*
+                ])
+            }
+
+            fn description(&self) -> &'static str {
+                #description
+            }
+
+            #fingerprint
+
+            #is_reversible
+
+            #replaces
+
+            #is_repeatable
+        }
+    })
+}
+
+/// Parse a UUID from a string literal, as a `u128` that can be written into
+/// the generated code.
+fn parse_uuid(lit: &LitStr) -> syn::Result<u128> {
+    Uuid::parse_str(&lit.value())
+        .map(|id| id.as_u128())
+        .map_err(|e| syn::Error::new(lit.span(), format!("invalid UUID: {}", e)))
+}
+
+/// Parse a bracketed list of UUIDs from string literals.
+fn parse_uuids(input: syn::parse::ParseStream<'_>) -> syn::Result<Vec<u128>> {
+    let content;
+    bracketed!(content in input);
+    Punctuated::<LitStr, Token![,]>::parse_terminated(&content)?
+        .iter()
+        .map(parse_uuid)
+        .collect()
+}
+
+/// Embed a directory of SQL migrations in the binary at compile time.
+///
+/// The directory is given relative to the root of the crate, and is laid out
+/// as described in `schemerz::sql`. The macro expands to a
+/// `Vec<schemerz::sql::SqlMigration>`, in order of file name, so the crate
+/// must depend on `schemerz` with its `sql` feature enabled.
+/// `SqlMigration` implements `schemerz_postgres::PostgresMigration` and
+/// `schemerz_rusqlite::RusqliteMigration` when their `sql` features are
+/// enabled.
+///
+/// Compilation fails if the directory cannot be loaded, if two migrations
+/// have the same ID, or if a migration depends on one that is not in the
+/// directory or on itself.
+///
+/// Changing a migration file causes the crate to be rebuilt, but adding or
+/// removing one does not, since the macro cannot tell Cargo to watch the
+/// directory. Crates that embed migrations therefore need a build script,
+/// `build.rs` next to their `Cargo.toml`, that does:
+///
+/// ```rust,no_run
+/// # #![allow(clippy::needless_doctest_main)]
+/// fn main() {
+///     println!("cargo:rerun-if-changed=tests/migrations");
+/// }
+/// ```
+///
+/// Otherwise a new migration is only picked up once the crate is next rebuilt
+/// for another reason.
+///
+/// ```rust
+/// # extern crate rusqlite;
+/// # extern crate schemerz;
+/// # extern crate schemerz_macros;
+/// # extern crate schemerz_rusqlite;
+/// use rusqlite::{Connection, Error as RusqliteError};
+/// use schemerz::Migrator;
+/// use schemerz_macros::embed_migrations;
+/// use schemerz_rusqlite::{RusqliteAdapter, RusqliteMigration};
+///
+/// # fn main() {
+/// let mut conn = Connection::open_in_memory().unwrap();
+/// let adapter = RusqliteAdapter::new(&mut conn, None);
+/// adapter.init().unwrap();
+///
+/// let mut migrator = Migrator::new(adapter);
+/// migrator
+///     .register_multiple(embed_migrations!("tests/migrations").into_iter().map(
+///         |migration| Box::new(migration) as Box<dyn RusqliteMigration<Error = RusqliteError>>,
+///     ))
+///     .unwrap();
+/// migrator.up(None).unwrap();
+/// assert_eq!(migrator.status().unwrap().applied.len(), 2);
+/// # }
+/// ```
+#[proc_macro]
+pub fn embed_migrations(input: TokenStream) -> TokenStream {
+    let dir = parse_macro_input!(input as LitStr);
+    let crate_dir = env::var("CARGO_MANIFEST_DIR").unwrap_or_default();
+    embed(&Path::new(&crate_dir).join(dir.value()))
+        .unwrap_or_else(|message| syn::Error::new(dir.span(), message).to_compile_error())
+        .into()
+}
+
+/// Load and check the migrations in `dir`, and generate the expression that
+/// constructs them.
+fn embed(dir: &Path) -> Result<TokenStream2, String> {
+    let migrations = sql::load_dir(dir).map_err(|e| e.to_string())?;
+    let graph = MigrationGraph::new(migrations).map_err(|e| e.to_string())?;
+
+    let migrations = graph.migrations().map(|migration| {
+        let id = migration.id().as_u128();
+        let name = migration.name();
+        let description = migration.description();
+        let mut dependencies: Vec<_> = migration
+            .dependencies()
+            .iter()
+            .map(|id| id.as_u128())
+            .collect();
+        dependencies.sort_unstable();
+        let transactional = migration.is_transactional();
+        let repeatable = migration.is_repeatable();
+        let up = migration.up_sql();
+        let down = match migration.down_sql() {
+            Some(down) => quote!(::std::option::Option::Some(#down)),
+            None => quote!(::std::option::Option::None),
+        };
+        quote! {
+            ::schemerz::sql::SqlMigration::embedded(
+                #id,
+                #name,
+                #description,
+                &[#(#dependencies),*],
+                #transactional,
+                #repeatable,
+                #up,
+                #down,
+            )
+        }
+    });
+
+    // Including the files makes the compiler rebuild the crate when they
+    // change.
+    let mut files = vec![];
+    for entry in fs::read_dir(dir).map_err(|e| e.to_string())? {
+        let path = entry.map_err(|e| e.to_string())?.path();
+        if let Some(path) = path.to_str() {
+            if path.ends_with(".sql") || path.ends_with(".toml") {
+                files.push(path.to_owned());
+            }
+        }
+    }
+    files.sort();
+
+    Ok(quote! {
+        {
+            #(const _: &[u8] = include_bytes!(#files);)*
+            ::std::vec![#(#migrations),*]
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::process;
+
+    use super::*;
+
+    /// Create an empty directory containing the given files.
+    fn migrations_dir(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = env::temp_dir().join(format!("schemerz-macros-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir).unwrap();
+        for (file_name, contents) in files {
+            fs::write(dir.join(file_name), contents).unwrap();
+        }
+        dir
+    }
+
+    fn embed_files(name: &str, files: &[(&str, &str)]) -> Result<String, String> {
+        let dir = migrations_dir(name, files);
+        let result = embed(&dir).map(|tokens| tokens.to_string());
+        fs::remove_dir_all(&dir).unwrap();
+        result
+    }
+
+    #[test]
+    fn test_derive_errors() {
+        let error = |input: DeriveInput| derive(input).unwrap_err().to_string();
+
+        assert_eq!(
+            error(syn::parse_quote! {
+                #[migration(description = "No ID")]
+                struct M;
+            }),
+            "missing `#[migration(id = \"...\")]` attribute"
+        );
+        assert!(error(syn::parse_quote! {
+            #[migration(id = "not-a-uuid", description = "Bad ID")]
+            struct M;
+        })
+        .starts_with("invalid UUID"));
+        assert!(error(syn::parse_quote! {
+            #[migration(
+                id = "4885e8ab-dafa-4d76-a565-2dee8b04ef60",
+                depends_on = ["4885e8ab-dafa-4d76-a565-2dee8b04ef60"],
+                description = "Cyclic",
+            )]
+            struct M;
+        })
+        .ends_with("depends on itself"));
+        assert_eq!(
+            error(syn::parse_quote! {
+                #[migration(id = "4885e8ab-dafa-4d76-a565-2dee8b04ef60", descripton = "Typo")]
+                struct M;
+            }),
+            "unknown migration attribute"
+        );
+        assert!(error(syn::parse_quote! {
+            #[migration(
+                id = "4885e8ab-dafa-4d76-a565-2dee8b04ef60",
+                description = "Repeatable",
+                repeatable,
+            )]
+            struct M;
+        })
+        .starts_with("repeatable migrations require"));
+    }
+
+    #[test]
+    fn test_embed() {
+        let tokens = embed_files(
+            "embed",
+            &[
+                (
+                    "4885e8ab-dafa-4d76-a565-2dee8b04ef60_first.up.sql",
+                    "CREATE TABLE first (id integer);",
+                ),
+                (
+                    "4885e8ab-dafa-4d76-a565-2dee8b04ef60_first.down.sql",
+                    "DROP TABLE first;",
+                ),
+                (
+                    "0b0a4ca6-4a5b-4b8e-9fd4-29b0bd4d48ee_second.up.sql",
+                    "-- depends_on: 4885e8ab-dafa-4d76-a565-2dee8b04ef60\n",
+                ),
+            ],
+        )
+        .unwrap();
+        assert_eq!(tokens.matches("SqlMigration :: embedded").count(), 2);
+        assert_eq!(tokens.matches("include_bytes !").count(), 3);
+        assert!(tokens.contains("\"DROP TABLE first;\""));
+    }
+
+    #[test]
+    fn test_embed_checks() {
+        let error = embed_files(
+            "duplicate",
+            &[
+                ("4885e8ab-dafa-4d76-a565-2dee8b04ef60_first.up.sql", ""),
+                ("4885e8ab-dafa-4d76-a565-2dee8b04ef60_second.up.sql", ""),
+            ],
+        )
+        .unwrap_err();
+        assert!(
+            error.contains("Duplicate migration ID 4885e8ab-dafa-4d76-a565-2dee8b04ef60"),
+            "{}",
+            error
+        );
+
+        let error = embed_files(
+            "unknown",
+            &[(
+                "4885e8ab-dafa-4d76-a565-2dee8b04ef60_first.up.sql",
+                "-- depends_on: 0b0a4ca6-4a5b-4b8e-9fd4-29b0bd4d48ee\n",
+            )],
+        )
+        .unwrap_err();
+        assert!(
+            error.contains("Unknown migration ID 0b0a4ca6-4a5b-4b8e-9fd4-29b0bd4d48ee"),
+            "{}",
+            error
+        );
+
+        assert!(embed_files("missing-up", &[("not-a-migration.down.sql", "")]).is_err());
+    }
+}
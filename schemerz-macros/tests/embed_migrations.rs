@@ -0,0 +1,24 @@
+//! Checks that `embed_migrations!` embeds every migration in the directory,
+//! which relies on the build script rebuilding the tests when one is added.
+
+use std::fs;
+
+use schemerz::Migration;
+use schemerz_macros::embed_migrations;
+
+#[test]
+fn test_embeds_every_migration() {
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/migrations");
+    let mut files: Vec<_> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .filter(|file_name| file_name.ends_with(".up.sql"))
+        .collect();
+    files.sort();
+
+    let embedded: Vec<_> = embed_migrations!("tests/migrations")
+        .iter()
+        .map(|migration| format!("{}_{}.up.sql", migration.id(), migration.name()))
+        .collect();
+    assert_eq!(embedded, files);
+}
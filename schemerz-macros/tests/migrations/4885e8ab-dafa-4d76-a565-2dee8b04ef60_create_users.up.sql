@@ -0,0 +1,2 @@
+-- description: Create the users table.
+CREATE TABLE users (id integer PRIMARY KEY, email text NOT NULL);
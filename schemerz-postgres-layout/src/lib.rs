@@ -0,0 +1,245 @@
+//! The layout of the tables in which the schemerz PostgreSQL adapters,
+//! `schemerz-postgres` and `schemerz-tokio-postgres`, store migration metadata.
+//!
+//! This only produces SQL, which each adapter runs with its own client, so
+//! that the two adapters create, upgrade, record migrations in and lock the
+//! same tables with the same statements, and can be used on the same database.
+#![warn(clippy::all)]
+#![forbid(unsafe_code)]
+
+use schemerz::{MigrationDirection, TableName};
+
+/// The version of the layout of the metadata tables that the adapters' `init`
+/// creates, or upgrades older tables to. Tables created before layouts were
+/// versioned are version 0, and only have an `id` column.
+pub const LAYOUT_VERSION: i32 = 1;
+
+/// SQL expression deriving the key of the advisory lock held while migrating
+/// from the metadata table name, given as the first parameter.
+const LOCK_KEY: &str = "('x' || left(md5($1::text), 16))::bit(64)::bigint";
+
+/// Returns how a migration direction is stored in the history and dirty
+/// tables.
+pub fn direction_to_sql(direction: MigrationDirection) -> &'static str {
+    match direction {
+        MigrationDirection::Up => "up",
+        MigrationDirection::Down => "down",
+    }
+}
+
+/// Parses a migration direction stored in the history or dirty table.
+pub fn direction_from_sql(direction: &str) -> Result<MigrationDirection, String> {
+    match direction {
+        "up" => Ok(MigrationDirection::Up),
+        "down" => Ok(MigrationDirection::Down),
+        direction => Err(format!("Unknown migration direction {}", direction)),
+    }
+}
+
+/// The tables in which the adapters store migration metadata.
+#[derive(Clone, Debug)]
+pub struct Tables {
+    /// The migrations currently applied.
+    pub metadata: TableName,
+    /// Every migration applied or reverted, in order.
+    pub history: TableName,
+    /// Non-transactional migrations that started to be applied or reverted,
+    /// but did not finish. Only `schemerz-postgres` runs such migrations, but
+    /// both adapters clear their markers.
+    pub dirty: TableName,
+    /// The version of the layout of these tables.
+    pub layout: TableName,
+}
+
+impl Tables {
+    /// The tables named after the metadata table `metadata`, by appending
+    /// `_history`, `_dirty` and `_layout` to it.
+    pub fn new(metadata: TableName) -> Self {
+        Tables {
+            history: metadata.with_suffix("_history"),
+            dirty: metadata.with_suffix("_dirty"),
+            layout: metadata.with_suffix("_layout"),
+            metadata,
+        }
+    }
+
+    /// Returns the SQL that creates the metadata and layout tables if they do
+    /// not exist, and locks the layout table until the end of the transaction,
+    /// so that concurrent upgrades run one after the other.
+    pub fn create(&self) -> String {
+        format!(
+            r#"
+                CREATE TABLE IF NOT EXISTS {0} (id uuid PRIMARY KEY) WITH (OIDS=FALSE);
+                CREATE TABLE IF NOT EXISTS {1} (version integer NOT NULL);
+                LOCK TABLE {1} IN EXCLUSIVE MODE;
+            "#,
+            self.metadata, self.layout
+        )
+    }
+
+    /// Returns the query for the layout version of the tables, as a single
+    /// `integer`.
+    pub fn select_version(&self) -> String {
+        format!("SELECT COALESCE(MAX(version), 0) FROM {};", self.layout)
+    }
+
+    /// Returns the SQL that upgrades the tables from layout `version` to
+    /// [`LAYOUT_VERSION`] and records it, or `None` if they are already laid
+    /// out by it.
+    ///
+    /// Tables laid out by a later version cannot be used, so for those the
+    /// SQL fails with `feature_not_supported`.
+    pub fn upgrade(&self, version: i32) -> Option<String> {
+        if version > LAYOUT_VERSION {
+            return Some(format!(
+                "DO $$ BEGIN RAISE EXCEPTION USING
+                    ERRCODE = 'feature_not_supported',
+                    MESSAGE = 'Metadata table layout version {} is newer than the latest known, {}';
+                END $$;",
+                version, LAYOUT_VERSION
+            ));
+        }
+        if version == LAYOUT_VERSION {
+            return None;
+        }
+
+        let mut sql: String = (version..LAYOUT_VERSION)
+            .map(|from| self.upgrade_from(from))
+            .collect();
+        sql.push_str(&format!(
+            "DELETE FROM {0}; INSERT INTO {0} (version) VALUES ({1});",
+            self.layout, LAYOUT_VERSION
+        ));
+        Some(sql)
+    }
+
+    /// Returns the query for the IDs of the applied migrations.
+    pub fn select_applied(&self) -> String {
+        format!("SELECT id FROM {};", self.metadata)
+    }
+
+    /// Returns the query for the IDs and fingerprints of the applied
+    /// migrations that were recorded with a fingerprint.
+    pub fn select_fingerprints(&self) -> String {
+        format!(
+            "SELECT id, fingerprint FROM {} WHERE fingerprint IS NOT NULL;",
+            self.metadata
+        )
+    }
+
+    /// Returns the query for the history, oldest first, as the ID,
+    /// description, direction, `executed_at` and `duration_ms` of each entry.
+    pub fn select_history(&self) -> String {
+        format!(
+            "SELECT id, description, direction, executed_at, duration_ms FROM {} ORDER BY seq;",
+            self.history
+        )
+    }
+
+    /// Returns the query for the IDs and directions of the dirty migrations.
+    pub fn select_dirty(&self) -> String {
+        format!("SELECT id, direction FROM {};", self.dirty)
+    }
+
+    /// Returns the statement recording a migration as applied, replacing any
+    /// earlier record of it, given its ID, fingerprint, description,
+    /// `applied_at` and `duration_ms` as parameters.
+    pub fn insert_applied(&self) -> String {
+        format!(
+            "INSERT INTO {} (id, fingerprint, description, applied_at, duration_ms) VALUES ($1::uuid, $2, $3, $4, $5)
+                ON CONFLICT (id) DO UPDATE SET fingerprint = $2, description = $3, applied_at = $4, duration_ms = $5;",
+            self.metadata
+        )
+    }
+
+    /// Returns the statement recording a migration as not applied, given its
+    /// ID as the parameter.
+    pub fn delete_applied(&self) -> String {
+        format!("DELETE FROM {} WHERE id = $1::uuid;", self.metadata)
+    }
+
+    /// Returns the statement recording a migration having been applied or
+    /// reverted in the history, given its ID, direction (see
+    /// [`direction_to_sql`]), description, `executed_at` and `duration_ms` as
+    /// parameters.
+    pub fn insert_history(&self) -> String {
+        format!(
+            "INSERT INTO {} (id, direction, description, executed_at, duration_ms) VALUES ($1::uuid, $2, $3, $4, $5);",
+            self.history
+        )
+    }
+
+    /// Returns the statement marking a non-transactional migration as having
+    /// started to be applied or reverted, given its ID, direction and
+    /// `started_at` as parameters.
+    pub fn insert_dirty(&self) -> String {
+        format!(
+            "INSERT INTO {} (id, direction, started_at) VALUES ($1::uuid, $2, $3)
+                ON CONFLICT (id) DO UPDATE SET direction = $2, started_at = $3;",
+            self.dirty
+        )
+    }
+
+    /// Returns the statement removing the dirty marker of a migration, if it
+    /// has one, given its ID as the parameter.
+    ///
+    /// Both adapters run this whenever they record a migration as applied or
+    /// reverted, so that a marker left by an earlier attempt is cleared.
+    pub fn delete_dirty(&self) -> String {
+        format!("DELETE FROM {} WHERE id = $1::uuid;", self.dirty)
+    }
+
+    /// Returns the query acquiring the advisory lock held while migrating,
+    /// given the metadata table name as the parameter.
+    ///
+    /// If `transactional`, the lock is released when the transaction ends;
+    /// otherwise it is held until [`Tables::unlock`] is run.
+    pub fn lock(&self, transactional: bool) -> String {
+        let lock = if transactional {
+            "pg_advisory_xact_lock"
+        } else {
+            "pg_advisory_lock"
+        };
+        format!("SELECT {}({});", lock, LOCK_KEY)
+    }
+
+    /// Returns the query releasing the advisory lock acquired by
+    /// [`Tables::lock`], given the metadata table name as the parameter.
+    pub fn unlock(&self) -> String {
+        format!("SELECT pg_advisory_unlock({});", LOCK_KEY)
+    }
+
+    /// Returns the SQL that upgrades the tables from layout `version` to the
+    /// next one.
+    fn upgrade_from(&self, version: i32) -> String {
+        match version {
+            // Record the fingerprint, description and timing of each applied
+            // migration, keep the history of applied and reverted ones, and
+            // mark non-transactional ones that did not finish.
+            0 => format!(
+                r#"
+                    ALTER TABLE {}
+                        ADD COLUMN fingerprint bytea,
+                        ADD COLUMN description text,
+                        ADD COLUMN applied_at timestamptz,
+                        ADD COLUMN duration_ms bigint;
+                    CREATE TABLE {} (
+                        seq bigserial PRIMARY KEY,
+                        id uuid NOT NULL,
+                        direction text NOT NULL,
+                        description text,
+                        executed_at timestamptz NOT NULL,
+                        duration_ms bigint NOT NULL
+                    );
+                    CREATE TABLE {} (
+                        id uuid PRIMARY KEY,
+                        direction text NOT NULL,
+                        started_at timestamptz NOT NULL
+                    );
+                "#,
+                self.metadata, self.history, self.dirty
+            ),
+            _ => unreachable!("no upgrade from metadata table layout version {}", version),
+        }
+    }
+}
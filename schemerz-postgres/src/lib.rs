@@ -5,24 +5,24 @@
 //!
 //! ```rust
 //! extern crate postgres;
-//! #[macro_use]
 //! extern crate schemerz;
+//! extern crate schemerz_macros;
 //! extern crate schemerz_postgres;
 //! extern crate uuid;
 //!
-//! use std::collections::HashSet;
+//! use std::collections::{HashMap, HashSet};
 //!
 //! use postgres::{Client, NoTls, Transaction};
 //! use schemerz::{Migration, Migrator};
+//! use schemerz_macros::Migration;
 //! use schemerz_postgres::{PostgresAdapter, PostgresAdapterError, PostgresMigration};
-//! use uuid::uuid;
 //!
+//! #[derive(Migration)]
+//! #[migration(
+//!     id = "4885e8ab-dafa-4d76-a565-2dee8b04ef60",
+//!     description = "An example migration without dependencies."
+//! )]
 //! struct MyExampleMigration;
-//! migration!(
-//!     MyExampleMigration,
-//!     uuid!("4885e8ab-dafa-4d76-a565-2dee8b04ef60"),
-//!     [],
-//!     "An example migration without dependencies.");
 //!
 //! impl PostgresMigration for MyExampleMigration {
 //!     fn up(&self, transaction: &mut Transaction) -> Result<(), PostgresAdapterError> {
@@ -53,12 +53,20 @@
 #![warn(clippy::all)]
 #![forbid(unsafe_code)]
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+#[cfg(feature = "sql")]
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime};
 
-use postgres::{Client, Error as PostgresError, Transaction};
+use postgres::types::{FromSql, ToSql, Type};
+use postgres::{Client, Error as PostgresError, Row, Transaction};
 use uuid::Uuid;
 
-use schemerz::{Adapter, Migration};
+#[cfg(feature = "sql")]
+use schemerz::sql;
+use schemerz::{Adapter, HistoryEntry, Migration, MigrationDirection, TableName};
+use schemerz_postgres_layout::{direction_from_sql, direction_to_sql, Tables};
 
 /// PostgreSQL-specific trait for schema migrations.
 pub trait PostgresMigration: Migration<Uuid> {
@@ -68,25 +76,297 @@ pub trait PostgresMigration: Migration<Uuid> {
     }
 
     /// Revert a migration to the database using a transaction.
+    ///
+    /// The default implementation does nothing. Migrations that cannot be
+    /// reverted should instead return `false` from
+    /// [`Migration::is_reversible`], so that `Migrator` refuses to revert them.
     fn down(&self, _transaction: &mut Transaction<'_>) -> Result<(), PostgresError> {
         Ok(())
     }
+
+    /// Whether this migration runs inside a transaction.
+    ///
+    /// Statements such as `CREATE INDEX CONCURRENTLY`, `ALTER TYPE ... ADD
+    /// VALUE` and `VACUUM` cannot run inside a transaction. Migrations that
+    /// use them should return `false`, and implement
+    /// [`PostgresMigration::up_without_transaction`] and
+    /// [`PostgresMigration::down_without_transaction`] instead of
+    /// [`PostgresMigration::up`] and [`PostgresMigration::down`].
+    ///
+    /// The default implementation returns `true`.
+    fn is_transactional(&self) -> bool {
+        true
+    }
+
+    /// Apply a non-transactional migration to the database using the client
+    /// directly.
+    ///
+    /// The migration is only recorded as applied once this returns, so if it
+    /// fails midway, whatever it has already done remains in place and the
+    /// adapter records it as dirty (see [`PostgresAdapter::dirty_migrations`]).
+    /// Such migrations should therefore be safe to run again, for example by
+    /// using `IF NOT EXISTS`.
+    fn up_without_transaction(&self, _client: &mut Client) -> Result<(), PostgresError> {
+        Ok(())
+    }
+
+    /// Revert a non-transactional migration to the database using the client
+    /// directly. This is handled as for
+    /// [`PostgresMigration::up_without_transaction`].
+    fn down_without_transaction(&self, _client: &mut Client) -> Result<(), PostgresError> {
+        Ok(())
+    }
 }
 
 pub type PostgresAdapterError = PostgresError;
 
+#[cfg(feature = "sql")]
+impl PostgresMigration for sql::SqlMigration {
+    fn up(&self, transaction: &mut Transaction<'_>) -> Result<(), PostgresError> {
+        transaction.batch_execute(self.up_sql())
+    }
+
+    fn down(&self, transaction: &mut Transaction<'_>) -> Result<(), PostgresError> {
+        match self.down_sql() {
+            Some(sql) => transaction.batch_execute(sql),
+            None => Ok(()),
+        }
+    }
+
+    fn is_transactional(&self) -> bool {
+        sql::SqlMigration::is_transactional(self)
+    }
+
+    fn up_without_transaction(&self, client: &mut Client) -> Result<(), PostgresError> {
+        client.batch_execute(self.up_sql())
+    }
+
+    fn down_without_transaction(&self, client: &mut Client) -> Result<(), PostgresError> {
+        match self.down_sql() {
+            Some(sql) => client.batch_execute(sql),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Load the migrations in a directory of SQL files, as described in
+/// [`schemerz::sql`], in order of file name.
+///
+/// Each file is sent to the server as a single query. PostgreSQL runs a query
+/// containing several statements in an implicit transaction, so files of
+/// migrations declared non-transactional should contain only one statement.
+#[cfg(feature = "sql")]
+pub fn load_sql_migrations(
+    dir: impl AsRef<Path>,
+) -> Result<Vec<Box<dyn PostgresMigration>>, sql::LoadError> {
+    Ok(sql::load_dir(dir)?
+        .into_iter()
+        .map(|migration| Box::new(migration) as Box<dyn PostgresMigration>)
+        .collect())
+}
+
+struct WrappedDirection(MigrationDirection);
+
+impl<'a> FromSql<'a> for WrappedDirection {
+    fn from_sql(ty: &Type, raw: &'a [u8]) -> Result<Self, Box<dyn Error + Sync + Send>> {
+        Ok(WrappedDirection(direction_from_sql(
+            <&str as FromSql>::from_sql(ty, raw)?,
+        )?))
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        <&str as FromSql>::accepts(ty)
+    }
+}
+
+/// Record a migration having been applied or reverted in the history table.
+fn record_history(
+    tables: &Tables,
+    transaction: &mut Transaction<'_>,
+    migration: &dyn Migration<Uuid>,
+    direction: MigrationDirection,
+    executed_at: SystemTime,
+    duration: Duration,
+) -> Result<(), PostgresError> {
+    transaction.execute(
+        tables.insert_history().as_str(),
+        &[
+            &migration.id(),
+            &direction_to_sql(direction),
+            &migration.description(),
+            &executed_at,
+            &(duration.as_millis() as i64),
+        ],
+    )?;
+    Ok(())
+}
+
+/// Record a non-transactional migration as having started to be applied or
+/// reverted in the dirty table.
+fn record_dirty(
+    tables: &Tables,
+    client: &mut Client,
+    migration: &dyn Migration<Uuid>,
+    direction: MigrationDirection,
+    started_at: SystemTime,
+) -> Result<(), PostgresError> {
+    client.execute(
+        tables.insert_dirty().as_str(),
+        &[&migration.id(), &direction_to_sql(direction), &started_at],
+    )?;
+    Ok(())
+}
+
+/// Record a migration as applied in the metadata table, replacing any earlier
+/// record of it, and in the history table, clearing any dirty marker left by an
+/// earlier attempt.
+fn record_applied(
+    tables: &Tables,
+    transaction: &mut Transaction<'_>,
+    migration: &dyn Migration<Uuid>,
+    applied_at: SystemTime,
+    duration: Duration,
+) -> Result<(), PostgresError> {
+    transaction.execute(
+        tables.insert_applied().as_str(),
+        &[
+            &migration.id(),
+            &migration.fingerprint(),
+            &migration.description(),
+            &applied_at,
+            &(duration.as_millis() as i64),
+        ],
+    )?;
+    clear_dirty(tables, transaction, migration.id())?;
+    record_history(
+        tables,
+        transaction,
+        migration,
+        MigrationDirection::Up,
+        applied_at,
+        duration,
+    )
+}
+
+/// Remove a migration from the metadata table, and record it as reverted in
+/// the history table, clearing any dirty marker left by an earlier attempt.
+fn record_reverted(
+    tables: &Tables,
+    transaction: &mut Transaction<'_>,
+    migration: &dyn Migration<Uuid>,
+    reverted_at: SystemTime,
+    duration: Duration,
+) -> Result<(), PostgresError> {
+    transaction.execute(tables.delete_applied().as_str(), &[&migration.id()])?;
+    clear_dirty(tables, transaction, migration.id())?;
+    record_history(
+        tables,
+        transaction,
+        migration,
+        MigrationDirection::Down,
+        reverted_at,
+        duration,
+    )
+}
+
+/// Remove the dirty marker of a migration, if it has one.
+fn clear_dirty(
+    tables: &Tables,
+    transaction: &mut Transaction<'_>,
+    id: Uuid,
+) -> Result<(), PostgresError> {
+    transaction.execute(tables.delete_dirty().as_str(), &[&id])?;
+    Ok(())
+}
+
+/// Fail with the error PostgreSQL itself raises when a statement that cannot
+/// run inside a transaction is run inside one.
+fn reject_in_transaction(
+    transaction: &mut Transaction<'_>,
+    migration: &dyn Migration<Uuid>,
+) -> Result<(), PostgresError> {
+    transaction.batch_execute(&format!(
+        "DO $$ BEGIN RAISE EXCEPTION USING
+            ERRCODE = 'active_sql_transaction',
+            MESSAGE = 'Non-transactional migration {} cannot run inside a transaction';
+        END $$;",
+        migration.id()
+    ))
+}
+
+/// Create the metadata tables, or upgrade them from the layout they were
+/// created with to [`schemerz_postgres_layout::LAYOUT_VERSION`].
+fn upgrade_layout(tables: &Tables, transaction: &mut Transaction<'_>) -> Result<(), PostgresError> {
+    transaction.batch_execute(&tables.create())?;
+    let version: i32 = transaction
+        .query_one(tables.select_version().as_str(), &[])?
+        .get(0);
+    match tables.upgrade(version) {
+        Some(upgrade) => transaction.batch_execute(&upgrade),
+        None => Ok(()),
+    }
+}
+
+/// The connection used by a `PostgresAdapter`.
+enum Conn<'a> {
+    /// Each migration runs in its own transaction.
+    Client(&'a mut Client),
+    /// Every migration runs in a savepoint of this transaction, which is
+    /// committed by `PostgresAdapter::commit`.
+    Transaction(Transaction<'a>),
+}
+
+impl Conn<'_> {
+    fn execute(
+        &mut self,
+        query: &str,
+        params: &[&(dyn ToSql + Sync)],
+    ) -> Result<u64, PostgresError> {
+        match self {
+            Conn::Client(client) => client.execute(query, params),
+            Conn::Transaction(transaction) => transaction.execute(query, params),
+        }
+    }
+
+    fn query(
+        &mut self,
+        query: &str,
+        params: &[&(dyn ToSql + Sync)],
+    ) -> Result<Vec<Row>, PostgresError> {
+        match self {
+            Conn::Client(client) => client.query(query, params),
+            Conn::Transaction(transaction) => transaction.query(query, params),
+        }
+    }
+
+    /// Begin a transaction, or a savepoint if every migration runs in a single
+    /// transaction.
+    fn transaction(&mut self) -> Result<Transaction<'_>, PostgresError> {
+        match self {
+            Conn::Client(client) => client.transaction(),
+            Conn::Transaction(transaction) => transaction.transaction(),
+        }
+    }
+}
+
 /// Adapter between schemerz and PostgreSQL.
 pub struct PostgresAdapter<'a> {
-    conn: &'a mut Client,
-    migration_metadata_table: String,
+    conn: Conn<'a>,
+    tables: Tables,
+    lock_timeout: Option<Duration>,
 }
 
 impl<'a> PostgresAdapter<'a> {
     /// Construct a PostgreSQL schemerz adapter.
     ///
     /// `table_name` specifies the name of the table that schemerz will use
-    /// for storing metadata about applied migrations. If `None`, a default
-    /// will be used.
+    /// for storing metadata about applied migrations, optionally qualified by
+    /// its schema. If `None`, a default will be used. The history of applied
+    /// and reverted migrations is stored in a second table in the same schema,
+    /// named by appending `_history` to `table_name`, and non-transactional
+    /// migrations that did not finish are recorded in a third, named by
+    /// appending `_dirty`. The version of the tables' layout is stored in a
+    /// fourth, named by appending `_layout`.
     ///
     /// ```rust
     /// # extern crate postgres;
@@ -99,31 +379,168 @@ impl<'a> PostgresAdapter<'a> {
     /// let adapter = schemerz_postgres::PostgresAdapter::new(&mut conn, None);
     /// # }
     /// ```
-    pub fn new(conn: &'a mut Client, table_name: Option<String>) -> PostgresAdapter<'a> {
+    pub fn new(conn: &'a mut Client, table_name: Option<TableName>) -> PostgresAdapter<'a> {
+        Self::with_conn(Conn::Client(conn), table_name)
+    }
+
+    /// Construct a PostgreSQL schemerz adapter that applies and reverts every
+    /// migration inside a single transaction.
+    ///
+    /// Nothing is persisted until [`PostgresAdapter::commit`] is called, so a
+    /// failure partway through a `Migrator::up` or `Migrator::down` leaves the
+    /// database untouched once the adapter is dropped. Each migration runs in
+    /// a savepoint of the outer transaction. `table_name` is handled as for
+    /// [`PostgresAdapter::new`].
+    ///
+    /// Migrations for which [`PostgresMigration::is_transactional`] returns
+    /// `false` fail with an `active_sql_transaction` error.
+    ///
+    /// ```rust
+    /// # extern crate postgres;
+    /// # extern crate schemerz;
+    /// # extern crate schemerz_postgres;
+    /// # use schemerz::Migrator;
+    /// # use schemerz_postgres::PostgresAdapter;
+    /// #
+    /// # fn main() {
+    /// let mut conn = postgres::Client::connect(
+    ///     "postgresql://postgres@localhost",
+    ///     postgres::NoTls).unwrap();
+    /// conn.execute("SET search_path = pg_temp", &[]).unwrap();
+    /// let mut adapter = PostgresAdapter::new_transactional(&mut conn, None).unwrap();
+    /// adapter.init().unwrap();
+    ///
+    /// let mut migrator = Migrator::new(adapter);
+    /// // Register migrations here.
+    /// migrator.up(None).unwrap();
+    /// migrator.into_adapter().commit().unwrap();
+    /// # }
+    /// ```
+    pub fn new_transactional(
+        conn: &'a mut Client,
+        table_name: Option<TableName>,
+    ) -> Result<PostgresAdapter<'a>, PostgresError> {
+        Ok(Self::with_conn(
+            Conn::Transaction(conn.transaction()?),
+            table_name,
+        ))
+    }
+
+    fn with_conn(conn: Conn<'a>, table_name: Option<TableName>) -> PostgresAdapter<'a> {
+        let metadata = table_name.unwrap_or_default();
         PostgresAdapter {
             conn,
-            migration_metadata_table: table_name.unwrap_or_else(|| "_schemerz".into()),
+            tables: Tables::new(metadata),
+            lock_timeout: None,
+        }
+    }
+
+    /// Give up acquiring the migration lock after `timeout`, failing with a
+    /// `lock_not_available` error, instead of waiting indefinitely.
+    ///
+    /// While migrating, the adapter holds an advisory lock keyed on the
+    /// metadata table name, so that migrators sharing that table run one after
+    /// the other. Adapters constructed with [`PostgresAdapter::new`] hold it
+    /// for the session until `Migrator::up` or `Migrator::down` returns, and
+    /// those constructed with [`PostgresAdapter::new_transactional`] hold it
+    /// until their transaction ends.
+    pub fn with_lock_timeout(mut self, timeout: Duration) -> Self {
+        self.lock_timeout = Some(timeout);
+        self
+    }
+
+    /// Commit the transaction spanning every migration applied or reverted by
+    /// an adapter constructed with [`PostgresAdapter::new_transactional`].
+    ///
+    /// For adapters constructed with [`PostgresAdapter::new`], each migration
+    /// has already been committed, and this does nothing.
+    pub fn commit(self) -> Result<(), PostgresError> {
+        match self.conn {
+            Conn::Client(_) => Ok(()),
+            Conn::Transaction(transaction) => transaction.commit(),
         }
     }
 
     /// Initialize the schemerz metadata schema. This must be called before
     /// using `Migrator` with this adapter. This is safe to call multiple times.
+    ///
+    /// If the metadata table name is qualified by a schema that does not exist,
+    /// the schema is created. Metadata tables created by earlier versions of
+    /// schemerz are upgraded in place. Tables laid out by a later version,
+    /// which this one does not know how to use, are rejected.
     pub fn init(&mut self) -> Result<(), PostgresError> {
-        self.conn.execute(
-            format!(
-                r#"
-                    CREATE TABLE IF NOT EXISTS {} (
-                        id uuid PRIMARY KEY
-                    ) WITH (
-                        OIDS=FALSE
-                    )
-                "#,
-                self.migration_metadata_table
-            )
-            .as_str(),
-            &[],
-        )?;
-        Ok(())
+        let mut trans = self.conn.transaction()?;
+        if let Some(schema) = self.tables.metadata.quoted_schema() {
+            trans.execute(
+                format!("CREATE SCHEMA IF NOT EXISTS {}", schema).as_str(),
+                &[],
+            )?;
+        }
+        upgrade_layout(&self.tables, &mut trans)?;
+        trans.commit()
+    }
+
+    /// Returns the non-transactional migrations that started to be applied or
+    /// reverted, but failed midway, along with the direction they were run in.
+    ///
+    /// Such a migration may have left the database partially migrated. Its
+    /// marker is cleared once it is run again successfully, or once it has
+    /// been repaired by hand and [`PostgresAdapter::clear_dirty`] is called.
+    pub fn dirty_migrations(&mut self) -> Result<HashMap<Uuid, MigrationDirection>, PostgresError> {
+        let rows = self.conn.query(self.tables.select_dirty().as_str(), &[])?;
+        Ok(rows
+            .iter()
+            .map(|row| (row.get(0), row.get::<_, WrappedDirection>(1).0))
+            .collect())
+    }
+
+    /// Clear the dirty marker of a migration, if it has one.
+    pub fn clear_dirty(&mut self, id: Uuid) -> Result<(), PostgresError> {
+        let mut trans = self.conn.transaction()?;
+        clear_dirty(&self.tables, &mut trans, id)?;
+        trans.commit()
+    }
+
+    /// Run a non-transactional migration directly on the client, recording it
+    /// as dirty until it has finished.
+    fn run_without_transaction(
+        &mut self,
+        migration: &<Self as Adapter<Uuid>>::MigrationType,
+        direction: MigrationDirection,
+    ) -> Result<(), PostgresError> {
+        let client = match &mut self.conn {
+            Conn::Client(client) => client,
+            Conn::Transaction(transaction) => {
+                return reject_in_transaction(&mut transaction.transaction()?, migration)
+            }
+        };
+
+        let started_at = SystemTime::now();
+        let start = Instant::now();
+        record_dirty(&self.tables, client, migration, direction, started_at)?;
+        match direction {
+            MigrationDirection::Up => migration.up_without_transaction(client)?,
+            MigrationDirection::Down => migration.down_without_transaction(client)?,
+        }
+
+        let mut trans = client.transaction()?;
+        match direction {
+            MigrationDirection::Up => record_applied(
+                &self.tables,
+                &mut trans,
+                migration,
+                started_at,
+                start.elapsed(),
+            )?,
+            MigrationDirection::Down => record_reverted(
+                &self.tables,
+                &mut trans,
+                migration,
+                started_at,
+                start.elapsed(),
+            )?,
+        }
+        trans.commit()
     }
 }
 
@@ -133,48 +550,147 @@ impl<'a> Adapter<Uuid> for PostgresAdapter<'a> {
     type Error = PostgresAdapterError;
 
     fn applied_migrations(&mut self) -> Result<HashSet<Uuid>, Self::Error> {
-        let rows = self.conn.query(
-            format!("SELECT id FROM {};", self.migration_metadata_table).as_str(),
-            &[],
-        )?;
+        let rows = self
+            .conn
+            .query(self.tables.select_applied().as_str(), &[])?;
         Ok(rows.iter().map(|row| row.get(0)).collect())
     }
 
+    fn applied_fingerprints(&mut self) -> Result<HashMap<Uuid, Vec<u8>>, Self::Error> {
+        let rows = self
+            .conn
+            .query(self.tables.select_fingerprints().as_str(), &[])?;
+        Ok(rows.iter().map(|row| (row.get(0), row.get(1))).collect())
+    }
+
+    fn history(&mut self) -> Result<Vec<HistoryEntry<Uuid>>, Self::Error> {
+        let rows = self
+            .conn
+            .query(self.tables.select_history().as_str(), &[])?;
+        Ok(rows
+            .iter()
+            .map(|row| HistoryEntry {
+                id: row.get(0),
+                description: row.get::<_, Option<String>>(1).unwrap_or_default(),
+                direction: row.get::<_, WrappedDirection>(2).0,
+                executed_at: row.get(3),
+                duration: Duration::from_millis(row.get::<_, i64>(4) as u64),
+            })
+            .collect())
+    }
+
     fn apply_migration(&mut self, migration: &Self::MigrationType) -> Result<(), Self::Error> {
+        if !migration.is_transactional() {
+            return self.run_without_transaction(migration, MigrationDirection::Up);
+        }
+
         let mut trans = self.conn.transaction()?;
+        let applied_at = SystemTime::now();
+        let start = Instant::now();
         migration.up(&mut trans)?;
-        trans.execute(
-            format!(
-                "INSERT INTO {} (id) VALUES ($1::uuid);",
-                self.migration_metadata_table
-            )
-            .as_str(),
-            &[&migration.id()],
+        record_applied(
+            &self.tables,
+            &mut trans,
+            migration,
+            applied_at,
+            start.elapsed(),
         )?;
         trans.commit()
     }
 
     fn revert_migration(&mut self, migration: &Self::MigrationType) -> Result<(), Self::Error> {
+        if !migration.is_transactional() {
+            return self.run_without_transaction(migration, MigrationDirection::Down);
+        }
+
         let mut trans = self.conn.transaction()?;
+        let reverted_at = SystemTime::now();
+        let start = Instant::now();
         migration.down(&mut trans)?;
+        record_reverted(
+            &self.tables,
+            &mut trans,
+            migration,
+            reverted_at,
+            start.elapsed(),
+        )?;
+        trans.commit()
+    }
+
+    fn reapply_migration(&mut self, migration: &Self::MigrationType) -> Result<(), Self::Error> {
+        // Recording the migration as applied replaces the earlier record of it.
+        self.apply_migration(migration)
+    }
+
+    fn mark_applied(&mut self, migration: &Self::MigrationType) -> Result<(), Self::Error> {
+        let mut trans = self.conn.transaction()?;
+        record_applied(
+            &self.tables,
+            &mut trans,
+            migration,
+            SystemTime::now(),
+            Duration::ZERO,
+        )?;
+        trans.commit()
+    }
+
+    fn unmark_applied(&mut self, migration: &Self::MigrationType) -> Result<(), Self::Error> {
+        let mut trans = self.conn.transaction()?;
+        record_reverted(
+            &self.tables,
+            &mut trans,
+            migration,
+            SystemTime::now(),
+            Duration::ZERO,
+        )?;
+        trans.commit()
+    }
+
+    fn lock(&mut self) -> Result<(), Self::Error> {
+        // Transactional adapters only release the lock when their transaction
+        // ends, so that other migrators see the migrations it committed.
+        let lock = self.tables.lock(matches!(self.conn, Conn::Transaction(_)));
+
+        let mut trans = self.conn.transaction()?;
+        let previous_timeout: String = trans
+            .query_one("SELECT current_setting('lock_timeout')", &[])?
+            .get(0);
+        if let Some(timeout) = self.lock_timeout {
+            // A timeout of zero would disable the timeout.
+            let timeout = format!("{}ms", timeout.as_millis().max(1));
+            trans.execute("SELECT set_config('lock_timeout', $1, true)", &[&timeout])?;
+        }
+        trans.execute(lock.as_str(), &[&self.tables.metadata.to_string()])?;
         trans.execute(
-            format!(
-                "DELETE FROM {} WHERE id = $1::uuid;",
-                self.migration_metadata_table
-            )
-            .as_str(),
-            &[&migration.id()],
+            "SELECT set_config('lock_timeout', $1, true)",
+            &[&previous_timeout],
         )?;
         trans.commit()
     }
+
+    fn unlock(&mut self) -> Result<(), Self::Error> {
+        if let Conn::Client(_) = self.conn {
+            self.conn.execute(
+                self.tables.unlock().as_str(),
+                &[&self.tables.metadata.to_string()],
+            )?;
+        }
+        Ok(())
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::sync::{Arc, Barrier};
+    use std::thread;
+
     use super::*;
+    use postgres::error::SqlState;
     use postgres::NoTls;
     use schemerz::test_schemerz_adapter;
     use schemerz::testing::*;
+    use schemerz::{Migrator, MigratorError};
+    use schemerz_postgres_layout::LAYOUT_VERSION;
 
     impl PostgresMigration for TestMigration<Uuid> {}
 
@@ -182,6 +698,16 @@ mod tests {
         fn mock(id: Uuid, dependencies: HashSet<Uuid>) -> Self::MigrationType {
             Box::new(TestMigration::new(id, dependencies))
         }
+
+        fn mock_baseline(
+            id: Uuid,
+            dependencies: HashSet<Uuid>,
+            replaces: HashSet<Uuid>,
+        ) -> Option<Self::MigrationType> {
+            Some(Box::new(
+                TestMigration::new(id, dependencies).with_replaces(replaces),
+            ))
+        }
     }
 
     fn build_test_connection() -> Client {
@@ -205,4 +731,563 @@ mod tests {
         build_test_adapter(&mut conn),
         uuid_iter(),
     );
+
+    fn fingerprinted_migration(id: Uuid, fingerprint: &[u8]) -> Box<dyn PostgresMigration> {
+        Box::new(TestMigration::new(id, HashSet::new()).with_fingerprint(fingerprint.to_vec()))
+    }
+
+    #[test]
+    fn test_fingerprint_drift() {
+        let mut conn = build_test_connection();
+        let id = uuid_iter().next().unwrap();
+
+        let mut migrator = Migrator::new(build_test_adapter(&mut conn));
+        migrator
+            .register(fingerprinted_migration(id, b"v1"))
+            .unwrap();
+        migrator.up(None).unwrap();
+        drop(migrator);
+
+        let mut migrator = Migrator::new(build_test_adapter(&mut conn));
+        migrator
+            .register(fingerprinted_migration(id, b"v2"))
+            .unwrap();
+        assert!(matches!(
+            migrator.up(None),
+            Err(MigratorError::Drift { id: drifted, .. }) if drifted == id
+        ));
+    }
+
+    #[test]
+    fn test_repeatable() {
+        let mut conn = build_test_connection();
+        let id = uuid_iter().next().unwrap();
+        let repeatable = |fingerprint: &[u8]| -> Box<dyn PostgresMigration> {
+            Box::new(
+                TestMigration::new(id, HashSet::new())
+                    .with_fingerprint(fingerprint.to_vec())
+                    .repeatable(),
+            )
+        };
+
+        for fingerprint in [b"v1", b"v1", b"v2"] {
+            let mut migrator = Migrator::new(build_test_adapter(&mut conn));
+            migrator.register(repeatable(fingerprint)).unwrap();
+            migrator.up(None).unwrap();
+            assert!(migrator.status().unwrap().outdated.is_empty());
+        }
+
+        let mut adapter = build_test_adapter(&mut conn);
+        assert_eq!(adapter.applied_fingerprints().unwrap()[&id], b"v2".to_vec());
+        let history = adapter.history().unwrap();
+        assert_eq!(
+            history
+                .iter()
+                .map(|entry| (entry.id, entry.direction))
+                .collect::<Vec<_>>(),
+            vec![(id, MigrationDirection::Up), (id, MigrationDirection::Up)]
+        );
+    }
+
+    #[test]
+    fn test_history() {
+        let mut conn = build_test_connection();
+        let mut ids = uuid_iter();
+        let id1 = ids.next().unwrap();
+        let id2 = ids.next().unwrap();
+
+        let mut migrator = Migrator::new(build_test_adapter(&mut conn));
+        migrator
+            .register_multiple(
+                vec![
+                    PostgresAdapter::mock(id1, HashSet::new()),
+                    PostgresAdapter::mock(id2, [id1].into_iter().collect()),
+                ]
+                .into_iter(),
+            )
+            .unwrap();
+        migrator.up(None).unwrap();
+        migrator.down(Some(id1)).unwrap();
+
+        let history = migrator.history().unwrap();
+        assert_eq!(
+            history
+                .iter()
+                .map(|entry| (entry.id, entry.direction))
+                .collect::<Vec<_>>(),
+            vec![
+                (id1, MigrationDirection::Up),
+                (id2, MigrationDirection::Up),
+                (id2, MigrationDirection::Down),
+            ]
+        );
+        assert!(history
+            .iter()
+            .all(|entry| entry.description == "Test Migration"));
+        drop(migrator);
+
+        let row = conn
+            .query_one("SELECT applied_at FROM _schemerz WHERE id = $1", &[&id1])
+            .unwrap();
+        assert!(row.get::<_, Option<SystemTime>>(0).is_some());
+    }
+
+    struct SqlMigration {
+        id: Uuid,
+        dependencies: HashSet<Uuid>,
+        sql: &'static str,
+    }
+
+    impl Migration<Uuid> for SqlMigration {
+        fn id(&self) -> Uuid {
+            self.id
+        }
+
+        fn dependencies(&self) -> HashSet<Uuid> {
+            self.dependencies.clone()
+        }
+
+        fn description(&self) -> &'static str {
+            "SQL Test Migration"
+        }
+    }
+
+    impl PostgresMigration for SqlMigration {
+        fn up(&self, transaction: &mut Transaction<'_>) -> Result<(), PostgresError> {
+            transaction.batch_execute(self.sql)
+        }
+    }
+
+    fn table_exists(conn: &mut Client, table: &str) -> bool {
+        conn.query_one(
+            "SELECT to_regclass($1::text) IS NOT NULL",
+            &[&format!("pg_temp.{}", table)],
+        )
+        .unwrap()
+        .get(0)
+    }
+
+    fn transactional_migrator<'a>(
+        conn: &'a mut Client,
+        second_sql: &'static str,
+    ) -> Migrator<Uuid, PostgresAdapter<'a>> {
+        let mut ids = uuid_iter();
+        let id1 = ids.next().unwrap();
+        let id2 = ids.next().unwrap();
+
+        let mut adapter = PostgresAdapter::new_transactional(conn, None).unwrap();
+        adapter.init().unwrap();
+        let mut migrator = Migrator::new(adapter);
+        migrator
+            .register_multiple(
+                vec![
+                    Box::new(SqlMigration {
+                        id: id1,
+                        dependencies: HashSet::new(),
+                        sql: "CREATE TABLE first (id integer PRIMARY KEY);",
+                    }) as Box<dyn PostgresMigration>,
+                    Box::new(SqlMigration {
+                        id: id2,
+                        dependencies: [id1].into_iter().collect(),
+                        sql: second_sql,
+                    }),
+                ]
+                .into_iter(),
+            )
+            .unwrap();
+        migrator
+    }
+
+    #[test]
+    fn test_transactional_rollback() {
+        let mut conn = build_test_connection();
+
+        let mut migrator = transactional_migrator(&mut conn, "NOT VALID SQL;");
+        assert!(migrator.up(None).is_err());
+        drop(migrator);
+
+        assert!(!table_exists(&mut conn, "first"));
+        assert!(!table_exists(&mut conn, "_schemerz"));
+    }
+
+    #[test]
+    fn test_transactional_commit() {
+        let mut conn = build_test_connection();
+
+        let mut migrator =
+            transactional_migrator(&mut conn, "CREATE TABLE second (id integer PRIMARY KEY);");
+        migrator.up(None).unwrap();
+        migrator.into_adapter().commit().unwrap();
+
+        assert!(table_exists(&mut conn, "first"));
+        assert!(table_exists(&mut conn, "second"));
+        let applied: i64 = conn
+            .query_one("SELECT COUNT(*) FROM _schemerz", &[])
+            .unwrap()
+            .get(0);
+        assert_eq!(applied, 2);
+    }
+
+    #[test]
+    fn test_init_upgrades_bare_table() {
+        let mut conn = build_test_connection();
+        conn.execute("CREATE TABLE _schemerz (id uuid PRIMARY KEY)", &[])
+            .unwrap();
+
+        let id = uuid_iter().next().unwrap();
+        let mut migrator = Migrator::new(build_test_adapter(&mut conn));
+        migrator
+            .register(fingerprinted_migration(id, b"v1"))
+            .unwrap();
+        migrator.up(None).unwrap();
+        migrator.validate().unwrap();
+        drop(migrator);
+
+        let version: i32 = conn
+            .query_one("SELECT version FROM _schemerz_layout", &[])
+            .unwrap()
+            .get(0);
+        assert_eq!(version, LAYOUT_VERSION);
+        build_test_adapter(&mut conn);
+        let rows: i64 = conn
+            .query_one("SELECT COUNT(*) FROM _schemerz_layout", &[])
+            .unwrap()
+            .get(0);
+        assert_eq!(rows, 1);
+    }
+
+    #[test]
+    fn test_init_rejects_newer_layout() {
+        let mut conn = build_test_connection();
+        build_test_adapter(&mut conn);
+        conn.execute(
+            "UPDATE _schemerz_layout SET version = $1",
+            &[&(LAYOUT_VERSION + 1)],
+        )
+        .unwrap();
+
+        let error = PostgresAdapter::new(&mut conn, None).init().unwrap_err();
+        assert_eq!(error.code(), Some(&SqlState::FEATURE_NOT_SUPPORTED));
+    }
+
+    #[test]
+    fn test_baseline() {
+        let mut conn = build_test_connection();
+        let mut ids = uuid_iter();
+        let id1 = ids.next().unwrap();
+        let id2 = ids.next().unwrap();
+        let baseline = ids.next().unwrap();
+
+        let mut migrator = Migrator::new(build_test_adapter(&mut conn));
+        migrator
+            .register_multiple(
+                vec![
+                    Box::new(TestMigration::new(id1, HashSet::new())) as Box<dyn PostgresMigration>,
+                    Box::new(TestMigration::new(id2, [id1].into_iter().collect())),
+                    Box::new(
+                        TestMigration::new(baseline, HashSet::new())
+                            .with_replaces([id1, id2].into_iter().collect()),
+                    ),
+                ]
+                .into_iter(),
+            )
+            .unwrap();
+        migrator.up(None).unwrap();
+        migrator.validate().unwrap();
+        migrator.down(None).unwrap();
+
+        let history = migrator.history().unwrap();
+        assert_eq!(
+            history
+                .iter()
+                .map(|entry| (entry.id, entry.direction))
+                .collect::<Vec<_>>(),
+            vec![
+                (baseline, MigrationDirection::Up),
+                (id1, MigrationDirection::Up),
+                (id2, MigrationDirection::Up),
+                (id2, MigrationDirection::Down),
+                (id1, MigrationDirection::Down),
+                (baseline, MigrationDirection::Down),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lock_timeout() {
+        let table = "_schemerz_lock_timeout_test";
+        let mut holder_conn = build_test_connection();
+        let mut holder =
+            PostgresAdapter::new(&mut holder_conn, Some(TableName::new(table).unwrap()));
+        holder.lock().unwrap();
+
+        let mut conn = build_test_connection();
+        let mut adapter = PostgresAdapter::new(&mut conn, Some(TableName::new(table).unwrap()))
+            .with_lock_timeout(Duration::from_millis(100));
+        adapter.init().unwrap();
+        let mut migrator = Migrator::new(adapter);
+        migrator
+            .register(Box::new(TestMigration::new(
+                uuid_iter().next().unwrap(),
+                HashSet::new(),
+            )))
+            .unwrap();
+
+        match migrator.up(None) {
+            Err(MigratorError::Adapter(e)) => {
+                assert_eq!(e.code(), Some(&SqlState::LOCK_NOT_AVAILABLE))
+            }
+            result => panic!("Expected a lock timeout, got {:?}", result.map(|_| ())),
+        }
+
+        holder.unlock().unwrap();
+        migrator.up(None).unwrap();
+        assert_eq!(migrator.status().unwrap().applied.len(), 1);
+    }
+
+    #[test]
+    fn test_concurrent_migrators() {
+        // Both migrators must share the metadata table, so it cannot be a
+        // temporary one.
+        let table = "_schemerz_concurrency_test";
+        let mut conn = Client::connect("postgresql://postgres@localhost", NoTls).unwrap();
+        let drop_tables = format!(
+            "DROP TABLE IF EXISTS {0}, {0}_history, {0}_dirty, {0}_layout",
+            table
+        );
+        conn.batch_execute(&drop_tables).unwrap();
+        PostgresAdapter::new(&mut conn, Some(TableName::new(table).unwrap()))
+            .init()
+            .unwrap();
+
+        let id = uuid_iter().next().unwrap();
+        let barrier = Arc::new(Barrier::new(2));
+        let threads: Vec<_> = (0..2)
+            .map(|_| {
+                let barrier = barrier.clone();
+                thread::spawn(move || {
+                    let mut conn =
+                        Client::connect("postgresql://postgres@localhost", NoTls).unwrap();
+                    let mut migrator = Migrator::new(PostgresAdapter::new(
+                        &mut conn,
+                        Some(TableName::new(table).unwrap()),
+                    ));
+                    migrator
+                        .register(Box::new(SqlMigration {
+                            id,
+                            dependencies: HashSet::new(),
+                            sql: "SELECT pg_sleep(0.2);",
+                        }))
+                        .unwrap();
+                    barrier.wait();
+                    migrator.up(None).unwrap();
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let applied: i64 = conn
+            .query_one(
+                format!("SELECT COUNT(*) FROM {}_history", table).as_str(),
+                &[],
+            )
+            .unwrap()
+            .get(0);
+        conn.batch_execute(&drop_tables).unwrap();
+        assert_eq!(applied, 1);
+    }
+
+    #[test]
+    fn test_schema_qualified_table() {
+        // Schemas cannot be temporary, so use one of our own and drop it.
+        let schema = "schemerz \"qualified\" test";
+        let mut conn = Client::connect("postgresql://postgres@localhost", NoTls).unwrap();
+        let drop_schema = format!(
+            "DROP SCHEMA IF EXISTS \"{}\" CASCADE",
+            schema.replace('"', "\"\"")
+        );
+        conn.batch_execute(&drop_schema).unwrap();
+
+        let table_name =
+            TableName::parse(r#""schemerz ""qualified"" test"."Meta; DROP TABLE x""#).unwrap();
+        assert_eq!(table_name.schema(), Some(schema));
+        let mut adapter = PostgresAdapter::new(&mut conn, Some(table_name.clone()));
+        adapter.init().unwrap();
+        adapter.init().unwrap();
+
+        let id = uuid_iter().next().unwrap();
+        let mut migrator = Migrator::new(adapter);
+        migrator
+            .register(Box::new(TestMigration::new(id, HashSet::new())))
+            .unwrap();
+        migrator.up(None).unwrap();
+        assert_eq!(migrator.status().unwrap().applied.len(), 1);
+        assert_eq!(migrator.history().unwrap().len(), 1);
+        drop(migrator);
+
+        let applied: i64 = conn
+            .query_one(format!("SELECT COUNT(*) FROM {}", table_name).as_str(), &[])
+            .unwrap()
+            .get(0);
+        conn.batch_execute(&drop_schema).unwrap();
+        assert_eq!(applied, 1);
+    }
+
+    struct NonTransactionalMigration {
+        id: Uuid,
+        up: &'static [&'static str],
+        down: &'static [&'static str],
+    }
+
+    impl Migration<Uuid> for NonTransactionalMigration {
+        fn id(&self) -> Uuid {
+            self.id
+        }
+
+        fn dependencies(&self) -> HashSet<Uuid> {
+            HashSet::new()
+        }
+
+        fn description(&self) -> &'static str {
+            "Non-transactional Test Migration"
+        }
+    }
+
+    impl PostgresMigration for NonTransactionalMigration {
+        fn is_transactional(&self) -> bool {
+            false
+        }
+
+        fn up_without_transaction(&self, client: &mut Client) -> Result<(), PostgresError> {
+            // Run each statement separately, so that a failing statement does
+            // not roll back the ones before it.
+            self.up.iter().try_for_each(|sql| client.batch_execute(sql))
+        }
+
+        fn down_without_transaction(&self, client: &mut Client) -> Result<(), PostgresError> {
+            self.down
+                .iter()
+                .try_for_each(|sql| client.batch_execute(sql))
+        }
+    }
+
+    fn non_transactional_migrator<'a>(
+        adapter: PostgresAdapter<'a>,
+        up: &'static [&'static str],
+    ) -> Migrator<Uuid, PostgresAdapter<'a>> {
+        let mut migrator = Migrator::new(adapter);
+        migrator
+            .register(Box::new(NonTransactionalMigration {
+                id: uuid_iter().next().unwrap(),
+                up,
+                down: &["DROP TABLE nontx"],
+            }))
+            .unwrap();
+        migrator
+    }
+
+    #[test]
+    fn test_non_transactional() {
+        let mut conn = build_test_connection();
+        let id = uuid_iter().next().unwrap();
+
+        let mut migrator = non_transactional_migrator(
+            build_test_adapter(&mut conn),
+            &[
+                "CREATE TABLE IF NOT EXISTS nontx (id integer)",
+                "NOT VALID SQL",
+            ],
+        );
+        assert!(migrator.up(None).is_err());
+        assert!(migrator.status().unwrap().applied.is_empty());
+        let mut adapter = migrator.into_adapter();
+        assert_eq!(
+            adapter.dirty_migrations().unwrap(),
+            [(id, MigrationDirection::Up)].into_iter().collect()
+        );
+        drop(adapter);
+        assert!(table_exists(&mut conn, "nontx"));
+
+        // VACUUM cannot run inside a transaction.
+        let mut migrator = non_transactional_migrator(
+            build_test_adapter(&mut conn),
+            &[
+                "CREATE TABLE IF NOT EXISTS nontx (id integer)",
+                "VACUUM nontx",
+            ],
+        );
+        migrator.up(None).unwrap();
+        assert_eq!(migrator.status().unwrap().applied.len(), 1);
+        migrator.down(None).unwrap();
+        assert!(migrator.status().unwrap().applied.is_empty());
+        assert_eq!(migrator.history().unwrap().len(), 2);
+        let mut adapter = migrator.into_adapter();
+        assert!(adapter.dirty_migrations().unwrap().is_empty());
+        drop(adapter);
+        assert!(!table_exists(&mut conn, "nontx"));
+    }
+
+    #[test]
+    fn test_non_transactional_in_transaction() {
+        let mut conn = build_test_connection();
+        let mut adapter = PostgresAdapter::new_transactional(&mut conn, None).unwrap();
+        adapter.init().unwrap();
+
+        let mut migrator = non_transactional_migrator(adapter, &["VACUUM"]);
+        match migrator.up(None) {
+            Err(MigratorError::Migration { error, .. }) => {
+                assert_eq!(error.code(), Some(&SqlState::ACTIVE_SQL_TRANSACTION))
+            }
+            result => panic!("Expected a transaction error, got {:?}", result.map(|_| ())),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "sql")]
+    fn test_sql_migrations() {
+        let dir =
+            std::env::temp_dir().join(format!("schemerz-postgres-sql-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir(&dir).unwrap();
+        let id1 = "4885e8ab-dafa-4d76-a565-2dee8b04ef60";
+        let id2 = "0b0a4ca6-4a5b-4b8e-9fd4-29b0bd4d48ee";
+        for (file_name, sql) in [
+            (
+                format!("{}_users.up.sql", id1),
+                String::from("CREATE TABLE users (email text);"),
+            ),
+            (
+                format!("{}_users.down.sql", id1),
+                String::from("DROP TABLE users;"),
+            ),
+            (
+                format!("{}_index.up.sql", id2),
+                format!(
+                    "-- depends_on: {}\n-- transactional: false\nCREATE INDEX CONCURRENTLY users_email ON users (email);",
+                    id1
+                ),
+            ),
+            (
+                format!("{}_index.down.sql", id2),
+                String::from("DROP INDEX CONCURRENTLY users_email;"),
+            ),
+        ] {
+            std::fs::write(dir.join(file_name), sql).unwrap();
+        }
+        let migrations = load_sql_migrations(&dir).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let mut conn = build_test_connection();
+        let mut migrator = Migrator::new(build_test_adapter(&mut conn));
+        migrator.register_multiple(migrations.into_iter()).unwrap();
+        migrator.up(None).unwrap();
+        migrator.validate().unwrap();
+        migrator.down(None).unwrap();
+        assert_eq!(migrator.history().unwrap().len(), 4);
+        drop(migrator);
+
+        assert!(!table_exists(&mut conn, "users"));
+    }
 }
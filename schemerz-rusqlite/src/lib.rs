@@ -5,8 +5,8 @@
 //!
 //! ```rust
 //! extern crate rusqlite;
-//! #[macro_use]
 //! extern crate schemerz;
+//! extern crate schemerz_macros;
 //! extern crate schemerz_rusqlite;
 //! extern crate uuid;
 //!
@@ -14,15 +14,15 @@
 //!
 //! use rusqlite::{params, Connection, Transaction, Error as RusqliteError};
 //! use schemerz::{Migration, Migrator};
+//! use schemerz_macros::Migration;
 //! use schemerz_rusqlite::{RusqliteAdapter, RusqliteAdapterError, RusqliteMigration};
-//! use uuid::uuid;
 //!
+//! #[derive(Migration)]
+//! #[migration(
+//!     id = "4885e8ab-dafa-4d76-a565-2dee8b04ef60",
+//!     description = "An example migration without dependencies."
+//! )]
 //! struct MyExampleMigration;
-//! migration!(
-//!     MyExampleMigration,
-//!     uuid!("4885e8ab-dafa-4d76-a565-2dee8b04ef60"),
-//!     [],
-//!     "An example migration without dependencies.");
 //!
 //! impl RusqliteMigration for MyExampleMigration {
 //!     type Error = RusqliteError;
@@ -52,14 +52,21 @@
 #![warn(clippy::all)]
 #![forbid(unsafe_code)]
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
 use std::marker::{PhantomData, Send, Sync};
+use std::mem;
+use std::ops::Deref;
+#[cfg(feature = "sql")]
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use rusqlite::{params, Connection, Error as RusqliteError, Transaction};
+use rusqlite::{ffi, params, Connection, Error as RusqliteError, Transaction, TransactionBehavior};
 use uuid::Uuid;
 
-use schemerz::{Adapter, Migration};
+#[cfg(feature = "sql")]
+use schemerz::sql;
+use schemerz::{Adapter, HistoryEntry, Migration, MigrationDirection, TableName};
 
 /// SQlite-specific trait for schema migrations.
 pub trait RusqliteMigration: Migration<Uuid> {
@@ -71,6 +78,10 @@ pub trait RusqliteMigration: Migration<Uuid> {
     }
 
     /// Revert a migration to the database using a transaction.
+    ///
+    /// The default implementation does nothing. Migrations that cannot be
+    /// reverted should instead return `false` from
+    /// [`Migration::is_reversible`], so that `Migrator` refuses to revert them.
     fn down(&self, _transaction: &Transaction<'_>) -> Result<(), Self::Error> {
         Ok(())
     }
@@ -78,6 +89,37 @@ pub trait RusqliteMigration: Migration<Uuid> {
 
 pub type RusqliteAdapterError = RusqliteError;
 
+#[cfg(feature = "sql")]
+impl RusqliteMigration for sql::SqlMigration {
+    type Error = RusqliteError;
+
+    fn up(&self, transaction: &Transaction<'_>) -> Result<(), RusqliteError> {
+        transaction.execute_batch(self.up_sql())
+    }
+
+    fn down(&self, transaction: &Transaction<'_>) -> Result<(), RusqliteError> {
+        match self.down_sql() {
+            Some(sql) => transaction.execute_batch(sql),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Load the migrations in a directory of SQL files, as described in
+/// [`schemerz::sql`], in order of file name.
+///
+/// Every migration runs inside a transaction, whether or not it is declared
+/// transactional.
+#[cfg(feature = "sql")]
+pub fn load_sql_migrations(
+    dir: impl AsRef<Path>,
+) -> Result<Vec<Box<dyn RusqliteMigration<Error = RusqliteError>>>, sql::LoadError> {
+    Ok(sql::load_dir(dir)?
+        .into_iter()
+        .map(|migration| Box::new(migration) as Box<dyn RusqliteMigration<Error = RusqliteError>>)
+        .collect())
+}
+
 struct WrappedUuid(Uuid);
 
 impl rusqlite::types::FromSql for WrappedUuid {
@@ -88,10 +130,253 @@ impl rusqlite::types::FromSql for WrappedUuid {
     }
 }
 
+struct WrappedDirection(MigrationDirection);
+
+impl rusqlite::types::FromSql for WrappedDirection {
+    fn column_result(value: rusqlite::types::ValueRef<'_>) -> rusqlite::types::FromSqlResult<Self> {
+        match value.as_str()? {
+            "up" => Ok(WrappedDirection(MigrationDirection::Up)),
+            "down" => Ok(WrappedDirection(MigrationDirection::Down)),
+            _ => Err(rusqlite::types::FromSqlError::InvalidType),
+        }
+    }
+}
+
+fn direction_to_sql(direction: MigrationDirection) -> &'static str {
+    match direction {
+        MigrationDirection::Up => "up",
+        MigrationDirection::Down => "down",
+    }
+}
+
+/// Milliseconds since the Unix epoch, as stored in the metadata tables.
+fn timestamp_to_sql(timestamp: SystemTime) -> i64 {
+    timestamp
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since_epoch| since_epoch.as_millis() as i64)
+}
+
+fn timestamp_from_sql(millis: i64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(millis as u64)
+}
+
+/// Record a migration having been applied or reverted in the history table.
+fn record_history(
+    history_table: &TableName,
+    transaction: &Transaction<'_>,
+    migration: &dyn Migration<Uuid>,
+    direction: MigrationDirection,
+    executed_at: SystemTime,
+    duration: Duration,
+) -> Result<(), RusqliteError> {
+    transaction.execute(
+        &format!(
+            "INSERT INTO {} (id, direction, description, executed_at, duration_ms) VALUES (?1, ?2, ?3, ?4, ?5);",
+            history_table
+        ),
+        params![
+            &migration.id().as_bytes()[..],
+            direction_to_sql(direction),
+            migration.description(),
+            timestamp_to_sql(executed_at),
+            duration.as_millis() as i64,
+        ],
+    )?;
+    Ok(())
+}
+
+/// Returns whether a migration is recorded as applied in the metadata table.
+fn is_applied(
+    metadata_table: &TableName,
+    transaction: &Transaction<'_>,
+    migration: &dyn Migration<Uuid>,
+) -> Result<bool, RusqliteError> {
+    let uuid = migration.id();
+    let uuid_bytes = &uuid.as_bytes()[..];
+    transaction.query_row(
+        &format!(
+            "SELECT EXISTS (SELECT 1 FROM {} WHERE id = ?1);",
+            metadata_table
+        ),
+        [&uuid_bytes],
+        |row| row.get(0),
+    )
+}
+
+/// Returns whether a migration is recorded as applied in the metadata table
+/// with its current fingerprint.
+fn is_current(
+    metadata_table: &TableName,
+    transaction: &Transaction<'_>,
+    migration: &dyn Migration<Uuid>,
+) -> Result<bool, RusqliteError> {
+    let uuid = migration.id();
+    let uuid_bytes = &uuid.as_bytes()[..];
+    transaction.query_row(
+        &format!(
+            "SELECT EXISTS (SELECT 1 FROM {} WHERE id = ?1 AND fingerprint IS ?2);",
+            metadata_table
+        ),
+        params![uuid_bytes, migration.fingerprint()],
+        |row| row.get(0),
+    )
+}
+
+/// Record a migration as applied in the metadata table, replacing any earlier
+/// record of it, and in the history table.
+fn record_applied(
+    metadata_table: &TableName,
+    history_table: &TableName,
+    transaction: &Transaction<'_>,
+    migration: &dyn Migration<Uuid>,
+    applied_at: SystemTime,
+    duration: Duration,
+) -> Result<(), RusqliteError> {
+    let uuid = migration.id();
+    let uuid_bytes = &uuid.as_bytes()[..];
+    transaction.execute(
+        &format!(
+            "INSERT INTO {} (id, fingerprint, description, applied_at, duration_ms) VALUES (?1, ?2, ?3, ?4, ?5)
+                ON CONFLICT (id) DO UPDATE SET fingerprint = ?2, description = ?3, applied_at = ?4, duration_ms = ?5;",
+            metadata_table
+        ),
+        params![
+            uuid_bytes,
+            migration.fingerprint(),
+            migration.description(),
+            timestamp_to_sql(applied_at),
+            duration.as_millis() as i64,
+        ],
+    )?;
+    record_history(
+        history_table,
+        transaction,
+        migration,
+        MigrationDirection::Up,
+        applied_at,
+        duration,
+    )
+}
+
+/// Remove a migration from the metadata table, and record it as reverted in
+/// the history table.
+fn record_reverted(
+    metadata_table: &TableName,
+    history_table: &TableName,
+    transaction: &Transaction<'_>,
+    migration: &dyn Migration<Uuid>,
+    reverted_at: SystemTime,
+    duration: Duration,
+) -> Result<(), RusqliteError> {
+    let uuid = migration.id();
+    let uuid_bytes = &uuid.as_bytes()[..];
+    transaction.execute(
+        &format!("DELETE FROM {} WHERE id = ?1;", metadata_table),
+        [&uuid_bytes],
+    )?;
+    record_history(
+        history_table,
+        transaction,
+        migration,
+        MigrationDirection::Down,
+        reverted_at,
+        duration,
+    )
+}
+
+/// The version of the layout of the metadata tables that
+/// `RusqliteAdapter::init` creates, or upgrades older tables to. Tables
+/// created before layouts were versioned are version 0, and only have an `id`
+/// column.
+const LAYOUT_VERSION: i64 = 1;
+
+/// The connection used by a `RusqliteAdapter`.
+///
+/// Transactions are begun with `BEGIN IMMEDIATE`, which takes the database's
+/// write lock straight away, so that concurrent migrators wait for each other
+/// rather than reading the applied migrations from under each other.
+enum Conn<'a> {
+    /// Each migration runs in its own transaction.
+    Connection(&'a Connection),
+    /// The adapter is locked by this transaction on the connection, in which
+    /// each migration runs in a savepoint of its own. It is committed by
+    /// `Adapter::unlock`.
+    Locked(&'a Connection, Transaction<'a>),
+    /// Every migration runs in this transaction, which is committed by
+    /// `RusqliteAdapter::commit`.
+    Transaction(Transaction<'a>),
+}
+
+impl Conn<'_> {
+    /// Run `f` in a transaction of its own that is committed if `f` succeeds,
+    /// in a savepoint of the transaction holding the lock if there is one, or
+    /// in the transaction spanning every migration if there is one.
+    fn run<E: From<RusqliteError>>(
+        &mut self,
+        f: impl FnOnce(&Transaction<'_>) -> Result<(), E>,
+    ) -> Result<(), E> {
+        match self {
+            Conn::Connection(conn) => {
+                let trans = Transaction::new_unchecked(conn, TransactionBehavior::Immediate)?;
+                f(&trans)?;
+                trans.commit().map_err(|e| e.into())
+            }
+            Conn::Locked(_, trans) => {
+                trans.execute_batch("SAVEPOINT schemerz_migration;")?;
+                match f(trans) {
+                    Ok(()) => Ok(trans.execute_batch("RELEASE schemerz_migration;")?),
+                    Err(e) => {
+                        // The original error is more useful than one from
+                        // rolling back.
+                        let _ = trans.execute_batch(
+                            "ROLLBACK TO schemerz_migration; RELEASE schemerz_migration;",
+                        );
+                        Err(e)
+                    }
+                }
+            }
+            Conn::Transaction(trans) => f(trans),
+        }
+    }
+
+    /// Take the database's write lock until `unlock`, unless every migration
+    /// already runs in a transaction holding it.
+    fn lock(&mut self) -> Result<(), RusqliteError> {
+        if let Conn::Connection(conn) = *self {
+            let trans = Transaction::new_unchecked(conn, TransactionBehavior::Immediate)?;
+            *self = Conn::Locked(conn, trans);
+        }
+        Ok(())
+    }
+
+    /// Commit the migrations run since `lock`, releasing the write lock.
+    fn unlock(&mut self) -> Result<(), RusqliteError> {
+        if let Conn::Locked(conn, _) = *self {
+            if let Conn::Locked(_, trans) = mem::replace(self, Conn::Connection(conn)) {
+                trans.commit()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Deref for Conn<'_> {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        match self {
+            Conn::Connection(conn) => conn,
+            Conn::Locked(_, transaction) | Conn::Transaction(transaction) => transaction,
+        }
+    }
+}
+
 /// Adapter between schemerz and SQLite.
 pub struct RusqliteAdapter<'a, E> {
-    conn: &'a mut Connection,
-    migration_metadata_table: String,
+    conn: Conn<'a>,
+    migration_metadata_table: TableName,
+    migration_history_table: TableName,
+    migration_layout_table: TableName,
     _err: PhantomData<E>,
 }
 
@@ -100,7 +385,19 @@ impl<'a, E> RusqliteAdapter<'a, E> {
     ///
     /// `table_name` specifies the name of the table that schemerz will use
     /// for storing metadata about applied migrations. If `None`, a default
-    /// will be used.
+    /// will be used. The history of applied and reverted migrations is stored
+    /// in a second table, named by appending `_history` to `table_name`, and
+    /// the version of the tables' layout in a third, named by appending
+    /// `_layout`. If `table_name` is qualified by a schema, that must be the
+    /// name of an attached database, such as `main` or `temp`.
+    ///
+    /// While migrating, `Migrator` locks the database by holding a `BEGIN
+    /// IMMEDIATE` transaction, which is committed once it is done, so that
+    /// concurrent migrators wait for each other and read the applied
+    /// migrations under the lock. They wait for up to the connection's busy
+    /// timeout; see [`Connection::busy_timeout`]. Each migration is applied or
+    /// reverted in a savepoint of its own, so one that fails is rolled back
+    /// while the ones before it are still committed.
     ///
     /// ```rust
     /// # extern crate rusqlite;
@@ -111,30 +408,183 @@ impl<'a, E> RusqliteAdapter<'a, E> {
     /// let adapter: schemerz_rusqlite::RusqliteAdapter<RusqliteError> = schemerz_rusqlite::RusqliteAdapter::new(&mut conn, None);
     /// # }
     /// ```
-    pub fn new(conn: &'a mut Connection, table_name: Option<String>) -> RusqliteAdapter<'a, E> {
+    pub fn new(conn: &'a mut Connection, table_name: Option<TableName>) -> RusqliteAdapter<'a, E> {
+        Self::with_conn(Conn::Connection(conn), table_name)
+    }
+
+    /// Construct a SQLite schemerz adapter that applies and reverts every
+    /// migration inside a single transaction.
+    ///
+    /// Nothing is persisted until [`RusqliteAdapter::commit`] is called, so a
+    /// failure partway through a `Migrator::up` or `Migrator::down` leaves the
+    /// database untouched once the adapter is dropped. The transaction holds
+    /// the database's write lock from the start, so concurrent migrators wait
+    /// until it is committed or dropped. `table_name` is handled as for
+    /// [`RusqliteAdapter::new`].
+    ///
+    /// ```rust
+    /// # extern crate rusqlite;
+    /// # extern crate schemerz;
+    /// # extern crate schemerz_rusqlite;
+    /// # use rusqlite::{Error as RusqliteError};
+    /// # use schemerz::Migrator;
+    /// # use schemerz_rusqlite::RusqliteAdapter;
+    /// #
+    /// # fn main() {
+    /// let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+    /// let adapter: RusqliteAdapter<RusqliteError> =
+    ///     RusqliteAdapter::new_transactional(&mut conn, None).unwrap();
+    /// adapter.init().unwrap();
+    ///
+    /// let mut migrator = Migrator::new(adapter);
+    /// // Register migrations here.
+    /// migrator.up(None).unwrap();
+    /// migrator.into_adapter().commit().unwrap();
+    /// # }
+    /// ```
+    pub fn new_transactional(
+        conn: &'a mut Connection,
+        table_name: Option<TableName>,
+    ) -> Result<RusqliteAdapter<'a, E>, RusqliteError> {
+        Ok(Self::with_conn(
+            Conn::Transaction(conn.transaction_with_behavior(TransactionBehavior::Immediate)?),
+            table_name,
+        ))
+    }
+
+    fn with_conn(conn: Conn<'a>, table_name: Option<TableName>) -> RusqliteAdapter<'a, E> {
+        let migration_metadata_table = table_name.unwrap_or_default();
         RusqliteAdapter {
             conn,
-            migration_metadata_table: table_name.unwrap_or_else(|| "_schemerz".into()),
+            migration_history_table: migration_metadata_table.with_suffix("_history"),
+            migration_layout_table: migration_metadata_table.with_suffix("_layout"),
+            migration_metadata_table,
             _err: PhantomData,
         }
     }
 
+    /// Commit the transaction spanning every migration applied or reverted by
+    /// an adapter constructed with [`RusqliteAdapter::new_transactional`].
+    ///
+    /// For adapters constructed with [`RusqliteAdapter::new`], each migration
+    /// has already been committed, and this does nothing.
+    pub fn commit(self) -> Result<(), RusqliteError> {
+        match self.conn {
+            Conn::Connection(_) => Ok(()),
+            Conn::Locked(_, transaction) | Conn::Transaction(transaction) => transaction.commit(),
+        }
+    }
+
     /// Initialize the schemerz metadata schema. This must be called before
     /// using `Migrator` with this adapter. This is safe to call multiple times.
+    ///
+    /// Metadata tables created by earlier versions of schemerz are upgraded in
+    /// place. Tables laid out by a later version, which this one does not
+    /// know how to use, are rejected with a [`RusqliteError::SqliteFailure`]
+    /// whose code is [`rusqlite::ErrorCode::Unknown`].
     pub fn init(&self) -> Result<(), RusqliteError> {
+        // Take the write lock up front, so that concurrent calls cannot both
+        // start upgrading the same tables.
+        let (begin, commit, rollback) = match self.conn {
+            Conn::Connection(_) => ("BEGIN IMMEDIATE;", "COMMIT;", "ROLLBACK;"),
+            Conn::Locked(..) | Conn::Transaction(_) => (
+                "SAVEPOINT schemerz_init;",
+                "RELEASE schemerz_init;",
+                "ROLLBACK TO schemerz_init; RELEASE schemerz_init;",
+            ),
+        };
+        self.conn.execute_batch(begin)?;
+        match self.upgrade_layout() {
+            Ok(()) => self.conn.execute_batch(commit),
+            Err(e) => {
+                // The original error is more useful than one from rolling back.
+                let _ = self.conn.execute_batch(rollback);
+                Err(e)
+            }
+        }
+    }
+
+    /// Create the metadata tables, or upgrade them from the layout they were
+    /// created with to [`LAYOUT_VERSION`].
+    fn upgrade_layout(&self) -> Result<(), RusqliteError> {
         self.conn.execute(
             &format!(
-                r#"
-                    CREATE TABLE IF NOT EXISTS {} (
-                        id blob PRIMARY KEY
-                    )
-                "#,
+                "CREATE TABLE IF NOT EXISTS {} (id blob PRIMARY KEY)",
                 self.migration_metadata_table
             ),
             params![],
         )?;
+        self.conn.execute(
+            &format!(
+                "CREATE TABLE IF NOT EXISTS {} (version integer NOT NULL)",
+                self.migration_layout_table
+            ),
+            params![],
+        )?;
+
+        let version: i64 = self.conn.query_row(
+            &format!(
+                "SELECT COALESCE(MAX(version), 0) FROM {};",
+                self.migration_layout_table
+            ),
+            params![],
+            |row| row.get(0),
+        )?;
+        if version > LAYOUT_VERSION {
+            return Err(RusqliteError::SqliteFailure(
+                ffi::Error::new(ffi::SQLITE_ERROR),
+                Some(format!(
+                    "metadata table layout version {} is newer than the latest known, {}",
+                    version, LAYOUT_VERSION
+                )),
+            ));
+        }
+        if version == LAYOUT_VERSION {
+            return Ok(());
+        }
+
+        for from in version..LAYOUT_VERSION {
+            self.upgrade_layout_from(from)?;
+        }
+        self.conn.execute(
+            &format!("DELETE FROM {};", self.migration_layout_table),
+            params![],
+        )?;
+        self.conn.execute(
+            &format!(
+                "INSERT INTO {} (version) VALUES (?1);",
+                self.migration_layout_table
+            ),
+            params![LAYOUT_VERSION],
+        )?;
         Ok(())
     }
+
+    /// Upgrade the metadata tables from layout `version` to the next one.
+    fn upgrade_layout_from(&self, version: i64) -> Result<(), RusqliteError> {
+        match version {
+            // Record the fingerprint, description and timing of each applied
+            // migration, and keep the history of applied and reverted ones.
+            0 => self.conn.execute_batch(&format!(
+                r#"
+                    ALTER TABLE {0} ADD COLUMN fingerprint blob;
+                    ALTER TABLE {0} ADD COLUMN description text;
+                    ALTER TABLE {0} ADD COLUMN applied_at integer;
+                    ALTER TABLE {0} ADD COLUMN duration_ms integer;
+                    CREATE TABLE {1} (
+                        seq integer PRIMARY KEY AUTOINCREMENT,
+                        id blob NOT NULL,
+                        direction text NOT NULL,
+                        description text,
+                        executed_at integer NOT NULL,
+                        duration_ms integer NOT NULL
+                    );
+                "#,
+                self.migration_metadata_table, self.migration_history_table
+            )),
+            _ => unreachable!("no upgrade from metadata table layout version {}", version),
+        }
+    }
 }
 
 impl<'a, E> Adapter<Uuid> for RusqliteAdapter<'a, E>
@@ -160,43 +610,166 @@ where
         Ok(ids)
     }
 
+    fn applied_fingerprints(&mut self) -> Result<HashMap<Uuid, Vec<u8>>, Self::Error> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT id, fingerprint FROM {} WHERE fingerprint IS NOT NULL;",
+            self.migration_metadata_table
+        ))?;
+        let rows = stmt.query_map(params![], |row| {
+            Ok((row.get::<_, WrappedUuid>(0)?, row.get::<_, Vec<u8>>(1)?))
+        })?;
+        let mut fingerprints = HashMap::new();
+        for row in rows {
+            let (id, fingerprint) = row?;
+            fingerprints.insert(id.0, fingerprint);
+        }
+        Ok(fingerprints)
+    }
+
+    fn history(&mut self) -> Result<Vec<HistoryEntry<Uuid>>, Self::Error> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT id, description, direction, executed_at, duration_ms FROM {} ORDER BY seq;",
+            self.migration_history_table
+        ))?;
+        let rows = stmt.query_map(params![], |row| {
+            Ok(HistoryEntry {
+                id: row.get::<_, WrappedUuid>(0)?.0,
+                description: row.get::<_, Option<String>>(1)?.unwrap_or_default(),
+                direction: row.get::<_, WrappedDirection>(2)?.0,
+                executed_at: timestamp_from_sql(row.get(3)?),
+                duration: Duration::from_millis(row.get::<_, i64>(4)? as u64),
+            })
+        })?;
+        let mut history = vec![];
+        for row in rows {
+            history.push(row?);
+        }
+        Ok(history)
+    }
+
     fn apply_migration(&mut self, migration: &Self::MigrationType) -> Result<(), Self::Error> {
-        let trans = self.conn.transaction()?;
-        migration.up(&trans)?;
-        let uuid = migration.id();
-        let uuid_bytes = &uuid.as_bytes()[..];
-        trans.execute(
-            &format!(
-                "INSERT INTO {} (id) VALUES (?1);",
-                self.migration_metadata_table
-            ),
-            [&uuid_bytes],
-        )?;
-        trans.commit().map_err(|e| e.into())
+        self.conn.run(|trans| {
+            // Another migrator may have run it since the applied migrations
+            // were read.
+            if is_applied(&self.migration_metadata_table, trans, migration)? {
+                return Ok(());
+            }
+            let applied_at = SystemTime::now();
+            let start = Instant::now();
+            migration.up(trans)?;
+            record_applied(
+                &self.migration_metadata_table,
+                &self.migration_history_table,
+                trans,
+                migration,
+                applied_at,
+                start.elapsed(),
+            )?;
+            Ok(())
+        })
     }
 
     fn revert_migration(&mut self, migration: &Self::MigrationType) -> Result<(), Self::Error> {
-        let trans = self.conn.transaction()?;
-        migration.down(&trans)?;
-        let uuid = migration.id();
-        let uuid_bytes = &uuid.as_bytes()[..];
-        trans.execute(
-            &format!(
-                "DELETE FROM {} WHERE id = ?1;",
-                self.migration_metadata_table
-            ),
-            [&uuid_bytes],
-        )?;
-        trans.commit().map_err(|e| e.into())
+        self.conn.run(|trans| {
+            // Another migrator may have run it since the applied migrations
+            // were read.
+            if !is_applied(&self.migration_metadata_table, trans, migration)? {
+                return Ok(());
+            }
+            let reverted_at = SystemTime::now();
+            let start = Instant::now();
+            migration.down(trans)?;
+            record_reverted(
+                &self.migration_metadata_table,
+                &self.migration_history_table,
+                trans,
+                migration,
+                reverted_at,
+                start.elapsed(),
+            )?;
+            Ok(())
+        })
+    }
+
+    fn reapply_migration(&mut self, migration: &Self::MigrationType) -> Result<(), Self::Error> {
+        self.conn.run(|trans| {
+            // Another migrator may have applied it again since the applied
+            // fingerprints were read.
+            if is_current(&self.migration_metadata_table, trans, migration)? {
+                return Ok(());
+            }
+            let applied_at = SystemTime::now();
+            let start = Instant::now();
+            migration.up(trans)?;
+            record_applied(
+                &self.migration_metadata_table,
+                &self.migration_history_table,
+                trans,
+                migration,
+                applied_at,
+                start.elapsed(),
+            )?;
+            Ok(())
+        })
+    }
+
+    fn mark_applied(&mut self, migration: &Self::MigrationType) -> Result<(), Self::Error> {
+        self.conn.run(|trans| {
+            // Another migrator may have run it since the applied migrations
+            // were read.
+            if is_applied(&self.migration_metadata_table, trans, migration)? {
+                return Ok(());
+            }
+            record_applied(
+                &self.migration_metadata_table,
+                &self.migration_history_table,
+                trans,
+                migration,
+                SystemTime::now(),
+                Duration::ZERO,
+            )?;
+            Ok(())
+        })
+    }
+
+    fn unmark_applied(&mut self, migration: &Self::MigrationType) -> Result<(), Self::Error> {
+        self.conn.run(|trans| {
+            // Another migrator may have run it since the applied migrations
+            // were read.
+            if !is_applied(&self.migration_metadata_table, trans, migration)? {
+                return Ok(());
+            }
+            record_reverted(
+                &self.migration_metadata_table,
+                &self.migration_history_table,
+                trans,
+                migration,
+                SystemTime::now(),
+                Duration::ZERO,
+            )?;
+            Ok(())
+        })
+    }
+
+    fn lock(&mut self) -> Result<(), Self::Error> {
+        Ok(self.conn.lock()?)
+    }
+
+    fn unlock(&mut self) -> Result<(), Self::Error> {
+        Ok(self.conn.unlock()?)
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::sync::{Arc, Barrier, Mutex};
+    use std::thread;
+
     use super::*;
-    use rusqlite::Error as RusqliteError;
+    use rusqlite::{Error as RusqliteError, ErrorCode};
     use schemerz::test_schemerz_adapter;
     use schemerz::testing::*;
+    use schemerz::{MigrationObserver, Migrator, MigratorError, PlannedMigration};
 
     impl RusqliteMigration for TestMigration<Uuid> {
         type Error = RusqliteError;
@@ -206,6 +779,16 @@ mod tests {
         fn mock(id: Uuid, dependencies: HashSet<Uuid>) -> Self::MigrationType {
             Box::new(TestMigration::new(id, dependencies))
         }
+
+        fn mock_baseline(
+            id: Uuid,
+            dependencies: HashSet<Uuid>,
+            replaces: HashSet<Uuid>,
+        ) -> Option<Self::MigrationType> {
+            Some(Box::new(
+                TestMigration::new(id, dependencies).with_replaces(replaces),
+            ))
+        }
     }
 
     fn build_test_connection() -> Connection {
@@ -227,4 +810,466 @@ mod tests {
         build_test_adapter(&mut conn),
         uuid_iter(),
     );
+
+    fn fingerprinted_migration(
+        id: Uuid,
+        fingerprint: &[u8],
+    ) -> Box<dyn RusqliteMigration<Error = RusqliteError>> {
+        Box::new(TestMigration::new(id, HashSet::new()).with_fingerprint(fingerprint.to_vec()))
+    }
+
+    #[test]
+    fn test_fingerprint_drift() {
+        let mut conn = build_test_connection();
+        let id = uuid_iter().next().unwrap();
+
+        let mut migrator = Migrator::new(build_test_adapter(&mut conn));
+        migrator
+            .register(fingerprinted_migration(id, b"v1"))
+            .unwrap();
+        migrator.up(None).unwrap();
+        drop(migrator);
+
+        let mut migrator = Migrator::new(build_test_adapter(&mut conn));
+        migrator
+            .register(fingerprinted_migration(id, b"v2"))
+            .unwrap();
+        assert!(matches!(
+            migrator.up(None),
+            Err(MigratorError::Drift { id: drifted, .. }) if drifted == id
+        ));
+    }
+
+    #[test]
+    fn test_repeatable() {
+        let mut conn = build_test_connection();
+        let id = uuid_iter().next().unwrap();
+        let repeatable = |fingerprint: &[u8]| -> Box<dyn RusqliteMigration<Error = RusqliteError>> {
+            Box::new(
+                TestMigration::new(id, HashSet::new())
+                    .with_fingerprint(fingerprint.to_vec())
+                    .repeatable(),
+            )
+        };
+
+        for fingerprint in [b"v1", b"v1", b"v2"] {
+            let mut migrator = Migrator::new(build_test_adapter(&mut conn));
+            migrator.register(repeatable(fingerprint)).unwrap();
+            migrator.up(None).unwrap();
+            assert!(migrator.status().unwrap().outdated.is_empty());
+        }
+
+        let mut adapter = build_test_adapter(&mut conn);
+        assert_eq!(adapter.applied_fingerprints().unwrap()[&id], b"v2".to_vec());
+        let history = adapter.history().unwrap();
+        assert_eq!(
+            history
+                .iter()
+                .map(|entry| (entry.id, entry.direction))
+                .collect::<Vec<_>>(),
+            vec![(id, MigrationDirection::Up), (id, MigrationDirection::Up)]
+        );
+    }
+
+    #[test]
+    fn test_history() {
+        let mut conn = build_test_connection();
+        let mut ids = uuid_iter();
+        let id1 = ids.next().unwrap();
+        let id2 = ids.next().unwrap();
+
+        let mut migrator = Migrator::new(build_test_adapter(&mut conn));
+        migrator
+            .register_multiple(
+                vec![
+                    RusqliteAdapter::mock(id1, HashSet::new()),
+                    RusqliteAdapter::mock(id2, [id1].into_iter().collect()),
+                ]
+                .into_iter(),
+            )
+            .unwrap();
+        migrator.up(None).unwrap();
+        migrator.down(Some(id1)).unwrap();
+
+        let history = migrator.history().unwrap();
+        assert_eq!(
+            history
+                .iter()
+                .map(|entry| (entry.id, entry.direction))
+                .collect::<Vec<_>>(),
+            vec![
+                (id1, MigrationDirection::Up),
+                (id2, MigrationDirection::Up),
+                (id2, MigrationDirection::Down),
+            ]
+        );
+        assert!(history
+            .iter()
+            .all(|entry| entry.description == "Test Migration"));
+        drop(migrator);
+
+        let applied_at: Option<i64> = conn
+            .query_row(
+                "SELECT applied_at FROM _schemerz WHERE id = ?1",
+                [&id1.as_bytes()[..]],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert!(applied_at.is_some());
+    }
+
+    struct SqlMigration {
+        id: Uuid,
+        dependencies: HashSet<Uuid>,
+        sql: &'static str,
+    }
+
+    impl Migration<Uuid> for SqlMigration {
+        fn id(&self) -> Uuid {
+            self.id
+        }
+
+        fn dependencies(&self) -> HashSet<Uuid> {
+            self.dependencies.clone()
+        }
+
+        fn description(&self) -> &'static str {
+            "SQL Test Migration"
+        }
+    }
+
+    impl RusqliteMigration for SqlMigration {
+        type Error = RusqliteError;
+
+        fn up(&self, transaction: &Transaction<'_>) -> Result<(), Self::Error> {
+            transaction.execute_batch(self.sql)
+        }
+    }
+
+    fn table_exists(conn: &Connection, table: &str) -> bool {
+        conn.query_row(
+            "SELECT COUNT(*) > 0 FROM sqlite_master WHERE name = ?1",
+            [table],
+            |row| row.get(0),
+        )
+        .unwrap()
+    }
+
+    fn transactional_migrator<'a>(
+        conn: &'a mut Connection,
+        second_sql: &'static str,
+    ) -> Migrator<Uuid, RusqliteAdapter<'a, RusqliteError>> {
+        let mut ids = uuid_iter();
+        let id1 = ids.next().unwrap();
+        let id2 = ids.next().unwrap();
+
+        let adapter = RusqliteAdapter::new_transactional(conn, None).unwrap();
+        adapter.init().unwrap();
+        let mut migrator = Migrator::new(adapter);
+        migrator
+            .register_multiple(
+                vec![
+                    Box::new(SqlMigration {
+                        id: id1,
+                        dependencies: HashSet::new(),
+                        sql: "CREATE TABLE first (id integer PRIMARY KEY);",
+                    }) as Box<dyn RusqliteMigration<Error = RusqliteError>>,
+                    Box::new(SqlMigration {
+                        id: id2,
+                        dependencies: [id1].into_iter().collect(),
+                        sql: second_sql,
+                    }),
+                ]
+                .into_iter(),
+            )
+            .unwrap();
+        migrator
+    }
+
+    #[test]
+    fn test_transactional_rollback() {
+        let mut conn = build_test_connection();
+
+        let mut migrator = transactional_migrator(&mut conn, "NOT VALID SQL;");
+        assert!(migrator.up(None).is_err());
+        drop(migrator);
+
+        assert!(!table_exists(&conn, "first"));
+        assert!(!table_exists(&conn, "_schemerz"));
+    }
+
+    #[test]
+    fn test_transactional_commit() {
+        let mut conn = build_test_connection();
+
+        let mut migrator =
+            transactional_migrator(&mut conn, "CREATE TABLE second (id integer PRIMARY KEY);");
+        migrator.up(None).unwrap();
+        migrator.into_adapter().commit().unwrap();
+
+        assert!(table_exists(&conn, "first"));
+        assert!(table_exists(&conn, "second"));
+        let applied: i64 = conn
+            .query_row("SELECT COUNT(*) FROM _schemerz", params![], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(applied, 2);
+    }
+
+    #[test]
+    fn test_init_upgrades_bare_table() {
+        let mut conn = build_test_connection();
+        conn.execute("CREATE TABLE _schemerz (id blob PRIMARY KEY)", params![])
+            .unwrap();
+
+        let id = uuid_iter().next().unwrap();
+        let mut migrator = Migrator::new(build_test_adapter(&mut conn));
+        migrator
+            .register(fingerprinted_migration(id, b"v1"))
+            .unwrap();
+        migrator.up(None).unwrap();
+        migrator.validate().unwrap();
+        drop(migrator);
+
+        let version: i64 = conn
+            .query_row("SELECT version FROM _schemerz_layout", params![], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(version, LAYOUT_VERSION);
+        build_test_adapter(&mut conn);
+        let rows: i64 = conn
+            .query_row("SELECT COUNT(*) FROM _schemerz_layout", params![], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(rows, 1);
+    }
+
+    #[test]
+    fn test_init_rejects_newer_layout() {
+        let mut conn = build_test_connection();
+        build_test_adapter(&mut conn);
+        conn.execute(
+            "UPDATE _schemerz_layout SET version = ?1",
+            params![LAYOUT_VERSION + 1],
+        )
+        .unwrap();
+
+        let adapter: RusqliteAdapter<'_, RusqliteError> = RusqliteAdapter::new(&mut conn, None);
+        match adapter.init() {
+            Err(RusqliteError::SqliteFailure(
+                ffi::Error {
+                    code: ErrorCode::Unknown,
+                    ..
+                },
+                Some(message),
+            )) => assert_eq!(
+                message,
+                format!(
+                    "metadata table layout version {} is newer than the latest known, {}",
+                    LAYOUT_VERSION + 1,
+                    LAYOUT_VERSION
+                )
+            ),
+            result => panic!("Expected a newer layout error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_baseline() {
+        let mut conn = build_test_connection();
+        let mut ids = uuid_iter();
+        let id1 = ids.next().unwrap();
+        let id2 = ids.next().unwrap();
+        let baseline = ids.next().unwrap();
+
+        let mut migrator = Migrator::new(build_test_adapter(&mut conn));
+        migrator
+            .register_multiple(
+                vec![
+                    Box::new(TestMigration::new(id1, HashSet::new()))
+                        as Box<dyn RusqliteMigration<Error = RusqliteError>>,
+                    Box::new(TestMigration::new(id2, [id1].into_iter().collect())),
+                    Box::new(
+                        TestMigration::new(baseline, HashSet::new())
+                            .with_replaces([id1, id2].into_iter().collect()),
+                    ),
+                ]
+                .into_iter(),
+            )
+            .unwrap();
+        migrator.up(None).unwrap();
+        migrator.validate().unwrap();
+        migrator.down(None).unwrap();
+
+        let history = migrator.history().unwrap();
+        assert_eq!(
+            history
+                .iter()
+                .map(|entry| (entry.id, entry.direction))
+                .collect::<Vec<_>>(),
+            vec![
+                (baseline, MigrationDirection::Up),
+                (id1, MigrationDirection::Up),
+                (id2, MigrationDirection::Up),
+                (id2, MigrationDirection::Down),
+                (id1, MigrationDirection::Down),
+                (baseline, MigrationDirection::Down),
+            ]
+        );
+    }
+
+    /// Records the number of steps of every plan computed.
+    struct PlanSizes(Arc<Mutex<Vec<usize>>>);
+
+    impl MigrationObserver<Uuid> for PlanSizes {
+        fn plan_computed(&mut self, plan: &[PlannedMigration<Uuid>], _: Duration) {
+            self.0.lock().unwrap().push(plan.len());
+        }
+    }
+
+    #[test]
+    fn test_concurrent_migrators() {
+        let path = std::env::temp_dir().join(format!(
+            "schemerz-rusqlite-concurrency-{}.db",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let mut conn = Connection::open(&path).unwrap();
+        build_test_adapter(&mut conn);
+
+        let mut ids = uuid_iter();
+        let id1 = ids.next().unwrap();
+        let id2 = ids.next().unwrap();
+        let barrier = Arc::new(Barrier::new(2));
+        let plan_sizes = Arc::new(Mutex::new(vec![]));
+        let threads: Vec<_> = (0..2)
+            .map(|_| {
+                let path = path.clone();
+                let barrier = barrier.clone();
+                let plan_sizes = plan_sizes.clone();
+                thread::spawn(move || {
+                    let mut conn = Connection::open(path).unwrap();
+                    let mut migrator = Migrator::new(RusqliteAdapter::new(&mut conn, None))
+                        .with_observer(PlanSizes(plan_sizes));
+                    migrator
+                        .register_multiple(
+                            vec![
+                                Box::new(SqlMigration {
+                                    id: id1,
+                                    dependencies: HashSet::new(),
+                                    sql: "CREATE TABLE first (id integer PRIMARY KEY);",
+                                })
+                                    as Box<dyn RusqliteMigration<Error = RusqliteError>>,
+                                Box::new(SqlMigration {
+                                    id: id2,
+                                    dependencies: [id1].into_iter().collect(),
+                                    sql: "CREATE TABLE second (id integer PRIMARY KEY);",
+                                }),
+                            ]
+                            .into_iter(),
+                        )
+                        .unwrap();
+                    barrier.wait();
+                    migrator.up(None).unwrap();
+                    barrier.wait();
+                    migrator.down(None).unwrap();
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let executed: i64 = conn
+            .query_row("SELECT COUNT(*) FROM _schemerz_history", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        drop(conn);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(executed, 4);
+
+        // Whichever migrator locks the database second reads the migrations
+        // applied or reverted by the first, and has nothing left to do.
+        let mut plan_sizes = plan_sizes.lock().unwrap().clone();
+        plan_sizes.sort();
+        assert_eq!(plan_sizes, vec![0, 0, 2, 2]);
+    }
+
+    #[test]
+    fn test_quoted_table_name() {
+        let mut conn = build_test_connection();
+        // An earlier version's table, which `init` must upgrade.
+        conn.execute(r#"CREATE TABLE "odd ""name"" x" (id blob PRIMARY KEY)"#, [])
+            .unwrap();
+
+        let table_name = TableName::new(r#"odd "name" x"#)
+            .unwrap()
+            .with_schema("main")
+            .unwrap();
+        let adapter = RusqliteAdapter::new(&mut conn, Some(table_name));
+        adapter.init().unwrap();
+
+        let id = uuid_iter().next().unwrap();
+        let mut migrator = Migrator::new(adapter);
+        migrator
+            .register(fingerprinted_migration(id, b"v1"))
+            .unwrap();
+        migrator.up(None).unwrap();
+        migrator.validate().unwrap();
+        assert_eq!(migrator.history().unwrap().len(), 1);
+        drop(migrator);
+
+        assert!(table_exists(&conn, r#"odd "name" x_history"#));
+    }
+
+    #[test]
+    #[cfg(feature = "sql")]
+    fn test_sql_migrations() {
+        let dir =
+            std::env::temp_dir().join(format!("schemerz-rusqlite-sql-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir(&dir).unwrap();
+        let id1 = "4885e8ab-dafa-4d76-a565-2dee8b04ef60";
+        let id2 = "0b0a4ca6-4a5b-4b8e-9fd4-29b0bd4d48ee";
+        for (file_name, sql) in [
+            (
+                format!("{}_users.up.sql", id1),
+                String::from("CREATE TABLE users (email text);"),
+            ),
+            (
+                format!("{}_users.down.sql", id1),
+                String::from("DROP TABLE users;"),
+            ),
+            (
+                format!("{}_index.up.sql", id2),
+                format!(
+                    "-- depends_on: {}\nCREATE INDEX users_email ON users (email);",
+                    id1
+                ),
+            ),
+            (
+                format!("{}_index.down.sql", id2),
+                String::from("DROP INDEX users_email;"),
+            ),
+        ] {
+            std::fs::write(dir.join(file_name), sql).unwrap();
+        }
+        let migrations = load_sql_migrations(&dir).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let mut conn = build_test_connection();
+        let mut migrator = Migrator::new(build_test_adapter(&mut conn));
+        migrator.register_multiple(migrations.into_iter()).unwrap();
+        migrator.up(None).unwrap();
+        migrator.validate().unwrap();
+        migrator.down(None).unwrap();
+        assert_eq!(migrator.history().unwrap().len(), 4);
+        drop(migrator);
+
+        assert!(!table_exists(&conn, "users"));
+    }
 }
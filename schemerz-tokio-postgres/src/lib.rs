@@ -0,0 +1,843 @@
+//! An adapter enabling use of the schemerz schema migration library with
+//! PostgreSQL from async code, using `tokio-postgres`.
+//!
+//! # Examples:
+//!
+//! ```rust
+//! extern crate async_trait;
+//! extern crate schemerz;
+//! extern crate schemerz_macros;
+//! extern crate schemerz_tokio_postgres;
+//! extern crate tokio;
+//! extern crate tokio_postgres;
+//! extern crate uuid;
+//!
+//! use std::collections::{HashMap, HashSet};
+//!
+//! use async_trait::async_trait;
+//! use schemerz::{AsyncMigrator, Migration};
+//! use schemerz_macros::Migration;
+//! use schemerz_tokio_postgres::{
+//!     TokioPostgresAdapter, TokioPostgresAdapterError, TokioPostgresMigration,
+//! };
+//! use tokio_postgres::{NoTls, Transaction};
+//!
+//! #[derive(Migration)]
+//! #[migration(
+//!     id = "4885e8ab-dafa-4d76-a565-2dee8b04ef60",
+//!     description = "An example migration without dependencies."
+//! )]
+//! struct MyExampleMigration;
+//!
+//! #[async_trait]
+//! impl TokioPostgresMigration for MyExampleMigration {
+//!     async fn up(&self, transaction: &Transaction<'_>) -> Result<(), TokioPostgresAdapterError> {
+//!         transaction.execute("CREATE TABLE my_example (id integer PRIMARY KEY);", &[]).await?;
+//!         Ok(())
+//!     }
+//!
+//!     async fn down(&self, transaction: &Transaction<'_>) -> Result<(), TokioPostgresAdapterError> {
+//!         transaction.execute("DROP TABLE my_example;", &[]).await?;
+//!         Ok(())
+//!     }
+//! }
+//!
+//! #[tokio::main(flavor = "current_thread")]
+//! async fn main() {
+//!     let (mut client, connection) = tokio_postgres::connect(
+//!         "postgresql://postgres@localhost",
+//!         NoTls).await.unwrap();
+//!     tokio::spawn(connection);
+//!     client.execute("SET search_path = pg_temp", &[]).await.unwrap();
+//!     let mut adapter = TokioPostgresAdapter::new(&mut client, None);
+//!     adapter.init().await.unwrap();
+//!
+//!     let mut migrator = AsyncMigrator::new(adapter);
+//!
+//!     let migration = Box::new(MyExampleMigration {});
+//!     migrator.register(migration);
+//!     migrator.up(None).await;
+//! }
+//! ```
+#![warn(clippy::all)]
+#![forbid(unsafe_code)]
+
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::time::{Duration, Instant, SystemTime};
+
+use async_trait::async_trait;
+use tokio_postgres::types::{FromSql, ToSql, Type};
+use tokio_postgres::{Client, Error as TokioPostgresError, Row, Transaction};
+use uuid::Uuid;
+
+use schemerz::{AsyncAdapter, HistoryEntry, Migration, MigrationDirection, TableName};
+use schemerz_postgres_layout::{direction_from_sql, direction_to_sql, Tables};
+
+/// Asynchronous PostgreSQL-specific trait for schema migrations.
+#[async_trait]
+pub trait TokioPostgresMigration: Migration<Uuid> + Send + Sync {
+    /// Apply a migration to the database using a transaction.
+    async fn up(&self, _transaction: &Transaction<'_>) -> Result<(), TokioPostgresError> {
+        Ok(())
+    }
+
+    /// Revert a migration to the database using a transaction.
+    ///
+    /// The default implementation does nothing. Migrations that cannot be
+    /// reverted should instead return `false` from
+    /// [`Migration::is_reversible`], so that `AsyncMigrator` refuses to revert
+    /// them.
+    async fn down(&self, _transaction: &Transaction<'_>) -> Result<(), TokioPostgresError> {
+        Ok(())
+    }
+}
+
+pub type TokioPostgresAdapterError = TokioPostgresError;
+
+struct WrappedDirection(MigrationDirection);
+
+impl<'a> FromSql<'a> for WrappedDirection {
+    fn from_sql(ty: &Type, raw: &'a [u8]) -> Result<Self, Box<dyn Error + Sync + Send>> {
+        Ok(WrappedDirection(direction_from_sql(
+            <&str as FromSql>::from_sql(ty, raw)?,
+        )?))
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        <&str as FromSql>::accepts(ty)
+    }
+}
+
+/// Record a migration having been applied or reverted in the history table.
+async fn record_history(
+    tables: &Tables,
+    transaction: &Transaction<'_>,
+    migration: &dyn TokioPostgresMigration,
+    direction: MigrationDirection,
+    executed_at: SystemTime,
+    duration: Duration,
+) -> Result<(), TokioPostgresError> {
+    transaction
+        .execute(
+            tables.insert_history().as_str(),
+            &[
+                &migration.id(),
+                &direction_to_sql(direction),
+                &migration.description(),
+                &executed_at,
+                &(duration.as_millis() as i64),
+            ],
+        )
+        .await?;
+    Ok(())
+}
+
+/// Record a migration as applied in the metadata table, replacing any earlier
+/// record of it, and in the history table, clearing any dirty marker left by
+/// `schemerz_postgres`.
+async fn record_applied(
+    tables: &Tables,
+    transaction: &Transaction<'_>,
+    migration: &dyn TokioPostgresMigration,
+    applied_at: SystemTime,
+    duration: Duration,
+) -> Result<(), TokioPostgresError> {
+    transaction
+        .execute(
+            tables.insert_applied().as_str(),
+            &[
+                &migration.id(),
+                &migration.fingerprint(),
+                &migration.description(),
+                &applied_at,
+                &(duration.as_millis() as i64),
+            ],
+        )
+        .await?;
+    clear_dirty(tables, transaction, migration.id()).await?;
+    record_history(
+        tables,
+        transaction,
+        migration,
+        MigrationDirection::Up,
+        applied_at,
+        duration,
+    )
+    .await
+}
+
+/// Remove a migration from the metadata table, and record it as reverted in
+/// the history table, clearing any dirty marker left by `schemerz_postgres`.
+async fn record_reverted(
+    tables: &Tables,
+    transaction: &Transaction<'_>,
+    migration: &dyn TokioPostgresMigration,
+    reverted_at: SystemTime,
+    duration: Duration,
+) -> Result<(), TokioPostgresError> {
+    transaction
+        .execute(tables.delete_applied().as_str(), &[&migration.id()])
+        .await?;
+    clear_dirty(tables, transaction, migration.id()).await?;
+    record_history(
+        tables,
+        transaction,
+        migration,
+        MigrationDirection::Down,
+        reverted_at,
+        duration,
+    )
+    .await
+}
+
+/// Remove the dirty marker of a migration, if it has one.
+async fn clear_dirty(
+    tables: &Tables,
+    transaction: &Transaction<'_>,
+    id: Uuid,
+) -> Result<(), TokioPostgresError> {
+    transaction
+        .execute(tables.delete_dirty().as_str(), &[&id])
+        .await?;
+    Ok(())
+}
+
+/// Create the metadata tables, or upgrade them from the layout they were
+/// created with to [`schemerz_postgres_layout::LAYOUT_VERSION`].
+async fn upgrade_layout(
+    tables: &Tables,
+    transaction: &mut Transaction<'_>,
+) -> Result<(), TokioPostgresError> {
+    transaction.batch_execute(&tables.create()).await?;
+    let version: i32 = transaction
+        .query_one(tables.select_version().as_str(), &[])
+        .await?
+        .get(0);
+    match tables.upgrade(version) {
+        Some(upgrade) => transaction.batch_execute(&upgrade).await,
+        None => Ok(()),
+    }
+}
+
+/// The connection used by a `TokioPostgresAdapter`.
+enum Conn<'a> {
+    /// Each migration runs in its own transaction.
+    Client(&'a mut Client),
+    /// Every migration runs in a savepoint of this transaction, which is
+    /// committed by `TokioPostgresAdapter::commit`.
+    Transaction(Transaction<'a>),
+}
+
+impl Conn<'_> {
+    async fn execute(
+        &self,
+        query: &str,
+        params: &[&(dyn ToSql + Sync)],
+    ) -> Result<u64, TokioPostgresError> {
+        match self {
+            Conn::Client(client) => client.execute(query, params).await,
+            Conn::Transaction(transaction) => transaction.execute(query, params).await,
+        }
+    }
+
+    async fn query(
+        &self,
+        query: &str,
+        params: &[&(dyn ToSql + Sync)],
+    ) -> Result<Vec<Row>, TokioPostgresError> {
+        match self {
+            Conn::Client(client) => client.query(query, params).await,
+            Conn::Transaction(transaction) => transaction.query(query, params).await,
+        }
+    }
+
+    /// Begin a transaction, or a savepoint if every migration runs in a single
+    /// transaction.
+    async fn transaction(&mut self) -> Result<Transaction<'_>, TokioPostgresError> {
+        match self {
+            Conn::Client(client) => client.transaction().await,
+            Conn::Transaction(transaction) => transaction.transaction().await,
+        }
+    }
+}
+
+/// Asynchronous adapter between schemerz and PostgreSQL.
+pub struct TokioPostgresAdapter<'a> {
+    conn: Conn<'a>,
+    tables: Tables,
+    lock_timeout: Option<Duration>,
+}
+
+impl<'a> TokioPostgresAdapter<'a> {
+    /// Construct an asynchronous PostgreSQL schemerz adapter.
+    ///
+    /// `table_name` specifies the name of the table that schemerz will use
+    /// for storing metadata about applied migrations, optionally qualified by
+    /// its schema. If `None`, a default will be used. The history of applied
+    /// and reverted migrations is stored in a second table in the same schema,
+    /// named by appending `_history` to `table_name`. The version of the
+    /// tables' layout is stored in a third, named by appending `_layout`.
+    ///
+    /// The tables are the same as those used by `schemerz_postgres`, so the
+    /// two adapters can be used interchangeably on the same database. This
+    /// includes the `_dirty` table: only `schemerz_postgres` runs the
+    /// non-transactional migrations recorded there, but applying or reverting
+    /// a migration with either adapter clears its dirty marker.
+    pub fn new(conn: &'a mut Client, table_name: Option<TableName>) -> TokioPostgresAdapter<'a> {
+        Self::with_conn(Conn::Client(conn), table_name)
+    }
+
+    /// Construct an asynchronous PostgreSQL schemerz adapter that applies and
+    /// reverts every migration inside a single transaction.
+    ///
+    /// Nothing is persisted until [`TokioPostgresAdapter::commit`] is called.
+    /// Each migration runs in a savepoint of the outer transaction.
+    /// `table_name` is handled as for [`TokioPostgresAdapter::new`].
+    pub async fn new_transactional(
+        conn: &'a mut Client,
+        table_name: Option<TableName>,
+    ) -> Result<TokioPostgresAdapter<'a>, TokioPostgresError> {
+        Ok(Self::with_conn(
+            Conn::Transaction(conn.transaction().await?),
+            table_name,
+        ))
+    }
+
+    fn with_conn(conn: Conn<'a>, table_name: Option<TableName>) -> TokioPostgresAdapter<'a> {
+        let metadata = table_name.unwrap_or_default();
+        TokioPostgresAdapter {
+            conn,
+            tables: Tables::new(metadata),
+            lock_timeout: None,
+        }
+    }
+
+    /// Give up acquiring the migration lock after `timeout`, failing with a
+    /// `lock_not_available` error, instead of waiting indefinitely.
+    ///
+    /// While migrating, the adapter holds an advisory lock keyed on the
+    /// metadata table name, so that migrators sharing that table run one after
+    /// the other.
+    pub fn with_lock_timeout(mut self, timeout: Duration) -> Self {
+        self.lock_timeout = Some(timeout);
+        self
+    }
+
+    /// Commit the transaction spanning every migration applied or reverted by
+    /// an adapter constructed with [`TokioPostgresAdapter::new_transactional`].
+    ///
+    /// For adapters constructed with [`TokioPostgresAdapter::new`], each
+    /// migration has already been committed, and this does nothing.
+    pub async fn commit(self) -> Result<(), TokioPostgresError> {
+        match self.conn {
+            Conn::Client(_) => Ok(()),
+            Conn::Transaction(transaction) => transaction.commit().await,
+        }
+    }
+
+    /// Initialize the schemerz metadata schema. This must be called before
+    /// using `AsyncMigrator` with this adapter. This is safe to call multiple
+    /// times.
+    ///
+    /// If the metadata table name is qualified by a schema that does not exist,
+    /// the schema is created. Metadata tables created by earlier versions of
+    /// schemerz are upgraded in place. Tables laid out by a later version,
+    /// which this one does not know how to use, are rejected.
+    pub async fn init(&mut self) -> Result<(), TokioPostgresError> {
+        let mut trans = self.conn.transaction().await?;
+        if let Some(schema) = self.tables.metadata.quoted_schema() {
+            trans
+                .execute(
+                    format!("CREATE SCHEMA IF NOT EXISTS {}", schema).as_str(),
+                    &[],
+                )
+                .await?;
+        }
+        upgrade_layout(&self.tables, &mut trans).await?;
+        trans.commit().await
+    }
+}
+
+impl AsyncAdapter<Uuid> for TokioPostgresAdapter<'_> {
+    type MigrationType = Box<dyn TokioPostgresMigration>;
+
+    type Error = TokioPostgresAdapterError;
+
+    async fn applied_migrations(&mut self) -> Result<HashSet<Uuid>, Self::Error> {
+        let rows = self
+            .conn
+            .query(self.tables.select_applied().as_str(), &[])
+            .await?;
+        Ok(rows.iter().map(|row| row.get(0)).collect())
+    }
+
+    async fn applied_fingerprints(&mut self) -> Result<HashMap<Uuid, Vec<u8>>, Self::Error> {
+        let rows = self
+            .conn
+            .query(self.tables.select_fingerprints().as_str(), &[])
+            .await?;
+        Ok(rows.iter().map(|row| (row.get(0), row.get(1))).collect())
+    }
+
+    async fn history(&mut self) -> Result<Vec<HistoryEntry<Uuid>>, Self::Error> {
+        let rows = self
+            .conn
+            .query(self.tables.select_history().as_str(), &[])
+            .await?;
+        Ok(rows
+            .iter()
+            .map(|row| HistoryEntry {
+                id: row.get(0),
+                description: row.get::<_, Option<String>>(1).unwrap_or_default(),
+                direction: row.get::<_, WrappedDirection>(2).0,
+                executed_at: row.get(3),
+                duration: Duration::from_millis(row.get::<_, i64>(4) as u64),
+            })
+            .collect())
+    }
+
+    async fn apply_migration(
+        &mut self,
+        migration: &Self::MigrationType,
+    ) -> Result<(), Self::Error> {
+        let trans = self.conn.transaction().await?;
+        let applied_at = SystemTime::now();
+        let start = Instant::now();
+        migration.up(&trans).await?;
+        record_applied(
+            &self.tables,
+            &trans,
+            migration.as_ref(),
+            applied_at,
+            start.elapsed(),
+        )
+        .await?;
+        trans.commit().await
+    }
+
+    async fn revert_migration(
+        &mut self,
+        migration: &Self::MigrationType,
+    ) -> Result<(), Self::Error> {
+        let trans = self.conn.transaction().await?;
+        let reverted_at = SystemTime::now();
+        let start = Instant::now();
+        migration.down(&trans).await?;
+        record_reverted(
+            &self.tables,
+            &trans,
+            migration.as_ref(),
+            reverted_at,
+            start.elapsed(),
+        )
+        .await?;
+        trans.commit().await
+    }
+
+    async fn reapply_migration(
+        &mut self,
+        migration: &Self::MigrationType,
+    ) -> Result<(), Self::Error> {
+        // Recording the migration as applied replaces the earlier record of it.
+        self.apply_migration(migration).await
+    }
+
+    async fn mark_applied(&mut self, migration: &Self::MigrationType) -> Result<(), Self::Error> {
+        let trans = self.conn.transaction().await?;
+        record_applied(
+            &self.tables,
+            &trans,
+            migration.as_ref(),
+            SystemTime::now(),
+            Duration::ZERO,
+        )
+        .await?;
+        trans.commit().await
+    }
+
+    async fn unmark_applied(&mut self, migration: &Self::MigrationType) -> Result<(), Self::Error> {
+        let trans = self.conn.transaction().await?;
+        record_reverted(
+            &self.tables,
+            &trans,
+            migration.as_ref(),
+            SystemTime::now(),
+            Duration::ZERO,
+        )
+        .await?;
+        trans.commit().await
+    }
+
+    async fn lock(&mut self) -> Result<(), Self::Error> {
+        // Transactional adapters only release the lock when their transaction
+        // ends, so that other migrators see the migrations it committed.
+        let lock = self.tables.lock(matches!(self.conn, Conn::Transaction(_)));
+
+        let trans = self.conn.transaction().await?;
+        let previous_timeout: String = trans
+            .query_one("SELECT current_setting('lock_timeout')", &[])
+            .await?
+            .get(0);
+        if let Some(timeout) = self.lock_timeout {
+            // A timeout of zero would disable the timeout.
+            let timeout = format!("{}ms", timeout.as_millis().max(1));
+            trans
+                .execute("SELECT set_config('lock_timeout', $1, true)", &[&timeout])
+                .await?;
+        }
+        trans
+            .execute(lock.as_str(), &[&self.tables.metadata.to_string()])
+            .await?;
+        trans
+            .execute(
+                "SELECT set_config('lock_timeout', $1, true)",
+                &[&previous_timeout],
+            )
+            .await?;
+        trans.commit().await
+    }
+
+    async fn unlock(&mut self) -> Result<(), Self::Error> {
+        if let Conn::Client(_) = self.conn {
+            self.conn
+                .execute(
+                    self.tables.unlock().as_str(),
+                    &[&self.tables.metadata.to_string()],
+                )
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use schemerz::testing::*;
+    use schemerz::{AsyncMigrator, MigratorError};
+    use schemerz_postgres_layout::LAYOUT_VERSION;
+    use tokio_postgres::error::SqlState;
+    use tokio_postgres::NoTls;
+
+    #[async_trait]
+    impl TokioPostgresMigration for TestMigration<Uuid> {}
+
+    async fn build_test_connection() -> Client {
+        let (client, connection) =
+            tokio_postgres::connect("postgresql://postgres@localhost", NoTls)
+                .await
+                .unwrap();
+        tokio::spawn(connection);
+        client
+            .execute("SET search_path = pg_temp", &[])
+            .await
+            .unwrap();
+        client
+    }
+
+    async fn build_test_adapter(conn: &mut Client) -> TokioPostgresAdapter<'_> {
+        let mut adapter = TokioPostgresAdapter::new(conn, None);
+        adapter.init().await.unwrap();
+        adapter
+    }
+
+    fn uuid_iter() -> impl Iterator<Item = Uuid> {
+        (0..).map(|v| Uuid::from_fields(v as u32, v, v, &[0; 8]))
+    }
+
+    fn mock(id: Uuid, dependencies: &[Uuid]) -> Box<dyn TokioPostgresMigration> {
+        Box::new(TestMigration::new(
+            id,
+            dependencies.iter().cloned().collect(),
+        ))
+    }
+
+    #[tokio::test]
+    async fn test_up_and_down() {
+        let mut conn = build_test_connection().await;
+        let mut ids = uuid_iter();
+        let id1 = ids.next().unwrap();
+        let id2 = ids.next().unwrap();
+        let id3 = ids.next().unwrap();
+
+        let mut migrator = AsyncMigrator::new(build_test_adapter(&mut conn).await);
+        migrator
+            .register_multiple(
+                vec![mock(id1, &[]), mock(id2, &[id1]), mock(id3, &[id2])].into_iter(),
+            )
+            .unwrap();
+
+        migrator.up(Some(id2)).await.unwrap();
+        let status = migrator.status().await.unwrap();
+        assert_eq!(
+            status.applied.iter().map(|m| m.id).collect::<Vec<_>>(),
+            vec![id1, id2]
+        );
+        assert_eq!(
+            status.pending.iter().map(|m| m.id).collect::<Vec<_>>(),
+            vec![id3]
+        );
+
+        migrator.up(None).await.unwrap();
+        migrator.down(Some(id1)).await.unwrap();
+
+        let history = migrator.history().await.unwrap();
+        assert_eq!(
+            history
+                .iter()
+                .map(|entry| (entry.id, entry.direction))
+                .collect::<Vec<_>>(),
+            vec![
+                (id1, MigrationDirection::Up),
+                (id2, MigrationDirection::Up),
+                (id3, MigrationDirection::Up),
+                (id3, MigrationDirection::Down),
+                (id2, MigrationDirection::Down),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fingerprint_drift() {
+        let mut conn = build_test_connection().await;
+        let id = uuid_iter().next().unwrap();
+        let fingerprinted = |fingerprint: &[u8]| -> Box<dyn TokioPostgresMigration> {
+            Box::new(TestMigration::new(id, HashSet::new()).with_fingerprint(fingerprint.to_vec()))
+        };
+
+        let mut migrator = AsyncMigrator::new(build_test_adapter(&mut conn).await);
+        migrator.register(fingerprinted(b"v1")).unwrap();
+        migrator.up(None).await.unwrap();
+        drop(migrator);
+
+        let mut migrator = AsyncMigrator::new(build_test_adapter(&mut conn).await);
+        migrator.register(fingerprinted(b"v2")).unwrap();
+        assert!(matches!(
+            migrator.up(None).await,
+            Err(MigratorError::Drift { id: drifted, .. }) if drifted == id
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_repeatable() {
+        let mut conn = build_test_connection().await;
+        let id = uuid_iter().next().unwrap();
+        let repeatable = |fingerprint: &[u8]| -> Box<dyn TokioPostgresMigration> {
+            Box::new(
+                TestMigration::new(id, HashSet::new())
+                    .with_fingerprint(fingerprint.to_vec())
+                    .repeatable(),
+            )
+        };
+
+        for fingerprint in [b"v1", b"v1", b"v2"] {
+            let mut migrator = AsyncMigrator::new(build_test_adapter(&mut conn).await);
+            migrator.register(repeatable(fingerprint)).unwrap();
+            migrator.up(None).await.unwrap();
+            assert!(migrator.status().await.unwrap().outdated.is_empty());
+        }
+
+        let mut adapter = build_test_adapter(&mut conn).await;
+        assert_eq!(
+            adapter.applied_fingerprints().await.unwrap()[&id],
+            b"v2".to_vec()
+        );
+        let history = adapter.history().await.unwrap();
+        assert_eq!(
+            history
+                .iter()
+                .map(|entry| (entry.id, entry.direction))
+                .collect::<Vec<_>>(),
+            vec![(id, MigrationDirection::Up), (id, MigrationDirection::Up)]
+        );
+    }
+
+    struct SqlMigration {
+        id: Uuid,
+        sql: &'static str,
+    }
+
+    impl Migration<Uuid> for SqlMigration {
+        fn id(&self) -> Uuid {
+            self.id
+        }
+
+        fn dependencies(&self) -> HashSet<Uuid> {
+            HashSet::new()
+        }
+
+        fn description(&self) -> &'static str {
+            "SQL Test Migration"
+        }
+    }
+
+    #[async_trait]
+    impl TokioPostgresMigration for SqlMigration {
+        async fn up(&self, transaction: &Transaction<'_>) -> Result<(), TokioPostgresError> {
+            transaction.batch_execute(self.sql).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_transactional_rollback() {
+        let mut conn = build_test_connection().await;
+        let mut ids = uuid_iter();
+
+        let mut adapter = TokioPostgresAdapter::new_transactional(&mut conn, None)
+            .await
+            .unwrap();
+        adapter.init().await.unwrap();
+        let mut migrator = AsyncMigrator::new(adapter);
+        migrator
+            .register_multiple(
+                vec![
+                    Box::new(SqlMigration {
+                        id: ids.next().unwrap(),
+                        sql: "CREATE TABLE first (id integer PRIMARY KEY);",
+                    }) as Box<dyn TokioPostgresMigration>,
+                    Box::new(SqlMigration {
+                        id: ids.next().unwrap(),
+                        sql: "NOT VALID SQL;",
+                    }),
+                ]
+                .into_iter(),
+            )
+            .unwrap();
+        assert!(migrator.up(None).await.is_err());
+        drop(migrator);
+
+        let exists: bool = conn
+            .query_one("SELECT to_regclass('pg_temp._schemerz') IS NOT NULL", &[])
+            .await
+            .unwrap()
+            .get(0);
+        assert!(!exists);
+    }
+
+    #[tokio::test]
+    async fn test_init_upgrades_bare_table() {
+        let mut conn = build_test_connection().await;
+        conn.execute("CREATE TABLE _schemerz (id uuid PRIMARY KEY)", &[])
+            .await
+            .unwrap();
+
+        build_test_adapter(&mut conn).await;
+        let version: i32 = conn
+            .query_one("SELECT version FROM _schemerz_layout", &[])
+            .await
+            .unwrap()
+            .get(0);
+        assert_eq!(version, LAYOUT_VERSION);
+        conn.execute("SELECT id, direction FROM _schemerz_dirty", &[])
+            .await
+            .unwrap();
+
+        let id = uuid_iter().next().unwrap();
+        let mut migrator = AsyncMigrator::new(build_test_adapter(&mut conn).await);
+        migrator.register(mock(id, &[])).unwrap();
+        migrator.up(None).await.unwrap();
+        assert_eq!(migrator.history().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_clears_dirty() {
+        let mut conn = build_test_connection().await;
+        let id = uuid_iter().next().unwrap();
+        build_test_adapter(&mut conn).await;
+        // A marker left by a non-transactional migration that failed midway
+        // when run by `schemerz_postgres`.
+        conn.execute(
+            "INSERT INTO _schemerz_dirty (id, direction, started_at) VALUES ($1, 'up', now())",
+            &[&id],
+        )
+        .await
+        .unwrap();
+
+        let mut migrator = AsyncMigrator::new(build_test_adapter(&mut conn).await);
+        migrator.register(mock(id, &[])).unwrap();
+        migrator.up(None).await.unwrap();
+        drop(migrator);
+
+        let dirty: i64 = conn
+            .query_one("SELECT count(*) FROM _schemerz_dirty", &[])
+            .await
+            .unwrap()
+            .get(0);
+        assert_eq!(dirty, 0);
+    }
+
+    #[tokio::test]
+    async fn test_baseline() {
+        let mut conn = build_test_connection().await;
+        let mut ids = uuid_iter();
+        let id1 = ids.next().unwrap();
+        let id2 = ids.next().unwrap();
+        let baseline = ids.next().unwrap();
+
+        let mut migrator = AsyncMigrator::new(build_test_adapter(&mut conn).await);
+        migrator
+            .register_multiple(
+                vec![
+                    Box::new(TestMigration::new(id1, HashSet::new()))
+                        as Box<dyn TokioPostgresMigration>,
+                    Box::new(TestMigration::new(id2, [id1].into_iter().collect())),
+                    Box::new(
+                        TestMigration::new(baseline, HashSet::new())
+                            .with_replaces([id1, id2].into_iter().collect()),
+                    ),
+                ]
+                .into_iter(),
+            )
+            .unwrap();
+        migrator.up(None).await.unwrap();
+        migrator.validate().await.unwrap();
+        migrator.down(None).await.unwrap();
+
+        let history = migrator.history().await.unwrap();
+        assert_eq!(
+            history
+                .iter()
+                .map(|entry| (entry.id, entry.direction))
+                .collect::<Vec<_>>(),
+            vec![
+                (baseline, MigrationDirection::Up),
+                (id1, MigrationDirection::Up),
+                (id2, MigrationDirection::Up),
+                (id2, MigrationDirection::Down),
+                (id1, MigrationDirection::Down),
+                (baseline, MigrationDirection::Down),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_lock_timeout() {
+        let table = "_schemerz_lock_timeout_test";
+        let mut holder_conn = build_test_connection().await;
+        let mut holder =
+            TokioPostgresAdapter::new(&mut holder_conn, Some(TableName::new(table).unwrap()));
+        holder.lock().await.unwrap();
+
+        let mut conn = build_test_connection().await;
+        let mut adapter =
+            TokioPostgresAdapter::new(&mut conn, Some(TableName::new(table).unwrap()))
+                .with_lock_timeout(Duration::from_millis(100));
+        adapter.init().await.unwrap();
+        let mut migrator = AsyncMigrator::new(adapter);
+        migrator
+            .register(mock(uuid_iter().next().unwrap(), &[]))
+            .unwrap();
+
+        match migrator.up(None).await {
+            Err(MigratorError::Adapter(e)) => {
+                assert_eq!(e.code(), Some(&SqlState::LOCK_NOT_AVAILABLE))
+            }
+            result => panic!("Expected a lock timeout, got {:?}", result.map(|_| ())),
+        }
+
+        holder.unlock().await.unwrap();
+        migrator.up(None).await.unwrap();
+        assert_eq!(migrator.status().await.unwrap().applied.len(), 1);
+    }
+}
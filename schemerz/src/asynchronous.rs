@@ -0,0 +1,488 @@
+//! Asynchronous counterparts of [`Adapter`](crate::Adapter) and
+//! [`Migrator`](crate::Migrator), for backends whose clients are driven by an
+//! async runtime.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use std::future::Future;
+use std::hash::Hash;
+use std::slice;
+use std::sync::Arc;
+use std::time::Instant;
+
+use log::info;
+
+use crate::graph::{Action, AppliedState, PlanStep, Registry};
+use crate::{
+    HistoryEntry, Migration, MigrationDirection, MigrationGraph, MigrationObserver,
+    MigrationStatus, MigratorError, PlannedMigration,
+};
+
+/// Trait necessary to adapt schemerz's migration management to a stateful
+/// backend that is accessed asynchronously.
+///
+/// This mirrors [`Adapter`](crate::Adapter), with every method returning a
+/// future. Adapters and their migrations must be shareable across threads, so
+/// that those futures can be sent to other threads.
+pub trait AsyncAdapter<I>: Send {
+    /// Type migrations must implement for this adapter.
+    type MigrationType: Migration<I> + Sync;
+
+    /// Type of errors returned by this adapter.
+    type Error: std::error::Error + 'static;
+
+    /// Returns the set of IDs for migrations that have been applied.
+    fn applied_migrations(
+        &mut self,
+    ) -> impl Future<Output = Result<HashSet<I>, Self::Error>> + Send;
+
+    /// Returns the fingerprints recorded for applied migrations, keyed by
+    /// migration ID. Migrations applied without a fingerprint are omitted.
+    ///
+    /// The default implementation records no fingerprints.
+    fn applied_fingerprints(
+        &mut self,
+    ) -> impl Future<Output = Result<HashMap<I, Vec<u8>>, Self::Error>> + Send {
+        async { Ok(HashMap::new()) }
+    }
+
+    /// Returns every recorded application and reversion of a migration, oldest
+    /// first.
+    ///
+    /// The default implementation records no history.
+    fn history(
+        &mut self,
+    ) -> impl Future<Output = Result<Vec<HistoryEntry<I>>, Self::Error>> + Send {
+        async { Ok(vec![]) }
+    }
+
+    /// Apply a single migration.
+    fn apply_migration(
+        &mut self,
+        _: &Self::MigrationType,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send;
+
+    /// Revert a single migration.
+    fn revert_migration(
+        &mut self,
+        _: &Self::MigrationType,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send;
+
+    /// Apply a single repeatable migration that is already applied, because
+    /// it has changed since.
+    ///
+    /// See [`Adapter::reapply_migration`](crate::Adapter::reapply_migration).
+    /// The default implementation records the migration as not applied, then
+    /// applies it.
+    fn reapply_migration(
+        &mut self,
+        migration: &Self::MigrationType,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        async move {
+            self.unmark_applied(migration).await?;
+            self.apply_migration(migration).await
+        }
+    }
+
+    /// Record a single migration as applied, without applying it.
+    ///
+    /// See [`Adapter::mark_applied`](crate::Adapter::mark_applied).
+    fn mark_applied(
+        &mut self,
+        _: &Self::MigrationType,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send;
+
+    /// Record a single migration as not applied, without reverting it.
+    ///
+    /// See [`Adapter::unmark_applied`](crate::Adapter::unmark_applied).
+    fn unmark_applied(
+        &mut self,
+        _: &Self::MigrationType,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send;
+
+    /// Acquire exclusive access to the backend for migrating, waiting for any
+    /// other migrator that holds it.
+    ///
+    /// See [`Adapter::lock`](crate::Adapter::lock). The default implementation
+    /// does nothing.
+    fn lock(&mut self) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        async { Ok(()) }
+    }
+
+    /// Release the access acquired by [`AsyncAdapter::lock`].
+    ///
+    /// The default implementation does nothing.
+    fn unlock(&mut self) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        async { Ok(()) }
+    }
+}
+
+/// Asynchronous counterpart of [`Migrator`](crate::Migrator).
+///
+/// Migrations are registered, ordered and validated exactly as they are by
+/// `Migrator`; only interaction with the adapter is asynchronous.
+pub struct AsyncMigrator<I, T: AsyncAdapter<I>> {
+    adapter: T,
+    registry: Registry<I, T::MigrationType>,
+    observer: Option<Box<dyn MigrationObserver<I> + Send>>,
+}
+
+impl<I, T> AsyncMigrator<I, T>
+where
+    I: Hash + Display + Eq + Clone,
+    T: AsyncAdapter<I>,
+{
+    /// Create an `AsyncMigrator` using the given `AsyncAdapter`.
+    pub fn new(adapter: T) -> AsyncMigrator<I, T> {
+        AsyncMigrator {
+            adapter,
+            registry: Registry::new(),
+            observer: None,
+        }
+    }
+
+    /// Create an `AsyncMigrator` using the given `AsyncAdapter` and an already
+    /// built dependency graph.
+    ///
+    /// See [`Migrator::with_graph`](crate::Migrator::with_graph).
+    pub fn with_graph(
+        adapter: T,
+        graph: impl Into<Arc<MigrationGraph<I, T::MigrationType>>>,
+    ) -> AsyncMigrator<I, T> {
+        AsyncMigrator {
+            adapter,
+            registry: Registry::Finalized(graph.into()),
+            observer: None,
+        }
+    }
+
+    /// Attach an observer that is notified as migrations are run by
+    /// [`AsyncMigrator::up`] and [`AsyncMigrator::down`], replacing any
+    /// previously attached observer.
+    pub fn with_observer(mut self, observer: impl MigrationObserver<I> + Send + 'static) -> Self {
+        self.observer = Some(Box::new(observer));
+        self
+    }
+
+    /// Returns the attached observer, if any.
+    fn observer(&mut self) -> Option<&mut dyn MigrationObserver<I>> {
+        self.observer.as_deref_mut().map(|observer| observer as _)
+    }
+
+    /// Consume this `AsyncMigrator`, returning its `AsyncAdapter`.
+    pub fn into_adapter(self) -> T {
+        self.adapter
+    }
+
+    /// Register a migration into the dependency graph.
+    pub fn register(
+        &mut self,
+        migration: T::MigrationType,
+    ) -> Result<(), MigratorError<I, T::Error>> {
+        self.registry.register(migration)
+    }
+
+    /// Register multiple migrations into the dependency graph.
+    pub fn register_multiple(
+        &mut self,
+        migrations: impl Iterator<Item = T::MigrationType>,
+    ) -> Result<(), MigratorError<I, T::Error>> {
+        for migration in migrations {
+            self.register(migration)?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the dependency graph of the registered migrations.
+    ///
+    /// See [`Migrator::graph`](crate::Migrator::graph).
+    #[allow(clippy::type_complexity)]
+    pub fn graph(
+        &mut self,
+    ) -> Result<Arc<MigrationGraph<I, T::MigrationType>>, MigratorError<I, T::Error>> {
+        self.registry.graph()
+    }
+
+    /// Returns the applied state recorded by the adapter, after checking that
+    /// it is consistent with the dependency graph and that no applied
+    /// migration has changed since it was applied, unless it is repeatable.
+    async fn checked_applied_migrations(
+        &mut self,
+        graph: &MigrationGraph<I, T::MigrationType>,
+    ) -> Result<AppliedState<I>, MigratorError<I, T::Error>> {
+        let applied = AppliedState {
+            migrations: self.adapter.applied_migrations().await?,
+            fingerprints: self.adapter.applied_fingerprints().await?,
+        };
+        graph.check(&applied.migrations, &applied.fingerprints)?;
+
+        Ok(applied)
+    }
+
+    /// Check that the migrations the adapter has recorded as applied are
+    /// consistent with the registered migrations.
+    ///
+    /// See [`Migrator::validate`](crate::Migrator::validate).
+    pub async fn validate(&mut self) -> Result<(), MigratorError<I, T::Error>> {
+        let graph = self.graph()?;
+        self.checked_applied_migrations(&graph).await.map(|_| ())
+    }
+
+    /// Compute the migrations that would be run in `direction` for the given
+    /// targets, in order.
+    async fn plan(
+        &mut self,
+        graph: &MigrationGraph<I, T::MigrationType>,
+        targets: Option<&[I]>,
+        direction: MigrationDirection,
+    ) -> Result<Vec<PlanStep>, MigratorError<I, T::Error>> {
+        let applied = self.checked_applied_migrations(graph).await?;
+        graph.plan(targets, direction, &applied)
+    }
+
+    /// Returns the migrations that [`AsyncMigrator::up`] would apply for the
+    /// same target, in the order they would be applied. No migrations are run.
+    pub async fn plan_up(
+        &mut self,
+        to: Option<I>,
+    ) -> Result<Vec<PlannedMigration<I>>, MigratorError<I, T::Error>> {
+        let graph = self.graph()?;
+        let steps = self
+            .plan(
+                &graph,
+                to.as_ref().map(slice::from_ref),
+                MigrationDirection::Up,
+            )
+            .await?;
+        Ok(graph.describe_plan(&steps, MigrationDirection::Up))
+    }
+
+    /// Returns the migrations that [`AsyncMigrator::down`] would revert for the
+    /// same target, in the order they would be reverted. No migrations are run.
+    pub async fn plan_down(
+        &mut self,
+        to: Option<I>,
+    ) -> Result<Vec<PlannedMigration<I>>, MigratorError<I, T::Error>> {
+        let graph = self.graph()?;
+        let steps = self
+            .plan(
+                &graph,
+                to.as_ref().map(slice::from_ref),
+                MigrationDirection::Down,
+            )
+            .await?;
+        Ok(graph.describe_plan(&steps, MigrationDirection::Down))
+    }
+
+    /// Returns the migrations that [`AsyncMigrator::up_many`] would apply for
+    /// the same targets, in the order they would be applied. No migrations are
+    /// run.
+    pub async fn plan_up_many(
+        &mut self,
+        targets: impl IntoIterator<Item = I>,
+    ) -> Result<Vec<PlannedMigration<I>>, MigratorError<I, T::Error>> {
+        let targets: Vec<_> = targets.into_iter().collect();
+        let graph = self.graph()?;
+        let steps = self
+            .plan(&graph, Some(&targets), MigrationDirection::Up)
+            .await?;
+        Ok(graph.describe_plan(&steps, MigrationDirection::Up))
+    }
+
+    /// Returns the migrations that [`AsyncMigrator::down_many`] would revert
+    /// for the same targets, in the order they would be reverted. No migrations
+    /// are run.
+    pub async fn plan_down_many(
+        &mut self,
+        targets: impl IntoIterator<Item = I>,
+    ) -> Result<Vec<PlannedMigration<I>>, MigratorError<I, T::Error>> {
+        let targets: Vec<_> = targets.into_iter().collect();
+        let graph = self.graph()?;
+        let steps = self
+            .plan(&graph, Some(&targets), MigrationDirection::Down)
+            .await?;
+        Ok(graph.describe_plan(&steps, MigrationDirection::Down))
+    }
+
+    /// Returns every application and reversion of a migration recorded by the
+    /// adapter, oldest first.
+    pub async fn history(&mut self) -> Result<Vec<HistoryEntry<I>>, MigratorError<I, T::Error>> {
+        Ok(self.adapter.history().await?)
+    }
+
+    /// Compare the registered migrations against the migrations the adapter
+    /// has recorded as applied.
+    pub async fn status(&mut self) -> Result<MigrationStatus<I>, MigratorError<I, T::Error>> {
+        let graph = self.graph()?;
+        let applied_migrations = self.adapter.applied_migrations().await?;
+        let applied_fingerprints = self.adapter.applied_fingerprints().await?;
+        graph
+            .status(&applied_migrations, &applied_fingerprints)
+            .map_err(MigratorError::Dependency)
+    }
+
+    /// If `show_status` is `true`, return the migrations the adapter has
+    /// recorded as applied.
+    async fn rendering_state(
+        &mut self,
+        show_status: bool,
+    ) -> Result<Option<HashSet<I>>, MigratorError<I, T::Error>> {
+        Ok(if show_status {
+            Some(self.adapter.applied_migrations().await?)
+        } else {
+            None
+        })
+    }
+
+    /// Render the dependency graph of the registered migrations in the Graphviz
+    /// DOT language, with each node labeled by its migration's ID and
+    /// description.
+    ///
+    /// If `show_status` is `true`, nodes are colored by whether the adapter has
+    /// recorded them as applied or pending.
+    pub async fn to_dot(
+        &mut self,
+        show_status: bool,
+    ) -> Result<String, MigratorError<I, T::Error>> {
+        let graph = self.graph()?;
+        let applied_migrations = self.rendering_state(show_status).await?;
+        Ok(graph.to_dot(applied_migrations.as_ref()))
+    }
+
+    /// Render the dependency graph of the registered migrations as a Mermaid
+    /// flowchart. See [`AsyncMigrator::to_dot`].
+    pub async fn to_mermaid(
+        &mut self,
+        show_status: bool,
+    ) -> Result<String, MigratorError<I, T::Error>> {
+        let graph = self.graph()?;
+        let applied_migrations = self.rendering_state(show_status).await?;
+        Ok(graph.to_mermaid(applied_migrations.as_ref()))
+    }
+
+    /// Record the specified migration as applied without running it. See
+    /// [`Migrator::mark_applied`](crate::Migrator::mark_applied).
+    pub async fn mark_applied(&mut self, id: I) -> Result<(), MigratorError<I, T::Error>> {
+        self.adapter.lock().await?;
+        let result = self.mark_applied_locked(&id).await;
+        let unlocked = self.adapter.unlock().await;
+
+        result?;
+        Ok(unlocked?)
+    }
+
+    async fn mark_applied_locked(&mut self, id: &I) -> Result<(), MigratorError<I, T::Error>> {
+        let graph = self.graph()?;
+        let applied = self.checked_applied_migrations(&graph).await?;
+        let migrations = graph
+            .to_mark_applied(id, &applied.migrations)
+            .map_err(MigratorError::Dependency)?;
+        for migration in migrations {
+            info!("Recording migration {} as applied", migration.id());
+            self.adapter.mark_applied(migration).await?;
+        }
+        Ok(())
+    }
+
+    /// Run the migrations planned in `direction` for the given targets while
+    /// holding the adapter's lock.
+    async fn migrate(
+        &mut self,
+        targets: Option<&[I]>,
+        direction: MigrationDirection,
+    ) -> Result<(), MigratorError<I, T::Error>> {
+        self.adapter.lock().await?;
+        let result = self.migrate_locked(targets, direction).await;
+        let unlocked = self.adapter.unlock().await;
+
+        result?;
+        Ok(unlocked?)
+    }
+
+    /// Run the migrations planned in `direction` for the given targets,
+    /// notifying the observer, if any, along the way.
+    async fn migrate_locked(
+        &mut self,
+        targets: Option<&[I]>,
+        direction: MigrationDirection,
+    ) -> Result<(), MigratorError<I, T::Error>> {
+        let start = Instant::now();
+        let graph = self.graph()?;
+        let plan = self.plan(&graph, targets, direction).await?;
+        graph.plan_computed(&plan, direction, start, self.observer());
+
+        for step in &plan {
+            let running = graph.start_step(step, direction, self.observer());
+            let result = match running.action {
+                Action::Apply => self.adapter.apply_migration(running.migration).await,
+                Action::Reapply => self.adapter.reapply_migration(running.migration).await,
+                Action::MarkApplied => self.adapter.mark_applied(running.migration).await,
+                Action::Revert => self.adapter.revert_migration(running.migration).await,
+                Action::UnmarkApplied => self.adapter.unmark_applied(running.migration).await,
+            };
+            running.finish(result, self.observer())?;
+        }
+
+        Ok(())
+    }
+
+    /// Apply migrations as necessary to so that the specified migration is
+    /// applied (inclusive).
+    ///
+    /// If `to` is `None`, apply all registered migrations.
+    pub async fn up(&mut self, to: Option<I>) -> Result<(), MigratorError<I, T::Error>> {
+        if let Some(to) = &to {
+            info!("Migrating up to target: {}", to);
+        } else {
+            info!("Migrating everything");
+        }
+
+        self.migrate(to.as_ref().map(slice::from_ref), MigrationDirection::Up)
+            .await
+    }
+
+    /// Revert migrations as necessary so that no migrations dependent on the
+    /// specified migration are applied. If the specified migration was already
+    /// applied, it will still be applied.
+    ///
+    /// If `to` is `None`, revert all applied migrations.
+    pub async fn down(&mut self, to: Option<I>) -> Result<(), MigratorError<I, T::Error>> {
+        if let Some(to) = &to {
+            info!("Migrating down to target: {}", to);
+        } else {
+            info!("Migrating everything");
+        }
+
+        self.migrate(to.as_ref().map(slice::from_ref), MigrationDirection::Down)
+            .await
+    }
+
+    /// Apply migrations as necessary so that all of the specified migrations
+    /// are applied (inclusive), in a single pass.
+    ///
+    /// See [`Migrator::up_many`](crate::Migrator::up_many).
+    pub async fn up_many(
+        &mut self,
+        targets: impl IntoIterator<Item = I>,
+    ) -> Result<(), MigratorError<I, T::Error>> {
+        let targets: Vec<_> = targets.into_iter().collect();
+        info!("Migrating up to {} targets", targets.len());
+
+        self.migrate(Some(&targets), MigrationDirection::Up).await
+    }
+
+    /// Revert migrations as necessary so that no migrations dependent on any of
+    /// the specified migrations are applied, in a single pass.
+    ///
+    /// See [`Migrator::down_many`](crate::Migrator::down_many).
+    pub async fn down_many(
+        &mut self,
+        targets: impl IntoIterator<Item = I>,
+    ) -> Result<(), MigratorError<I, T::Error>> {
+        let targets: Vec<_> = targets.into_iter().collect();
+        info!("Migrating down to {} targets", targets.len());
+
+        self.migrate(Some(&targets), MigrationDirection::Down).await
+    }
+}
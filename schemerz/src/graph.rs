@@ -0,0 +1,1063 @@
+//! The migration dependency graph, shared by [`Migrator`](crate::Migrator) and
+//! [`AsyncMigrator`](crate::AsyncMigrator).
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap, HashSet};
+use std::fmt::{Debug, Display, Write};
+use std::hash::Hash;
+use std::mem;
+use std::ops::Index;
+use std::sync::Arc;
+use std::time::Instant;
+
+use daggy::petgraph::EdgeDirection;
+use daggy::{Dag, NodeIndex, Walker};
+use log::{debug, info};
+
+use crate::traversal::DfsPostOrderDirectional;
+use crate::{
+    DependencyError, Migration, MigrationDirection, MigrationInfo, MigrationObserver,
+    MigrationStatus, MigratorError, PlannedMigration,
+};
+
+/// A single step of a migration plan.
+pub(crate) struct PlanStep {
+    pub(crate) idx: NodeIndex,
+    /// Whether the migration is only recorded as applied or reverted, rather
+    /// than run, because a baseline stands in for it.
+    pub(crate) recorded_only: bool,
+    /// Whether the migration is already applied, and is applied again because
+    /// it is repeatable and has changed since.
+    pub(crate) reapply: bool,
+}
+
+/// The adapter method that carries out a plan step.
+pub(crate) enum Action {
+    Apply,
+    Reapply,
+    MarkApplied,
+    Revert,
+    UnmarkApplied,
+}
+
+/// A plan step being run by a migrator, from when the observer is notified
+/// that it is about to run until it is notified of how it ended.
+///
+/// Only the adapter call in between differs between [`Migrator`](crate::Migrator)
+/// and [`AsyncMigrator`](crate::AsyncMigrator).
+pub(crate) struct RunningStep<'g, I, M> {
+    /// The migration to pass to the adapter.
+    pub(crate) migration: &'g M,
+    /// The adapter method to call.
+    pub(crate) action: Action,
+    planned: PlannedMigration<I>,
+    started: Instant,
+}
+
+impl<I, M> RunningStep<'_, I, M> {
+    /// Notify the observer, if any, of how running the step ended, and turn a
+    /// failure into a `MigratorError`.
+    pub(crate) fn finish<E: std::error::Error + 'static>(
+        self,
+        result: Result<(), E>,
+        observer: Option<&mut dyn MigrationObserver<I>>,
+    ) -> Result<(), MigratorError<I, E>> {
+        let duration = self.started.elapsed();
+        match result {
+            Ok(()) => {
+                if let Some(observer) = observer {
+                    match self.planned.direction {
+                        MigrationDirection::Up => observer.after_apply(&self.planned, duration),
+                        MigrationDirection::Down => observer.after_revert(&self.planned, duration),
+                    }
+                }
+                Ok(())
+            }
+            Err(error) => {
+                if let Some(observer) = observer {
+                    observer.failed(&self.planned, duration, &error);
+                }
+                Err(MigratorError::Migration {
+                    id: self.planned.id,
+                    description: self.planned.description,
+                    direction: self.planned.direction,
+                    error,
+                })
+            }
+        }
+    }
+}
+
+/// The applied state recorded by an adapter.
+pub(crate) struct AppliedState<I> {
+    pub(crate) migrations: HashSet<I>,
+    pub(crate) fingerprints: HashMap<I, Vec<u8>>,
+}
+
+/// A validated, immutable dependency graph of migrations.
+///
+/// Building a `MigrationGraph` checks every migration's dependencies up front,
+/// so a graph can be built once, for example at startup, and then shared
+/// between any number of [`Migrator`](crate::Migrator)s and
+/// [`AsyncMigrator`](crate::AsyncMigrator)s with
+/// [`Migrator::with_graph`](crate::Migrator::with_graph).
+pub struct MigrationGraph<I, M> {
+    dependencies: Dag<M, ()>,
+    id_map: HashMap<I, NodeIndex>,
+}
+
+impl<I, M> MigrationGraph<I, M> {
+    fn empty() -> Self {
+        MigrationGraph {
+            dependencies: Dag::new(),
+            id_map: HashMap::new(),
+        }
+    }
+
+    /// Returns the number of migrations in the graph.
+    pub fn len(&self) -> usize {
+        self.dependencies.node_count()
+    }
+
+    /// Returns `true` if the graph contains no migrations.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the migrations in the graph, in the order they were given.
+    pub fn migrations(&self) -> impl Iterator<Item = &M> {
+        self.dependencies
+            .graph()
+            .node_indices()
+            .map(|idx| &self.dependencies[idx])
+    }
+
+    /// Returns the migrations that no other migration depends on, in the order
+    /// they were given. A new migration that depends on every one of them is
+    /// ordered after every migration in the graph.
+    pub fn leaves(&self) -> impl Iterator<Item = &M> {
+        self.dependencies
+            .graph()
+            .externals(EdgeDirection::Outgoing)
+            .map(|idx| &self.dependencies[idx])
+    }
+}
+
+impl<I, M> MigrationGraph<I, M>
+where
+    I: Hash + Display + Eq + Clone,
+    M: Migration<I>,
+{
+    /// Build the dependency graph of the given migrations.
+    ///
+    /// Fails if any migration ID is duplicated, if any migration depends on or
+    /// replaces an unknown migration, or if the dependencies contain a cycle.
+    /// Rather than stopping at the first of these, every one of them is
+    /// reported in the returned [`GraphError`].
+    pub fn new(migrations: impl IntoIterator<Item = M>) -> Result<Self, GraphError<I>> {
+        let mut graph = Self::empty();
+        let mut errors = vec![];
+        for migration in migrations {
+            if let Err(e) = graph.add_node(migration) {
+                errors.push(e);
+            }
+        }
+        errors.extend(graph.add_edges());
+
+        if errors.is_empty() {
+            Ok(graph)
+        } else {
+            Err(GraphError { errors })
+        }
+    }
+
+    /// Returns `true` if the graph contains a migration with the given ID.
+    pub fn contains(&self, id: &I) -> bool {
+        self.id_map.contains_key(id)
+    }
+
+    /// Returns the migration with the given ID, if the graph contains it.
+    pub fn get(&self, id: &I) -> Option<&M> {
+        self.id_map.get(id).map(|idx| &self.dependencies[*idx])
+    }
+
+    /// Add a migration as a node of the graph.
+    fn add_node(&mut self, migration: M) -> Result<(), DependencyError<I>> {
+        let id = migration.id();
+        debug!("Registering migration {}", id);
+        if self.id_map.contains_key(&id) {
+            return Err(DependencyError::DuplicateId(id));
+        }
+
+        let migration_idx = self.dependencies.add_node(migration);
+        self.id_map.insert(id, migration_idx);
+
+        Ok(())
+    }
+
+    /// Creates the edges for the current migrations into the dependency graph,
+    /// returning every edge that could not be added.
+    ///
+    /// Besides its declared dependencies, each migration replaced by a baseline
+    /// is treated as depending on that baseline, so that the baseline is
+    /// ordered before everything that builds on the migrations it replaces.
+    fn add_edges(&mut self) -> Vec<DependencyError<I>> {
+        let mut errors = vec![];
+        let idxs: Vec<_> = self.dependencies.graph().node_indices().collect();
+        for migration_idx in idxs {
+            let migration = &self.dependencies[migration_idx];
+            let id = migration.id();
+            let depends = migration.dependencies();
+            let replaces = migration.replaces();
+
+            for r in replaces {
+                let Some(replaced_idx) = self.id_map.get(&r) else {
+                    errors.push(DependencyError::UnknownId(r));
+                    continue;
+                };
+                if self
+                    .dependencies
+                    .add_edge(migration_idx, *replaced_idx, ())
+                    .is_err()
+                {
+                    errors.push(DependencyError::Cycle {
+                        from: id.clone(),
+                        to: r,
+                    });
+                }
+            }
+
+            for d in depends {
+                let Some(parent_idx) = self.id_map.get(&d) else {
+                    errors.push(DependencyError::UnknownId(d));
+                    continue;
+                };
+                if self
+                    .dependencies
+                    .add_edge(*parent_idx, migration_idx, ())
+                    .is_err()
+                {
+                    errors.push(DependencyError::Cycle {
+                        from: d,
+                        to: id.clone(),
+                    });
+                }
+            }
+        }
+        errors
+    }
+
+    /// Collect the ids of recursively dependent migrations in `dir` induced
+    /// starting from each of `ids`. If `dir` is `Incoming`, this is all
+    /// ancestors (dependencies); if `Outgoing`, this is all descendents
+    /// (dependents). If `ids` is `None`, this is all migrations starting from
+    /// the sources or the sinks, respectively.
+    fn induced_stream(
+        &self,
+        ids: Option<&[I]>,
+        dir: EdgeDirection,
+    ) -> Result<HashSet<NodeIndex>, DependencyError<I>> {
+        let mut to_visit = Vec::new();
+        match ids {
+            Some(ids) => {
+                for id in ids {
+                    if let Some(idx) = self.id_map.get(id) {
+                        to_visit.push(*idx);
+                    } else {
+                        return Err(DependencyError::UnknownId(id.clone()));
+                    }
+                }
+            }
+            None => to_visit.extend(self.dependencies.graph().externals(dir.opposite())),
+        }
+
+        let mut target_set = HashSet::new();
+
+        for idx in to_visit {
+            if !target_set.contains(&idx) {
+                let walker = DfsPostOrderDirectional::new(dir, &self.dependencies, idx);
+                target_set.extend(walker.iter(&self.dependencies));
+            }
+        }
+
+        Ok(target_set)
+    }
+
+    /// Returns the given targets, along with every migration they recursively
+    /// depend on and, for each baseline among those, the migrations it
+    /// replaces, since applying a baseline records them as applied.
+    fn with_replaced(&self, targets: &[I]) -> Result<Vec<I>, DependencyError<I>> {
+        let mut to_visit = Vec::with_capacity(targets.len());
+        for target in targets {
+            let idx = self
+                .id_map
+                .get(target)
+                .ok_or_else(|| DependencyError::UnknownId(target.clone()))?;
+            to_visit.push(*idx);
+        }
+
+        let mut visited = HashSet::new();
+        while let Some(idx) = to_visit.pop() {
+            if !visited.insert(idx) {
+                continue;
+            }
+            let migration = &self.dependencies[idx];
+            to_visit.extend(
+                migration
+                    .dependencies()
+                    .iter()
+                    .chain(migration.replaces().iter())
+                    .map(|id| self.id_map[id]),
+            );
+        }
+        Ok(visited
+            .into_iter()
+            .map(|idx| self.dependencies[idx].id())
+            .collect())
+    }
+
+    /// Collect the migrations that recursively depend on the given one,
+    /// excluding itself.
+    ///
+    /// The migrations a baseline replaces are ordered after it, but do not
+    /// depend on it, so they are not reverted along with its dependents.
+    fn dependents(&self, idx: NodeIndex) -> HashSet<NodeIndex> {
+        let graph = self.dependencies.graph();
+        let mut dependents = HashSet::new();
+        let mut to_visit = vec![idx];
+        while let Some(idx) = to_visit.pop() {
+            let replaces = self.dependencies[idx].replaces();
+            for child in graph.neighbors_directed(idx, EdgeDirection::Outgoing) {
+                if replaces.contains(&self.dependencies[child].id()) {
+                    continue;
+                }
+                if dependents.insert(child) {
+                    to_visit.push(child);
+                }
+            }
+        }
+        dependents
+    }
+
+    /// Sort the given migrations so that each comes after all of its
+    /// dependencies among them.
+    ///
+    /// Whenever several migrations are ready to run, repeatable migrations
+    /// come after the others, and otherwise the one registered first comes
+    /// first, so the order only depends on the graph and on the order of
+    /// registration.
+    fn topological_order(&self, idxs: &HashSet<NodeIndex>) -> Vec<NodeIndex> {
+        let key = |idx: NodeIndex| Reverse((self.dependencies[idx].is_repeatable(), idx));
+        let graph = self.dependencies.graph();
+        let mut unsorted_parents: HashMap<NodeIndex, usize> = idxs
+            .iter()
+            .map(|idx| {
+                let parents = graph
+                    .neighbors_directed(*idx, EdgeDirection::Incoming)
+                    .filter(|parent| idxs.contains(parent))
+                    .count();
+                (*idx, parents)
+            })
+            .collect();
+        let mut ready: BinaryHeap<_> = unsorted_parents
+            .iter()
+            .filter(|(_, parents)| **parents == 0)
+            .map(|(idx, _)| key(*idx))
+            .collect();
+
+        let mut order = Vec::with_capacity(idxs.len());
+        while let Some(Reverse((_, idx))) = ready.pop() {
+            order.push(idx);
+            for child in graph.neighbors_directed(idx, EdgeDirection::Outgoing) {
+                if let Some(parents) = unsorted_parents.get_mut(&child) {
+                    *parents -= 1;
+                    if *parents == 0 {
+                        ready.push(key(child));
+                    }
+                }
+            }
+        }
+        order
+    }
+
+    /// Check that the given set of applied migrations is consistent with the
+    /// dependency graph: every applied migration must be registered, and all of
+    /// its dependencies must also be applied.
+    ///
+    /// Migrations are checked in order of registration, so the error returned
+    /// does not depend on the order of the set.
+    fn check_applied(&self, applied_migrations: &HashSet<I>) -> Result<(), DependencyError<I>> {
+        if let Some(id) = self.unknown_applied(applied_migrations).into_iter().next() {
+            return Err(DependencyError::UnknownApplied(id));
+        }
+        for idx in self.dependencies.graph().node_indices() {
+            let id = self.dependencies[idx].id();
+            if !applied_migrations.contains(&id) {
+                continue;
+            }
+            if let Some(dependency) = self.missing_dependency(idx, applied_migrations) {
+                return Err(DependencyError::UnappliedDependency { id, dependency });
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the IDs of the given applied migrations that are not registered,
+    /// sorted by their string representation.
+    fn unknown_applied(&self, applied_migrations: &HashSet<I>) -> Vec<I> {
+        let mut unknown: Vec<_> = applied_migrations
+            .iter()
+            .filter(|id| !self.id_map.contains_key(*id))
+            .cloned()
+            .collect();
+        unknown.sort_by_cached_key(|id| id.to_string());
+        unknown
+    }
+
+    /// Returns the first dependency of the migration, in order of
+    /// registration, that is not in the given set.
+    fn missing_dependency(&self, idx: NodeIndex, applied_migrations: &HashSet<I>) -> Option<I> {
+        self.dependencies[idx]
+            .dependencies()
+            .into_iter()
+            .filter(|dependency| !applied_migrations.contains(dependency))
+            .min_by_key(|dependency| self.id_map.get(dependency).copied())
+    }
+
+    /// Returns the registered migrations, in order of registration, whose
+    /// current fingerprint differs from the one recorded when they were
+    /// applied, including repeatable ones.
+    fn changed<'a>(
+        &'a self,
+        applied_fingerprints: &'a HashMap<I, Vec<u8>>,
+    ) -> impl Iterator<Item = &'a M> + 'a {
+        self.dependencies
+            .graph()
+            .node_indices()
+            .map(|idx| &self.dependencies[idx])
+            .filter(|migration| {
+                applied_fingerprints
+                    .get(&migration.id())
+                    .is_some_and(|recorded| {
+                        migration
+                            .fingerprint()
+                            .is_some_and(|current| &current != recorded)
+                    })
+            })
+    }
+
+    /// Returns the registered migrations, in order of registration, that are
+    /// not repeatable and whose current fingerprint differs from the one
+    /// recorded when they were applied.
+    fn drifted<'a>(
+        &'a self,
+        applied_fingerprints: &'a HashMap<I, Vec<u8>>,
+    ) -> impl Iterator<Item = &'a M> + 'a {
+        self.changed(applied_fingerprints)
+            .filter(|migration| !migration.is_repeatable())
+    }
+
+    /// Returns the indices of the repeatable migrations whose current
+    /// fingerprint differs from the one recorded when they were applied.
+    fn outdated(&self, applied_fingerprints: &HashMap<I, Vec<u8>>) -> HashSet<NodeIndex> {
+        self.changed(applied_fingerprints)
+            .filter(|migration| migration.is_repeatable())
+            .map(|migration| self.id_map[&migration.id()])
+            .collect()
+    }
+
+    /// Check that the applied state recorded by an adapter is consistent with
+    /// the dependency graph and that no applied migration has changed since it
+    /// was applied.
+    pub(crate) fn check<E: std::error::Error + 'static>(
+        &self,
+        applied_migrations: &HashSet<I>,
+        applied_fingerprints: &HashMap<I, Vec<u8>>,
+    ) -> Result<(), MigratorError<I, E>> {
+        self.check_applied(applied_migrations)
+            .map_err(MigratorError::Dependency)?;
+
+        if let Some(migration) = self.drifted(applied_fingerprints).next() {
+            return Err(MigratorError::Drift {
+                id: migration.id(),
+                description: migration.description(),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Returns the migrations to record as applied without running them, in
+    /// order, which are none if the migration is already applied.
+    ///
+    /// As when a baseline is applied, recording a baseline as applied also
+    /// records the migrations it replaces that are not applied.
+    ///
+    /// Fails if the migration is not registered, or if any dependency of the
+    /// migrations to record is neither applied nor recorded before it.
+    pub(crate) fn to_mark_applied(
+        &self,
+        id: &I,
+        applied_migrations: &HashSet<I>,
+    ) -> Result<Vec<&M>, DependencyError<I>> {
+        let idx = *self
+            .id_map
+            .get(id)
+            .ok_or_else(|| DependencyError::UnknownId(id.clone()))?;
+        if applied_migrations.contains(id) {
+            return Ok(vec![]);
+        }
+
+        let idxs: HashSet<_> = self.dependencies[idx]
+            .replaces()
+            .iter()
+            .filter(|id| !applied_migrations.contains(*id))
+            .map(|id| self.id_map[id])
+            .chain([idx])
+            .collect();
+        let idxs = self.topological_order(&idxs);
+
+        let mut recorded = applied_migrations.clone();
+        for idx in &idxs {
+            let id = self.dependencies[*idx].id();
+            if let Some(dependency) = self.missing_dependency(*idx, &recorded) {
+                return Err(DependencyError::UnappliedDependency { id, dependency });
+            }
+            recorded.insert(id);
+        }
+        Ok(idxs
+            .into_iter()
+            .map(|idx| &self.dependencies[idx])
+            .collect())
+    }
+
+    /// Compute the migrations that would be run in `direction` for the given
+    /// targets, in order. If `targets` is `None`, every migration is targeted.
+    ///
+    /// Going up, this is every migration the targets depend on, and the
+    /// targets themselves, that is not applied or is repeatable and has
+    /// changed; a targeted baseline also targets the migrations it replaces.
+    /// Going down, this is every migration that depends on any of the
+    /// targets, which does not include the migrations a baseline replaces.
+    ///
+    /// Fails if a migration that would be reverted is irreversible.
+    pub(crate) fn plan<E: std::error::Error + 'static>(
+        &self,
+        targets: Option<&[I]>,
+        direction: MigrationDirection,
+        applied: &AppliedState<I>,
+    ) -> Result<Vec<PlanStep>, MigratorError<I, E>> {
+        let applied_migrations = &applied.migrations;
+        // Migrations are reverted in the reverse of the order in which they
+        // would be applied.
+        let target_idxs = match direction {
+            MigrationDirection::Up => {
+                let targets = targets
+                    .map(|targets| self.with_replaced(targets))
+                    .transpose()
+                    .map_err(MigratorError::Dependency)?;
+                let target_idxs = self
+                    .induced_stream(targets.as_deref(), EdgeDirection::Incoming)
+                    .map_err(MigratorError::Dependency)?;
+                self.topological_order(&target_idxs)
+            }
+            MigrationDirection::Down => {
+                let target_idxs = match targets {
+                    Some(targets) => {
+                        let mut target_idxs = HashSet::new();
+                        for target in targets {
+                            let idx = self.id_map.get(target).ok_or_else(|| {
+                                MigratorError::Dependency(DependencyError::UnknownId(
+                                    target.clone(),
+                                ))
+                            })?;
+                            target_idxs.extend(self.dependents(*idx));
+                        }
+                        target_idxs
+                    }
+                    None => self
+                        .induced_stream(None, EdgeDirection::Outgoing)
+                        .map_err(MigratorError::Dependency)?,
+                };
+                let baselines = self.broken_baselines(&target_idxs);
+                let mut target_idxs = self.topological_order(&target_idxs);
+                target_idxs.reverse();
+                target_idxs.extend(baselines);
+                target_idxs
+            }
+        };
+
+        // Going up, run the migrations that are not yet applied, and the
+        // repeatable ones that have changed; going down, revert the ones that
+        // are applied.
+        let outdated = match direction {
+            MigrationDirection::Up => self.outdated(&applied.fingerprints),
+            MigrationDirection::Down => HashSet::new(),
+        };
+        let target_idxs = target_idxs.into_iter().filter(|idx| {
+            let is_applied = applied_migrations.contains(&self.dependencies[*idx].id());
+            match direction {
+                MigrationDirection::Up => !is_applied || outdated.contains(idx),
+                MigrationDirection::Down => is_applied,
+            }
+        });
+
+        let plan: Vec<_> = match direction {
+            MigrationDirection::Up => self.up_steps(target_idxs, applied_migrations),
+            // Reverting the migrations a baseline replaces reverts the baseline,
+            // so baselines are only ever recorded as reverted.
+            MigrationDirection::Down => target_idxs
+                .map(|idx| PlanStep {
+                    idx,
+                    recorded_only: !self.dependencies[idx].replaces().is_empty(),
+                    reapply: false,
+                })
+                .collect(),
+        };
+
+        if direction == MigrationDirection::Down {
+            let irreversible: Vec<_> = plan
+                .iter()
+                .filter(|step| !step.recorded_only)
+                .map(|step| &self.dependencies[step.idx])
+                .filter(|migration| !migration.is_reversible())
+                .map(|migration| migration.id())
+                .collect();
+            if !irreversible.is_empty() {
+                return Err(MigratorError::Irreversible { ids: irreversible });
+            }
+        }
+
+        Ok(plan)
+    }
+
+    /// The baselines, in order, that replace any of the migrations to revert
+    /// without being reverted themselves. A baseline only stays applied while
+    /// all of the migrations it replaces are, so these are recorded as
+    /// reverted after them.
+    fn broken_baselines(&self, idxs: &HashSet<NodeIndex>) -> Vec<NodeIndex> {
+        let baselines = self
+            .dependencies
+            .graph()
+            .node_indices()
+            .filter(|idx| !idxs.contains(idx))
+            .filter(|idx| {
+                self.dependencies[*idx]
+                    .replaces()
+                    .iter()
+                    .any(|id| idxs.contains(&self.id_map[id]))
+            })
+            .collect();
+        let mut baselines = self.topological_order(&baselines);
+        baselines.reverse();
+        baselines
+    }
+
+    /// Turn the unapplied migrations to apply, in order, into plan steps.
+    ///
+    /// A baseline none of whose replaced migrations are applied, and all of
+    /// whose replaced migrations are being applied, is run in their place,
+    /// after which they are all recorded as applied. Otherwise the replaced
+    /// migrations are run as usual, and the baseline is recorded as applied
+    /// once all of them are, so that a baseline is never recorded as applied
+    /// along with migrations beyond the target.
+    fn up_steps(
+        &self,
+        target_idxs: impl Iterator<Item = NodeIndex>,
+        applied_migrations: &HashSet<I>,
+    ) -> Vec<PlanStep> {
+        let target_idxs: Vec<_> = target_idxs.collect();
+        let target_ids: HashSet<_> = target_idxs
+            .iter()
+            .map(|idx| self.dependencies[*idx].id())
+            .collect();
+
+        // IDs replaced by baselines that will be run, and the number of
+        // replaced migrations still to be applied for each other baseline.
+        let mut replaced = HashSet::new();
+        let mut outstanding = BTreeMap::new();
+        for idx in &target_idxs {
+            let replaces = self.dependencies[*idx].replaces();
+            if replaces.is_empty() {
+                continue;
+            }
+            if replaces.iter().any(|id| applied_migrations.contains(id))
+                || !replaces.iter().all(|id| target_ids.contains(id))
+            {
+                let unapplied = replaces
+                    .iter()
+                    .filter(|id| !applied_migrations.contains(id))
+                    .count();
+                outstanding.insert(*idx, unapplied);
+            } else {
+                replaced.extend(replaces);
+            }
+        }
+
+        let mut steps = vec![];
+        for idx in target_idxs {
+            let migration = &self.dependencies[idx];
+            let id = migration.id();
+            if let Some(unapplied) = outstanding.get(&idx) {
+                if *unapplied == 0 {
+                    steps.push(PlanStep {
+                        idx,
+                        recorded_only: true,
+                        reapply: false,
+                    });
+                }
+                continue;
+            }
+            if replaced.contains(&id) {
+                continue;
+            }
+
+            steps.push(PlanStep {
+                idx,
+                recorded_only: false,
+                reapply: applied_migrations.contains(&id),
+            });
+
+            let mut replaced_idxs: Vec<_> = migration
+                .replaces()
+                .iter()
+                .map(|id| self.id_map[id])
+                .collect();
+            replaced_idxs.sort();
+            steps.extend(replaced_idxs.into_iter().map(|idx| PlanStep {
+                idx,
+                recorded_only: true,
+                reapply: false,
+            }));
+
+            for (baseline_idx, unapplied) in outstanding.iter_mut() {
+                if self.dependencies[*baseline_idx].replaces().contains(&id) {
+                    *unapplied -= 1;
+                    if *unapplied == 0 {
+                        steps.push(PlanStep {
+                            idx: *baseline_idx,
+                            recorded_only: true,
+                            reapply: false,
+                        });
+                    }
+                }
+            }
+        }
+        steps
+    }
+
+    /// Notify the observer, if any, that `plan` has been computed, `started`
+    /// being when computing it began.
+    pub(crate) fn plan_computed(
+        &self,
+        plan: &[PlanStep],
+        direction: MigrationDirection,
+        started: Instant,
+        observer: Option<&mut dyn MigrationObserver<I>>,
+    ) {
+        if let Some(observer) = observer {
+            observer.plan_computed(&self.describe_plan(plan, direction), started.elapsed());
+        }
+    }
+
+    /// Start running a plan step, notifying the observer, if any, and
+    /// choosing the adapter method that carries it out.
+    pub(crate) fn start_step(
+        &self,
+        step: &PlanStep,
+        direction: MigrationDirection,
+        observer: Option<&mut dyn MigrationObserver<I>>,
+    ) -> RunningStep<'_, I, M> {
+        let planned = self.describe(step, direction);
+        let action = match direction {
+            MigrationDirection::Up if step.recorded_only => {
+                info!("Recording migration {} as applied", planned.id);
+                Action::MarkApplied
+            }
+            MigrationDirection::Up if step.reapply => {
+                info!("Reapplying migration {}", planned.id);
+                Action::Reapply
+            }
+            MigrationDirection::Up => {
+                info!("Applying migration {}", planned.id);
+                Action::Apply
+            }
+            MigrationDirection::Down if step.recorded_only => {
+                info!("Recording migration {} as reverted", planned.id);
+                Action::UnmarkApplied
+            }
+            MigrationDirection::Down => {
+                info!("Reverting migration {}", planned.id);
+                Action::Revert
+            }
+        };
+        if let Some(observer) = observer {
+            match direction {
+                MigrationDirection::Up => observer.before_apply(&planned),
+                MigrationDirection::Down => observer.before_revert(&planned),
+            }
+        }
+
+        RunningStep {
+            migration: &self.dependencies[step.idx],
+            action,
+            planned,
+            started: Instant::now(),
+        }
+    }
+
+    /// Describe a single plan step.
+    pub(crate) fn describe(
+        &self,
+        step: &PlanStep,
+        direction: MigrationDirection,
+    ) -> PlannedMigration<I> {
+        let migration = &self.dependencies[step.idx];
+        PlannedMigration {
+            id: migration.id(),
+            description: migration.description(),
+            direction,
+            recorded_only: step.recorded_only,
+            reapply: step.reapply,
+        }
+    }
+
+    /// Describe the given plan steps.
+    pub(crate) fn describe_plan(
+        &self,
+        steps: &[PlanStep],
+        direction: MigrationDirection,
+    ) -> Vec<PlannedMigration<I>> {
+        steps
+            .iter()
+            .map(|step| self.describe(step, direction))
+            .collect()
+    }
+
+    /// Returns the dependency edges of the graph, as pairs of dependency and
+    /// dependent, in a stable order.
+    fn edges(&self) -> BTreeSet<(NodeIndex, NodeIndex)> {
+        self.dependencies
+            .raw_edges()
+            .iter()
+            .map(|edge| (edge.source(), edge.target()))
+            .collect()
+    }
+
+    /// Render the graph in the Graphviz DOT language. If `applied_migrations`
+    /// is given, nodes are colored by whether they are applied or pending.
+    pub(crate) fn to_dot(&self, applied_migrations: Option<&HashSet<I>>) -> String {
+        let escape = |s: &str| s.replace('\\', "\\\\").replace('"', "\\\"");
+
+        let mut dot = String::from("digraph migrations {\n");
+        for idx in self.dependencies.graph().node_indices() {
+            let migration = &self.dependencies[idx];
+            let label = format!(
+                "{}\\n{}",
+                escape(&migration.id().to_string()),
+                escape(migration.description())
+            );
+            let style = match applied_migrations {
+                Some(applied) if applied.contains(&migration.id()) => {
+                    ", style=filled, fillcolor=palegreen"
+                }
+                Some(_) => ", style=filled, fillcolor=lightgrey",
+                None => "",
+            };
+            writeln!(dot, "    m{} [label=\"{}\"{}];", idx.index(), label, style).unwrap();
+        }
+        for (from, to) in self.edges() {
+            writeln!(dot, "    m{} -> m{};", from.index(), to.index()).unwrap();
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Render the graph as a Mermaid flowchart. If `applied_migrations` is
+    /// given, nodes are colored by whether they are applied or pending.
+    pub(crate) fn to_mermaid(&self, applied_migrations: Option<&HashSet<I>>) -> String {
+        let escape = |s: &str| {
+            s.replace('"', "#quot;")
+                .replace('<', "#lt;")
+                .replace('>', "#gt;")
+        };
+
+        let mut mermaid = String::from("graph TD\n");
+        for idx in self.dependencies.graph().node_indices() {
+            let migration = &self.dependencies[idx];
+            writeln!(
+                mermaid,
+                "    m{}[\"{}<br/>{}\"]",
+                idx.index(),
+                escape(&migration.id().to_string()),
+                escape(migration.description())
+            )
+            .unwrap();
+        }
+        for (from, to) in self.edges() {
+            writeln!(mermaid, "    m{} --> m{}", from.index(), to.index()).unwrap();
+        }
+        if let Some(applied) = applied_migrations {
+            mermaid.push_str("    classDef applied fill:#98fb98\n");
+            mermaid.push_str("    classDef pending fill:#d3d3d3\n");
+            for idx in self.dependencies.graph().node_indices() {
+                let class = if applied.contains(&self.dependencies[idx].id()) {
+                    "applied"
+                } else {
+                    "pending"
+                };
+                writeln!(mermaid, "    class m{} {}", idx.index(), class).unwrap();
+            }
+        }
+        mermaid
+    }
+
+    /// Compare the registered migrations against the given applied state.
+    pub(crate) fn status(
+        &self,
+        applied_migrations: &HashSet<I>,
+        applied_fingerprints: &HashMap<I, Vec<u8>>,
+    ) -> Result<MigrationStatus<I>, DependencyError<I>> {
+        let all_idxs = self.induced_stream(None, EdgeDirection::Incoming)?;
+        let all_idxs = self.topological_order(&all_idxs);
+
+        let mut status = MigrationStatus {
+            applied: vec![],
+            pending: vec![],
+            unknown: self.unknown_applied(applied_migrations),
+            drifted: self
+                .drifted(applied_fingerprints)
+                .map(|migration| migration.id())
+                .collect(),
+            outdated: vec![],
+        };
+        let outdated = self.outdated(applied_fingerprints);
+        for idx in all_idxs {
+            let migration = &self.dependencies[idx];
+            let info = MigrationInfo {
+                id: migration.id(),
+                description: migration.description(),
+                dependencies: migration.dependencies(),
+            };
+            if outdated.contains(&idx) {
+                status.outdated.push(info.id.clone());
+            }
+            if applied_migrations.contains(&info.id) {
+                status.applied.push(info);
+            } else {
+                status.pending.push(info);
+            }
+        }
+
+        Ok(status)
+    }
+}
+
+impl<I, M> Index<NodeIndex> for MigrationGraph<I, M> {
+    type Output = M;
+
+    fn index(&self, idx: NodeIndex) -> &M {
+        &self.dependencies[idx]
+    }
+}
+
+/// Every error found in the dependencies of the migrations given to
+/// [`MigrationGraph::new`].
+#[derive(Debug)]
+pub struct GraphError<I> {
+    errors: Vec<DependencyError<I>>,
+}
+
+impl<I> GraphError<I> {
+    /// Returns the errors found, of which there is at least one.
+    pub fn errors(&self) -> &[DependencyError<I>] {
+        &self.errors
+    }
+
+    /// Consume this `GraphError`, returning the errors found.
+    pub fn into_errors(self) -> Vec<DependencyError<I>> {
+        self.errors
+    }
+}
+
+impl<I: Display> Display for GraphError<I> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid migration dependencies: ")?;
+        for (i, error) in self.errors.iter().enumerate() {
+            if i > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{}", error)?;
+        }
+        Ok(())
+    }
+}
+
+impl<I: Debug + Display> std::error::Error for GraphError<I> {}
+
+/// A [`MigrationGraph`] shared between migrators.
+pub(crate) type SharedGraph<I, M> = Arc<MigrationGraph<I, M>>;
+
+/// The migrations registered with a migrator, which are finalized into a
+/// [`MigrationGraph`] the first time they are needed and then reused.
+pub(crate) enum Registry<I, M> {
+    /// Migrations registered since the graph was last finalized, without any
+    /// dependency edges.
+    Pending(MigrationGraph<I, M>),
+    Finalized(SharedGraph<I, M>),
+}
+
+impl<I, M> Registry<I, M>
+where
+    I: Hash + Display + Eq + Clone,
+    M: Migration<I>,
+{
+    pub(crate) fn new() -> Self {
+        Registry::Pending(MigrationGraph::empty())
+    }
+
+    /// Register a migration, reopening the graph if it was already finalized.
+    ///
+    /// Fails if the finalized graph is shared with another migrator.
+    pub(crate) fn register<E: std::error::Error + 'static>(
+        &mut self,
+        migration: M,
+    ) -> Result<(), MigratorError<I, E>> {
+        if let Registry::Finalized(graph) = self {
+            let Some(graph) = Arc::get_mut(graph) else {
+                return Err(MigratorError::SharedGraph(migration.id()));
+            };
+            let mut graph = mem::replace(graph, MigrationGraph::empty());
+            graph.dependencies.clear_edges();
+            *self = Registry::Pending(graph);
+        }
+
+        let Registry::Pending(graph) = self else {
+            unreachable!("The graph was reopened above");
+        };
+        graph.add_node(migration).map_err(MigratorError::Dependency)
+    }
+
+    /// Returns the finalized graph, adding the dependency edges if this has not
+    /// been done since the last migration was registered.
+    pub(crate) fn graph<E: std::error::Error + 'static>(
+        &mut self,
+    ) -> Result<SharedGraph<I, M>, MigratorError<I, E>> {
+        if let Registry::Pending(graph) = self {
+            let errors = graph.add_edges();
+            if !errors.is_empty() {
+                // Leave the graph as it was, so that missing migrations can
+                // still be registered.
+                graph.dependencies.clear_edges();
+                return Err(MigratorError::Graph(GraphError { errors }));
+            }
+            let graph = mem::replace(graph, MigrationGraph::empty());
+            *self = Registry::Finalized(Arc::new(graph));
+        }
+
+        let Registry::Finalized(graph) = self else {
+            unreachable!("The graph was finalized above");
+        };
+        Ok(Arc::clone(graph))
+    }
+}
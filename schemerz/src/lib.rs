@@ -6,6 +6,8 @@
 //!
 //! - PostgreSQL: [`schemerz-postgres`](https://crates.io/crates/schemerz-postgres)
 //! - SQLite: [`schemerz-rusqlite`](https://crates.io/crates/schemerz-rusqlite)
+//! - PostgreSQL (async, via `tokio-postgres`):
+//!   [`schemerz-tokio-postgres`](https://crates.io/crates/schemerz-tokio-postgres)
 #![warn(clippy::all)]
 #![forbid(unsafe_code)]
 
@@ -13,20 +15,28 @@ use std::collections::{HashMap, HashSet};
 use std::fmt::{Debug, Display};
 use std::hash::Hash;
 use std::rc::Rc;
+use std::slice;
 use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 
-use daggy::petgraph::EdgeDirection;
-use daggy::{Dag, Walker};
-use indexmap::IndexSet;
-use log::{debug, info};
+use log::info;
 use thiserror::Error;
 
-use crate::traversal::DfsPostOrderDirectional;
+use crate::graph::{Action, AppliedState, PlanStep, Registry};
 
+mod asynchronous;
+mod graph;
+#[cfg(feature = "sql")]
+pub mod sql;
+mod table;
 #[macro_use]
 pub mod testing;
 mod traversal;
 
+pub use asynchronous::{AsyncAdapter, AsyncMigrator};
+pub use graph::{GraphError, MigrationGraph};
+pub use table::{TableName, TableNameError};
+
 /// Metadata for defining the identity and dependence relations of migrations.
 /// Specific adapters require additional traits for actual application and
 /// reversion of migrations.
@@ -39,6 +49,55 @@ pub trait Migration<I> {
 
     /// User-targeted description of this migration.
     fn description(&self) -> &'static str;
+
+    /// Fingerprint of this migration's content, such as a hash of its SQL or a
+    /// user-supplied version.
+    ///
+    /// Adapters that support it record the fingerprint when the migration is
+    /// applied, and `Migrator` refuses to run migrations if the fingerprint of
+    /// an applied migration has since changed. Returns `None` by default, which
+    /// disables this check for the migration.
+    fn fingerprint(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Whether this migration can be reverted.
+    ///
+    /// `Migrator` refuses to revert any set of migrations that includes an
+    /// irreversible migration, before reverting any of them. Returns `true` by
+    /// default.
+    fn is_reversible(&self) -> bool {
+        true
+    }
+
+    /// Set of IDs of the migrations this migration replaces, if it is a
+    /// baseline that squashes them into one.
+    ///
+    /// When none of the replaced migrations have been applied and all of them
+    /// are being applied, `Migrator` applies the baseline in their place and
+    /// records them all as applied. Otherwise it applies the replaced
+    /// migrations as usual, and only records the baseline as applied once all
+    /// of them are. Targeting the baseline going up also targets the
+    /// migrations it replaces, which do not depend on it going down.
+    /// Reverting any of the replaced migrations reverts the baseline, so the
+    /// baseline itself is never reverted, only recorded as such. Returns an
+    /// empty set by default.
+    fn replaces(&self) -> HashSet<I> {
+        HashSet::new()
+    }
+
+    /// Whether this migration is repeatable, such as one that creates a view
+    /// or trigger that is kept in sync with the code.
+    ///
+    /// `Migrator` applies a repeatable migration again whenever its
+    /// [`fingerprint`](Migration::fingerprint) differs from the one recorded
+    /// when it was last applied, rather than refusing to run, so it should be
+    /// written to replace what an earlier version of it created. Repeatable
+    /// migrations still run after their dependencies, and otherwise run after
+    /// every other migration being applied. Returns `false` by default.
+    fn is_repeatable(&self) -> bool {
+        false
+    }
 }
 
 impl<I, T> Migration<I> for Box<T>
@@ -56,6 +115,22 @@ where
     fn description(&self) -> &'static str {
         self.as_ref().description()
     }
+
+    fn fingerprint(&self) -> Option<Vec<u8>> {
+        self.as_ref().fingerprint()
+    }
+
+    fn is_reversible(&self) -> bool {
+        self.as_ref().is_reversible()
+    }
+
+    fn replaces(&self) -> HashSet<I> {
+        self.as_ref().replaces()
+    }
+
+    fn is_repeatable(&self) -> bool {
+        self.as_ref().is_repeatable()
+    }
 }
 
 impl<I, T> Migration<I> for Rc<T>
@@ -73,6 +148,22 @@ where
     fn description(&self) -> &'static str {
         self.as_ref().description()
     }
+
+    fn fingerprint(&self) -> Option<Vec<u8>> {
+        self.as_ref().fingerprint()
+    }
+
+    fn is_reversible(&self) -> bool {
+        self.as_ref().is_reversible()
+    }
+
+    fn replaces(&self) -> HashSet<I> {
+        self.as_ref().replaces()
+    }
+
+    fn is_repeatable(&self) -> bool {
+        self.as_ref().is_repeatable()
+    }
 }
 
 impl<I, T> Migration<I> for Arc<T>
@@ -90,10 +181,33 @@ where
     fn description(&self) -> &'static str {
         self.as_ref().description()
     }
+
+    fn fingerprint(&self) -> Option<Vec<u8>> {
+        self.as_ref().fingerprint()
+    }
+
+    fn is_reversible(&self) -> bool {
+        self.as_ref().is_reversible()
+    }
+
+    fn replaces(&self) -> HashSet<I> {
+        self.as_ref().replaces()
+    }
+
+    fn is_repeatable(&self) -> bool {
+        self.as_ref().is_repeatable()
+    }
 }
 
 /// Create a trivial implementation of `Migration` for a type.
 ///
+/// Passing `irreversible` after the description marks the migration as
+/// irreversible (see [`Migration::is_reversible`]).
+///
+/// This only supports unit-like types. `#[derive(Migration)]`, provided by the
+/// [`schemerz-macros`](https://crates.io/crates/schemerz-macros) crate, also
+/// supports generic types and types with fields, and should be preferred.
+///
 /// ## Example
 ///
 /// ```rust
@@ -118,19 +232,25 @@ where
 ///     [uuid!("bc960dc8-0e4a-4182-a62a-8e776d1e2b30")],
 ///     "Child migration in a DAG");
 ///
+/// struct DataMigration;
+/// migration!(
+///     DataMigration,
+///     uuid!("9b1e1b43-4a1d-4b0c-9a5f-2ad5c2c4b0f3"),
+///     [uuid!("4885e8ab-dafa-4d76-a565-2dee8b04ef60")],
+///     "Lossy data migration that cannot be reverted",
+///     irreversible);
+///
 /// fn main() {
 ///     let parent = ParentMigration;
 ///     let child = ChildMigration;
 ///
 ///     assert!(child.dependencies().contains(&parent.id()));
+///     assert!(!DataMigration.is_reversible());
 /// }
 /// ```
 #[macro_export]
 macro_rules! migration {
-    ($name:ident, $id:expr, [ $( $dependency_id:expr ),*], $description:expr) => {
-        migration!(::uuid::Uuid, $name, $id, [$($dependency_id),*], $description);
-    };
-    ($ty:path, $name:ident, $id:expr, [ $( $dependency_id:expr ),*], $description:expr) => {
+    (@impl $ty:path, $name:ident, $id:expr, [ $( $dependency_id:expr ),*], $description:expr, $reversible:expr) => {
         impl $crate::Migration<$ty> for $name
         {
             fn id(&self) -> $ty {
@@ -148,12 +268,28 @@ macro_rules! migration {
             fn description(&self) -> &'static str {
                 $description
             }
+
+            fn is_reversible(&self) -> bool {
+                $reversible
+            }
         }
     };
+    ($name:ident, $id:expr, [ $( $dependency_id:expr ),*], $description:expr) => {
+        migration!(::uuid::Uuid, $name, $id, [$($dependency_id),*], $description);
+    };
+    ($name:ident, $id:expr, [ $( $dependency_id:expr ),*], $description:expr, irreversible) => {
+        migration!(::uuid::Uuid, $name, $id, [$($dependency_id),*], $description, irreversible);
+    };
+    ($ty:path, $name:ident, $id:expr, [ $( $dependency_id:expr ),*], $description:expr) => {
+        migration!(@impl $ty, $name, $id, [$($dependency_id),*], $description, true);
+    };
+    ($ty:path, $name:ident, $id:expr, [ $( $dependency_id:expr ),*], $description:expr, irreversible) => {
+        migration!(@impl $ty, $name, $id, [$($dependency_id),*], $description, false);
+    };
 }
 
 /// Direction in which a migration is applied (`Up`) or reverted (`Down`).
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum MigrationDirection {
     Up,
     Down,
@@ -169,6 +305,108 @@ impl Display for MigrationDirection {
     }
 }
 
+/// A single step of a migration plan, as returned by [`Migrator::plan_up`] and
+/// [`Migrator::plan_down`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PlannedMigration<I> {
+    /// Unique identifier of the migration.
+    pub id: I,
+    /// User-targeted description of the migration.
+    pub description: &'static str,
+    /// Whether the migration would be applied or reverted.
+    pub direction: MigrationDirection,
+    /// Whether the migration would only be recorded as applied or reverted,
+    /// without being run, because a baseline stands in for it (see
+    /// [`Migration::replaces`]).
+    pub recorded_only: bool,
+    /// Whether the migration is already applied, and would be applied again
+    /// because it is repeatable and has changed since (see
+    /// [`Migration::is_repeatable`]).
+    pub reapply: bool,
+}
+
+/// Identity and dependencies of a registered migration, as reported by
+/// [`Migrator::status`].
+#[derive(Clone, Debug)]
+pub struct MigrationInfo<I> {
+    /// Unique identifier of the migration.
+    pub id: I,
+    /// User-targeted description of the migration.
+    pub description: &'static str,
+    /// Set of IDs of all direct dependencies of the migration.
+    pub dependencies: HashSet<I>,
+}
+
+/// Comparison of the registered migrations against the migrations recorded
+/// as applied by the adapter, as returned by [`Migrator::status`].
+#[derive(Clone, Debug)]
+pub struct MigrationStatus<I> {
+    /// Registered migrations that have been applied, in dependency order.
+    pub applied: Vec<MigrationInfo<I>>,
+    /// Registered migrations that have not been applied, in dependency order.
+    pub pending: Vec<MigrationInfo<I>>,
+    /// IDs of migrations recorded as applied that are not registered, sorted
+    /// by their string representation.
+    pub unknown: Vec<I>,
+    /// IDs of applied migrations whose fingerprint has changed since they were
+    /// applied, in order of registration.
+    pub drifted: Vec<I>,
+    /// IDs of applied repeatable migrations whose fingerprint has changed since
+    /// they were applied, which `up` applies again.
+    pub outdated: Vec<I>,
+}
+
+/// A record of a migration having been applied or reverted, as returned by
+/// [`Adapter::history`].
+#[derive(Clone, Debug)]
+pub struct HistoryEntry<I> {
+    /// Unique identifier of the migration.
+    pub id: I,
+    /// User-targeted description of the migration at the time it was run.
+    pub description: String,
+    /// Whether the migration was applied or reverted.
+    pub direction: MigrationDirection,
+    /// When the migration started being applied or reverted.
+    pub executed_at: SystemTime,
+    /// How long the migration took to apply or revert.
+    pub duration: Duration,
+}
+
+/// Receives callbacks as a [`Migrator`] or [`AsyncMigrator`] runs migrations,
+/// for example to report progress or to record metrics.
+///
+/// Every method has a default implementation that does nothing, so observers
+/// only need to implement the callbacks they are interested in.
+pub trait MigrationObserver<I> {
+    /// Called once `up` or `down` has computed the migrations it will run, in
+    /// the order they will be run, along with how long computing the plan took.
+    fn plan_computed(&mut self, _plan: &[PlannedMigration<I>], _duration: Duration) {}
+
+    /// Called before a migration is applied.
+    fn before_apply(&mut self, _migration: &PlannedMigration<I>) {}
+
+    /// Called after a migration has been applied successfully, along with how
+    /// long it took to apply.
+    fn after_apply(&mut self, _migration: &PlannedMigration<I>, _duration: Duration) {}
+
+    /// Called before a migration is reverted.
+    fn before_revert(&mut self, _migration: &PlannedMigration<I>) {}
+
+    /// Called after a migration has been reverted successfully, along with how
+    /// long it took to revert.
+    fn after_revert(&mut self, _migration: &PlannedMigration<I>, _duration: Duration) {}
+
+    /// Called when applying or reverting a migration fails, along with how long
+    /// it ran before failing. No further migrations are run.
+    fn failed(
+        &mut self,
+        _migration: &PlannedMigration<I>,
+        _duration: Duration,
+        _error: &dyn std::error::Error,
+    ) {
+    }
+}
+
 /// Trait necessary to adapt schemerz's migration management to a stateful
 /// backend.
 pub trait Adapter<I> {
@@ -181,11 +419,70 @@ pub trait Adapter<I> {
     /// Returns the set of IDs for migrations that have been applied.
     fn applied_migrations(&mut self) -> Result<HashSet<I>, Self::Error>;
 
+    /// Returns the fingerprints recorded for applied migrations, keyed by
+    /// migration ID. Migrations applied without a fingerprint are omitted.
+    ///
+    /// The default implementation records no fingerprints.
+    fn applied_fingerprints(&mut self) -> Result<HashMap<I, Vec<u8>>, Self::Error> {
+        Ok(HashMap::new())
+    }
+
+    /// Returns every recorded application and reversion of a migration, oldest
+    /// first.
+    ///
+    /// The default implementation records no history.
+    fn history(&mut self) -> Result<Vec<HistoryEntry<I>>, Self::Error> {
+        Ok(vec![])
+    }
+
     /// Apply a single migration.
     fn apply_migration(&mut self, _: &Self::MigrationType) -> Result<(), Self::Error>;
 
     /// Revert a single migration.
     fn revert_migration(&mut self, _: &Self::MigrationType) -> Result<(), Self::Error>;
+
+    /// Apply a single repeatable migration that is already applied, because
+    /// it has changed since (see [`Migration::is_repeatable`]).
+    ///
+    /// The default implementation records the migration as not applied, then
+    /// applies it. Adapters should override it to update the recorded
+    /// fingerprint in the same transaction as applying the migration.
+    fn reapply_migration(&mut self, migration: &Self::MigrationType) -> Result<(), Self::Error> {
+        self.unmark_applied(migration)?;
+        self.apply_migration(migration)
+    }
+
+    /// Record a single migration as applied, without applying it.
+    ///
+    /// This is needed to apply baselines (see [`Migration::replaces`]), and by
+    /// [`Migrator::mark_applied`].
+    fn mark_applied(&mut self, _: &Self::MigrationType) -> Result<(), Self::Error>;
+
+    /// Record a single migration as not applied, without reverting it.
+    ///
+    /// This is needed to revert baselines, and to reapply repeatable
+    /// migrations with the default [`Adapter::reapply_migration`].
+    fn unmark_applied(&mut self, _: &Self::MigrationType) -> Result<(), Self::Error>;
+
+    /// Acquire exclusive access to the backend for migrating, waiting for any
+    /// other migrator that holds it.
+    ///
+    /// [`Migrator::up`] and [`Migrator::down`] call this before reading the
+    /// applied migrations, and call [`Adapter::unlock`] once they are done, so
+    /// that concurrent migrators run one after the other and each sees the
+    /// migrations applied by the ones before it.
+    ///
+    /// The default implementation does nothing.
+    fn lock(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Release the access acquired by [`Adapter::lock`].
+    ///
+    /// The default implementation does nothing.
+    fn unlock(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
 }
 
 /// Error resulting from the definition of migration identity and dependency.
@@ -197,6 +494,10 @@ pub enum DependencyError<I> {
     UnknownId(I),
     #[error("Cyclic dependency caused by edge from migration IDs {from} to {to}")]
     Cycle { from: I, to: I },
+    #[error("Applied migration {id} depends on migration {dependency}, which is not applied")]
+    UnappliedDependency { id: I, dependency: I },
+    #[error("Applied migration ID {0} is not registered")]
+    UnknownApplied(I),
 }
 
 /// Error resulting either from migration definitions or from migration
@@ -205,8 +506,16 @@ pub enum DependencyError<I> {
 pub enum MigratorError<I, T: std::error::Error + 'static> {
     #[error("An error occurred due to migration dependencies")]
     Dependency(#[source] DependencyError<I>),
+    #[error("The migration graph is invalid")]
+    Graph(#[source] GraphError<I>),
+    #[error("Cannot register migration {0} into a migration graph shared with other migrators.")]
+    SharedGraph(I),
     #[error("An error occurred while interacting with the adapter.")]
     Adapter(#[from] T),
+    #[error("Applied migration {id} ({description}) has changed since it was applied.")]
+    Drift { id: I, description: &'static str },
+    #[error("Cannot revert irreversible migrations {ids:?}.")]
+    Irreversible { ids: Vec<I> },
     #[error(
         "An error occurred while applying migration {id} ({description}) {direction}: {error}."
     )]
@@ -222,8 +531,8 @@ pub enum MigratorError<I, T: std::error::Error + 'static> {
 /// Primary schemerz type for defining and applying migrations.
 pub struct Migrator<I, T: Adapter<I>> {
     adapter: T,
-    dependencies: Dag<T::MigrationType, ()>,
-    id_map: HashMap<I, daggy::NodeIndex>,
+    registry: Registry<I, T::MigrationType>,
+    observer: Option<Box<dyn MigrationObserver<I>>>,
 }
 
 impl<I, T> Migrator<I, T>
@@ -235,26 +544,55 @@ where
     pub fn new(adapter: T) -> Migrator<I, T> {
         Migrator {
             adapter,
-            dependencies: Dag::new(),
-            id_map: HashMap::new(),
+            registry: Registry::new(),
+            observer: None,
+        }
+    }
+
+    /// Create a `Migrator` using the given `Adapter` and an already built
+    /// dependency graph.
+    ///
+    /// The graph can be shared with other migrators, so that migrations can be
+    /// validated once and then applied with many adapters. Migrations cannot be
+    /// registered into a graph while it is shared.
+    pub fn with_graph(
+        adapter: T,
+        graph: impl Into<Arc<MigrationGraph<I, T::MigrationType>>>,
+    ) -> Migrator<I, T> {
+        Migrator {
+            adapter,
+            registry: Registry::Finalized(graph.into()),
+            observer: None,
         }
     }
 
+    /// Attach an observer that is notified as migrations are run by
+    /// [`Migrator::up`] and [`Migrator::down`], replacing any previously
+    /// attached observer.
+    pub fn with_observer(mut self, observer: impl MigrationObserver<I> + 'static) -> Self {
+        self.observer = Some(Box::new(observer));
+        self
+    }
+
+    /// Returns the attached observer, if any.
+    fn observer(&mut self) -> Option<&mut dyn MigrationObserver<I>> {
+        self.observer.as_deref_mut().map(|observer| observer as _)
+    }
+
+    /// Consume this `Migrator`, returning its `Adapter`.
+    ///
+    /// This is useful for adapters that need to be finalized after migrating,
+    /// such as to commit a transaction spanning every migration that was run.
+    pub fn into_adapter(self) -> T {
+        self.adapter
+    }
+
     /// Register a migration into the dependency graph.
     pub fn register(
         &mut self,
         migration: T::MigrationType,
     ) -> Result<(), MigratorError<I, T::Error>> {
-        let id = migration.id();
-        debug!("Registering migration {}", id);
-        if self.id_map.contains_key(&id) {
-            return Err(MigratorError::Dependency(DependencyError::DuplicateId(id)));
-        }
-
-        let migration_idx = self.dependencies.add_node(migration);
-        self.id_map.insert(id, migration_idx);
-
-        Ok(())
+        self.registry.register(migration)
     }
 
     /// Register multiple migrations into the dependency graph.
@@ -263,84 +601,247 @@ where
         migrations: impl Iterator<Item = T::MigrationType>,
     ) -> Result<(), MigratorError<I, T::Error>> {
         for migration in migrations {
-            let id = migration.id();
-            debug!("Registering migration (with multiple) {}", id);
-            if self.id_map.contains_key(&id) {
-                return Err(MigratorError::Dependency(DependencyError::DuplicateId(id)));
-            }
-
-            let migration_idx = self.dependencies.add_node(migration);
-            self.id_map.insert(id, migration_idx);
+            self.register(migration)?;
         }
 
         Ok(())
     }
 
-    /// Creates the edges for the current migrations into the dependency graph.
-    fn register_edges(&mut self) -> Result<(), MigratorError<I, T::Error>> {
-        for (id, migration_idx) in self.id_map.iter() {
-            let depends = self
-                .dependencies
-                .node_weight(*migration_idx)
-                .expect("We registered these nodes")
-                .dependencies();
-
-            for d in depends {
-                let parent_idx = self.id_map.get(&d).ok_or_else(|| {
-                    MigratorError::Dependency(DependencyError::UnknownId(d.clone()))
-                })?;
-                self.dependencies
-                    .add_edge(*parent_idx, *migration_idx, ())
-                    .map_err(|_| {
-                        MigratorError::Dependency(DependencyError::Cycle {
-                            from: d,
-                            to: id.clone(),
-                        })
-                    })?;
-            }
+    /// Returns the dependency graph of the registered migrations.
+    ///
+    /// The graph is built the first time it is needed after a migration is
+    /// registered, and reused until another one is. Fails with every error
+    /// found in the migrations' dependencies.
+    #[allow(clippy::type_complexity)]
+    pub fn graph(
+        &mut self,
+    ) -> Result<Arc<MigrationGraph<I, T::MigrationType>>, MigratorError<I, T::Error>> {
+        self.registry.graph()
+    }
+
+    /// Returns the applied state recorded by the adapter, after checking that
+    /// it is consistent with the dependency graph and that no applied
+    /// migration has changed since it was applied, unless it is repeatable.
+    fn checked_applied_migrations(
+        &mut self,
+        graph: &MigrationGraph<I, T::MigrationType>,
+    ) -> Result<AppliedState<I>, MigratorError<I, T::Error>> {
+        let applied = AppliedState {
+            migrations: self.adapter.applied_migrations()?,
+            fingerprints: self.adapter.applied_fingerprints()?,
+        };
+        graph.check(&applied.migrations, &applied.fingerprints)?;
+
+        Ok(applied)
+    }
+
+    /// Check that the migrations the adapter has recorded as applied are
+    /// consistent with the registered migrations.
+    ///
+    /// This fails if an applied migration is not registered, if it has a
+    /// dependency that is not applied, or if its fingerprint has changed since
+    /// it was applied and it is not repeatable. [`Migrator::up`] and
+    /// [`Migrator::down`] perform this check before running any migrations.
+    pub fn validate(&mut self) -> Result<(), MigratorError<I, T::Error>> {
+        let graph = self.graph()?;
+        self.checked_applied_migrations(&graph).map(|_| ())
+    }
+
+    /// Compute the migrations that would be run in `direction` for the given
+    /// targets, in order.
+    fn plan(
+        &mut self,
+        graph: &MigrationGraph<I, T::MigrationType>,
+        targets: Option<&[I]>,
+        direction: MigrationDirection,
+    ) -> Result<Vec<PlanStep>, MigratorError<I, T::Error>> {
+        let applied = self.checked_applied_migrations(graph)?;
+        graph.plan(targets, direction, &applied)
+    }
+
+    /// Returns the migrations that [`Migrator::up`] would apply for the same
+    /// target, in the order they would be applied. No migrations are run.
+    pub fn plan_up(
+        &mut self,
+        to: Option<I>,
+    ) -> Result<Vec<PlannedMigration<I>>, MigratorError<I, T::Error>> {
+        let graph = self.graph()?;
+        let steps = self.plan(
+            &graph,
+            to.as_ref().map(slice::from_ref),
+            MigrationDirection::Up,
+        )?;
+        Ok(graph.describe_plan(&steps, MigrationDirection::Up))
+    }
+
+    /// Returns the migrations that [`Migrator::down`] would revert for the same
+    /// target, in the order they would be reverted. No migrations are run.
+    pub fn plan_down(
+        &mut self,
+        to: Option<I>,
+    ) -> Result<Vec<PlannedMigration<I>>, MigratorError<I, T::Error>> {
+        let graph = self.graph()?;
+        let steps = self.plan(
+            &graph,
+            to.as_ref().map(slice::from_ref),
+            MigrationDirection::Down,
+        )?;
+        Ok(graph.describe_plan(&steps, MigrationDirection::Down))
+    }
+
+    /// Returns the migrations that [`Migrator::up_many`] would apply for the
+    /// same targets, in the order they would be applied. No migrations are run.
+    pub fn plan_up_many(
+        &mut self,
+        targets: impl IntoIterator<Item = I>,
+    ) -> Result<Vec<PlannedMigration<I>>, MigratorError<I, T::Error>> {
+        let targets: Vec<_> = targets.into_iter().collect();
+        let graph = self.graph()?;
+        let steps = self.plan(&graph, Some(&targets), MigrationDirection::Up)?;
+        Ok(graph.describe_plan(&steps, MigrationDirection::Up))
+    }
+
+    /// Returns the migrations that [`Migrator::down_many`] would revert for the
+    /// same targets, in the order they would be reverted. No migrations are
+    /// run.
+    pub fn plan_down_many(
+        &mut self,
+        targets: impl IntoIterator<Item = I>,
+    ) -> Result<Vec<PlannedMigration<I>>, MigratorError<I, T::Error>> {
+        let targets: Vec<_> = targets.into_iter().collect();
+        let graph = self.graph()?;
+        let steps = self.plan(&graph, Some(&targets), MigrationDirection::Down)?;
+        Ok(graph.describe_plan(&steps, MigrationDirection::Down))
+    }
+
+    /// Returns every application and reversion of a migration recorded by the
+    /// adapter, oldest first.
+    pub fn history(&mut self) -> Result<Vec<HistoryEntry<I>>, MigratorError<I, T::Error>> {
+        Ok(self.adapter.history()?)
+    }
+
+    /// Compare the registered migrations against the migrations the adapter
+    /// has recorded as applied.
+    pub fn status(&mut self) -> Result<MigrationStatus<I>, MigratorError<I, T::Error>> {
+        let graph = self.graph()?;
+        let applied_migrations = self.adapter.applied_migrations()?;
+        let applied_fingerprints = self.adapter.applied_fingerprints()?;
+        graph
+            .status(&applied_migrations, &applied_fingerprints)
+            .map_err(MigratorError::Dependency)
+    }
+
+    /// If `show_status` is `true`, return the migrations the adapter has
+    /// recorded as applied.
+    fn rendering_state(
+        &mut self,
+        show_status: bool,
+    ) -> Result<Option<HashSet<I>>, MigratorError<I, T::Error>> {
+        Ok(if show_status {
+            Some(self.adapter.applied_migrations()?)
+        } else {
+            None
+        })
+    }
+
+    /// Render the dependency graph of the registered migrations in the Graphviz
+    /// DOT language, with each node labeled by its migration's ID and
+    /// description.
+    ///
+    /// If `show_status` is `true`, nodes are colored by whether the adapter has
+    /// recorded them as applied or pending.
+    pub fn to_dot(&mut self, show_status: bool) -> Result<String, MigratorError<I, T::Error>> {
+        let graph = self.graph()?;
+        let applied_migrations = self.rendering_state(show_status)?;
+        Ok(graph.to_dot(applied_migrations.as_ref()))
+    }
+
+    /// Render the dependency graph of the registered migrations as a Mermaid
+    /// flowchart. See [`Migrator::to_dot`].
+    pub fn to_mermaid(&mut self, show_status: bool) -> Result<String, MigratorError<I, T::Error>> {
+        let graph = self.graph()?;
+        let applied_migrations = self.rendering_state(show_status)?;
+        Ok(graph.to_mermaid(applied_migrations.as_ref()))
+    }
+
+    /// Record the specified migration as applied without running it, for
+    /// example because it was applied by other means.
+    ///
+    /// Recording a baseline also records the migrations it replaces. Fails if
+    /// the migration is not registered, or if any of its dependencies is not
+    /// applied. Does nothing if it is already applied.
+    pub fn mark_applied(&mut self, id: I) -> Result<(), MigratorError<I, T::Error>> {
+        self.adapter.lock()?;
+        let result = self.mark_applied_locked(&id);
+        let unlocked = self.adapter.unlock();
+
+        result?;
+        Ok(unlocked?)
+    }
+
+    fn mark_applied_locked(&mut self, id: &I) -> Result<(), MigratorError<I, T::Error>> {
+        let graph = self.graph()?;
+        let applied = self.checked_applied_migrations(&graph)?;
+        let migrations = graph
+            .to_mark_applied(id, &applied.migrations)
+            .map_err(MigratorError::Dependency)?;
+        for migration in migrations {
+            info!("Recording migration {} as applied", migration.id());
+            self.adapter.mark_applied(migration)?;
         }
         Ok(())
     }
 
-    /// Collect the ids of recursively dependent migrations in `dir` induced
-    /// starting from `id`. If `dir` is `Incoming`, this is all ancestors
-    /// (dependencies); if `Outgoing`, this is all descendents (dependents).
-    /// If `id` is `None`, this is all migrations starting from the sources or
-    /// the sinks, respectively.
-    fn induced_stream(
-        &self,
-        id: Option<I>,
-        dir: EdgeDirection,
-    ) -> Result<IndexSet<daggy::NodeIndex>, DependencyError<I>> {
-        let mut to_visit = Vec::new();
-        match id {
-            Some(id) => {
-                if let Some(id) = self.id_map.get(&id) {
-                    to_visit.push(*id);
-                } else {
-                    return Err(DependencyError::UnknownId(id));
-                }
-            }
-            None => to_visit.extend(self.dependencies.graph().externals(dir.opposite())),
-        }
+    /// Run the migrations planned in `direction` for the given targets while
+    /// holding the adapter's lock.
+    fn migrate(
+        &mut self,
+        targets: Option<&[I]>,
+        direction: MigrationDirection,
+    ) -> Result<(), MigratorError<I, T::Error>> {
+        self.adapter.lock()?;
+        let result = self.migrate_locked(targets, direction);
+        let unlocked = self.adapter.unlock();
 
-        let mut target_set = IndexSet::new();
+        result?;
+        Ok(unlocked?)
+    }
 
-        for idx in to_visit {
-            if !target_set.contains(&idx) {
-                let walker = DfsPostOrderDirectional::new(dir, &self.dependencies, idx);
-                let nodes: Vec<daggy::NodeIndex> = walker.iter(&self.dependencies).collect();
-                target_set.extend(nodes.iter());
-            }
+    /// Run the migrations planned in `direction` for the given targets,
+    /// notifying the observer, if any, along the way.
+    fn migrate_locked(
+        &mut self,
+        targets: Option<&[I]>,
+        direction: MigrationDirection,
+    ) -> Result<(), MigratorError<I, T::Error>> {
+        let start = Instant::now();
+        let graph = self.graph()?;
+        let plan = self.plan(&graph, targets, direction)?;
+        graph.plan_computed(&plan, direction, start, self.observer());
+
+        for step in &plan {
+            let running = graph.start_step(step, direction, self.observer());
+            let result = match running.action {
+                Action::Apply => self.adapter.apply_migration(running.migration),
+                Action::Reapply => self.adapter.reapply_migration(running.migration),
+                Action::MarkApplied => self.adapter.mark_applied(running.migration),
+                Action::Revert => self.adapter.revert_migration(running.migration),
+                Action::UnmarkApplied => self.adapter.unmark_applied(running.migration),
+            };
+            running.finish(result, self.observer())?;
         }
 
-        Ok(target_set)
+        Ok(())
     }
 
     /// Apply migrations as necessary to so that the specified migration is
     /// applied (inclusive).
     ///
     /// If `to` is `None`, apply all registered migrations.
+    ///
+    /// Migrations are applied after their dependencies. Among migrations that
+    /// are ready to be applied, the one registered first is applied first, so
+    /// the order is the same on every run.
     pub fn up(&mut self, to: Option<I>) -> Result<(), MigratorError<I, T::Error>> {
         if let Some(to) = &to {
             info!("Migrating up to target: {}", to);
@@ -348,35 +849,7 @@ where
             info!("Migrating everything");
         }
 
-        // Register the edges
-        self.register_edges()?;
-
-        let target_idxs = self
-            .induced_stream(to, EdgeDirection::Incoming)
-            .map_err(MigratorError::Dependency)?;
-
-        // TODO: This is assuming the applied_migrations state is consistent
-        // with the dependency graph.
-        let applied_migrations = self.adapter.applied_migrations()?;
-        for idx in target_idxs {
-            let migration = &self.dependencies[idx];
-            let id = migration.id();
-            if applied_migrations.contains(&id) {
-                continue;
-            }
-
-            info!("Applying migration {}", id);
-            self.adapter
-                .apply_migration(migration)
-                .map_err(|e| MigratorError::Migration {
-                    id,
-                    description: migration.description(),
-                    direction: MigrationDirection::Up,
-                    error: e,
-                })?;
-        }
-
-        Ok(())
+        self.migrate(to.as_ref().map(slice::from_ref), MigrationDirection::Up)
     }
 
     /// Revert migrations as necessary so that no migrations dependent on the
@@ -384,65 +857,77 @@ where
     /// applied, it will still be applied.
     ///
     /// If `to` is `None`, revert all applied migrations.
+    ///
+    /// Migrations are reverted in the reverse of the order in which
+    /// [`Migrator::up`] would apply them.
     pub fn down(&mut self, to: Option<I>) -> Result<(), MigratorError<I, T::Error>> {
         if let Some(to) = &to {
-            info!("Migrating up to target: {}", to);
+            info!("Migrating down to target: {}", to);
         } else {
             info!("Migrating everything");
         }
 
-        // Register the edges
-        self.register_edges()?;
+        self.migrate(to.as_ref().map(slice::from_ref), MigrationDirection::Down)
+    }
 
-        let mut target_idxs = self
-            .induced_stream(to.clone(), EdgeDirection::Outgoing)
-            .map_err(MigratorError::Dependency)?;
-        if let Some(sink_id) = to {
-            target_idxs.swap_remove(
-                self.id_map
-                    .get(&sink_id)
-                    .expect("Id is checked in induced_stream and exists"),
-            );
-        }
+    /// Apply migrations as necessary so that all of the specified migrations
+    /// are applied (inclusive), in a single pass.
+    ///
+    /// This applies the union of the migrations [`Migrator::up`] would apply
+    /// for each target, in the same order as `up`. If `targets` is empty,
+    /// nothing is applied.
+    pub fn up_many(
+        &mut self,
+        targets: impl IntoIterator<Item = I>,
+    ) -> Result<(), MigratorError<I, T::Error>> {
+        let targets: Vec<_> = targets.into_iter().collect();
+        info!("Migrating up to {} targets", targets.len());
 
-        let applied_migrations = self.adapter.applied_migrations()?;
-        for idx in target_idxs {
-            let migration = &self.dependencies[idx];
-            let id = migration.id();
-            if !applied_migrations.contains(&id) {
-                continue;
-            }
+        self.migrate(Some(&targets), MigrationDirection::Up)
+    }
 
-            info!("Reverting migration {}", id);
-            self.adapter
-                .revert_migration(migration)
-                .map_err(|e| MigratorError::Migration {
-                    id,
-                    description: migration.description(),
-                    direction: MigrationDirection::Down,
-                    error: e,
-                })?;
-        }
+    /// Revert migrations as necessary so that no migrations dependent on any of
+    /// the specified migrations are applied, in a single pass.
+    ///
+    /// This reverts the union of the migrations [`Migrator::down`] would revert
+    /// for each target, in the same order as `down`. A target is reverted only
+    /// if it depends on another target. If `targets` is empty, nothing is
+    /// reverted.
+    pub fn down_many(
+        &mut self,
+        targets: impl IntoIterator<Item = I>,
+    ) -> Result<(), MigratorError<I, T::Error>> {
+        let targets: Vec<_> = targets.into_iter().collect();
+        info!("Migrating down to {} targets", targets.len());
 
-        Ok(())
+        self.migrate(Some(&targets), MigrationDirection::Down)
     }
 }
 
 #[cfg(test)]
 pub mod tests {
     use std::cell::RefCell;
+    use std::future::{self, Future};
+    use std::pin::pin;
+    use std::task::{Context, Poll, Wake};
 
     use super::testing::*;
     use super::*;
 
     struct DefaultTestAdapter {
         applied_migrations: HashSet<usize>,
+        fingerprints: HashMap<usize, Vec<u8>>,
+        failing_migration: Option<usize>,
+        locked: bool,
     }
 
     impl DefaultTestAdapter {
         fn new() -> DefaultTestAdapter {
             DefaultTestAdapter {
                 applied_migrations: HashSet::new(),
+                fingerprints: HashMap::new(),
+                failing_migration: None,
+                locked: false,
             }
         }
     }
@@ -460,13 +945,50 @@ pub mod tests {
             Ok(self.applied_migrations.clone())
         }
 
+        fn applied_fingerprints(&mut self) -> Result<HashMap<usize, Vec<u8>>, Self::Error> {
+            Ok(self.fingerprints.clone())
+        }
+
         fn apply_migration(&mut self, migration: &Self::MigrationType) -> Result<(), Self::Error> {
+            assert!(self.locked);
+            if self.failing_migration == Some(migration.id()) {
+                return Err(DefaultTestAdapterError);
+            }
             self.applied_migrations.insert(migration.id());
+            if let Some(fingerprint) = migration.fingerprint() {
+                self.fingerprints.insert(migration.id(), fingerprint);
+            }
             Ok(())
         }
 
         fn revert_migration(&mut self, migration: &Self::MigrationType) -> Result<(), Self::Error> {
+            assert!(self.locked);
             self.applied_migrations.remove(&migration.id());
+            self.fingerprints.remove(&migration.id());
+            Ok(())
+        }
+
+        fn mark_applied(&mut self, migration: &Self::MigrationType) -> Result<(), Self::Error> {
+            self.applied_migrations.insert(migration.id());
+            if let Some(fingerprint) = migration.fingerprint() {
+                self.fingerprints.insert(migration.id(), fingerprint);
+            }
+            Ok(())
+        }
+
+        fn unmark_applied(&mut self, migration: &Self::MigrationType) -> Result<(), Self::Error> {
+            Adapter::revert_migration(self, migration)
+        }
+
+        fn lock(&mut self) -> Result<(), Self::Error> {
+            assert!(!self.locked);
+            self.locked = true;
+            Ok(())
+        }
+
+        fn unlock(&mut self) -> Result<(), Self::Error> {
+            assert!(self.locked);
+            self.locked = false;
             Ok(())
         }
     }
@@ -475,6 +997,14 @@ pub mod tests {
         fn mock(id: usize, dependencies: HashSet<usize>) -> Self::MigrationType {
             TestMigration::new(id, dependencies)
         }
+
+        fn mock_baseline(
+            id: usize,
+            dependencies: HashSet<usize>,
+            replaces: HashSet<usize>,
+        ) -> Option<Self::MigrationType> {
+            Some(TestMigration::new(id, dependencies).with_replaces(replaces))
+        }
     }
 
     test_schemerz_adapter!(DefaultTestAdapter::new(), 0..);
@@ -541,6 +1071,795 @@ pub mod tests {
             (migration.check_fn_down)();
             Ok(())
         }
+
+        fn mark_applied(&mut self, migration: &Self::MigrationType) -> Result<(), Self::Error> {
+            self.applied_migrations.insert(migration.id());
+            Ok(())
+        }
+
+        fn unmark_applied(&mut self, migration: &Self::MigrationType) -> Result<(), Self::Error> {
+            self.applied_migrations.remove(&migration.id());
+            Ok(())
+        }
+    }
+
+    fn chain_migrator(applied: &[usize]) -> Migrator<usize, DefaultTestAdapter> {
+        let mut adapter = DefaultTestAdapter::new();
+        adapter.applied_migrations.extend(applied);
+
+        let mut migrator = Migrator::new(adapter);
+        migrator
+            .register_multiple(
+                [
+                    TestMigration::new(1, HashSet::new()),
+                    TestMigration::new(2, [1].into_iter().collect()),
+                    TestMigration::new(3, [2].into_iter().collect()),
+                ]
+                .into_iter(),
+            )
+            .unwrap();
+        migrator
+    }
+
+    #[test]
+    fn test_status() {
+        let mut migrator = chain_migrator(&[1, 7]);
+
+        let status = migrator.status().unwrap();
+        assert_eq!(
+            status.applied.iter().map(|m| m.id).collect::<Vec<_>>(),
+            vec![1]
+        );
+        assert_eq!(
+            status.pending.iter().map(|m| m.id).collect::<Vec<_>>(),
+            vec![2, 3]
+        );
+        assert_eq!(status.pending[1].dependencies, [2].into_iter().collect());
+        assert_eq!(status.unknown, vec![7]);
+
+        migrator.adapter.applied_migrations.remove(&7);
+        migrator.up(None).unwrap();
+        let status = migrator.status().unwrap();
+        assert_eq!(status.applied.len(), 3);
+        assert!(status.pending.is_empty());
+        assert!(status.unknown.is_empty());
+    }
+
+    #[test]
+    fn test_validate_applied_state() {
+        let mut migrator = chain_migrator(&[1, 2]);
+        migrator.validate().unwrap();
+
+        let mut migrator = chain_migrator(&[1, 3]);
+        assert!(matches!(
+            migrator.validate(),
+            Err(MigratorError::Dependency(
+                DependencyError::UnappliedDependency {
+                    id: 3,
+                    dependency: 2
+                }
+            ))
+        ));
+        assert!(matches!(
+            migrator.up(None),
+            Err(MigratorError::Dependency(
+                DependencyError::UnappliedDependency { .. }
+            ))
+        ));
+        assert!(matches!(
+            migrator.down(None),
+            Err(MigratorError::Dependency(
+                DependencyError::UnappliedDependency { .. }
+            ))
+        ));
+        assert_eq!(
+            migrator.adapter.applied_migrations,
+            [1, 3].into_iter().collect()
+        );
+
+        let mut migrator = chain_migrator(&[1, 4]);
+        assert!(matches!(
+            migrator.up(None),
+            Err(MigratorError::Dependency(DependencyError::UnknownApplied(
+                4
+            )))
+        ));
+        assert!(matches!(
+            migrator.down(None),
+            Err(MigratorError::Dependency(DependencyError::UnknownApplied(
+                4
+            )))
+        ));
+    }
+
+    #[test]
+    fn test_fingerprint_drift() {
+        let mut adapter = DefaultTestAdapter::new();
+        adapter.applied_migrations.extend([1, 2]);
+        adapter.fingerprints.insert(1, b"v1".to_vec());
+        adapter.fingerprints.insert(2, b"v1".to_vec());
+
+        let mut migrator = Migrator::new(adapter);
+        migrator
+            .register_multiple(
+                [
+                    TestMigration::new(1, HashSet::new()).with_fingerprint(b"v1".to_vec()),
+                    TestMigration::new(2, [1].into_iter().collect())
+                        .with_fingerprint(b"v2".to_vec()),
+                    TestMigration::new(3, [2].into_iter().collect()),
+                ]
+                .into_iter(),
+            )
+            .unwrap();
+
+        assert!(matches!(
+            migrator.validate(),
+            Err(MigratorError::Drift { id: 2, .. })
+        ));
+        assert!(matches!(
+            migrator.up(None),
+            Err(MigratorError::Drift { id: 2, .. })
+        ));
+        assert!(!migrator.adapter.applied_migrations.contains(&3));
+        assert_eq!(migrator.status().unwrap().drifted, vec![2]);
+
+        // A migration applied without a fingerprint is not checked.
+        migrator.adapter.fingerprints.remove(&2);
+        migrator.up(None).unwrap();
+        assert!(migrator.status().unwrap().drifted.is_empty());
+    }
+
+    #[test]
+    fn test_status_order() {
+        let ids = [5, 3, 8, 1, 7, 2, 6, 4];
+        let mut adapter = DefaultTestAdapter::new();
+        adapter.applied_migrations.extend(ids);
+        adapter.applied_migrations.extend([12, 9, 30, 11]);
+        for id in ids {
+            adapter.fingerprints.insert(id, b"v1".to_vec());
+        }
+
+        let mut migrator = Migrator::new(adapter);
+        migrator
+            .register_multiple(
+                ids.into_iter().map(|id| {
+                    TestMigration::new(id, HashSet::new()).with_fingerprint(b"v2".to_vec())
+                }),
+            )
+            .unwrap();
+
+        // Unknown migrations are sorted by ID, and drifted ones are in order of
+        // registration, whatever the order of the adapter's sets.
+        let status = migrator.status().unwrap();
+        assert_eq!(status.unknown, vec![11, 12, 30, 9]);
+        assert_eq!(status.drifted, ids);
+        assert!(matches!(
+            migrator.validate(),
+            Err(MigratorError::Dependency(DependencyError::UnknownApplied(
+                11
+            )))
+        ));
+
+        for id in [12, 9, 30, 11] {
+            migrator.adapter.applied_migrations.remove(&id);
+        }
+        assert!(matches!(
+            migrator.validate(),
+            Err(MigratorError::Drift { id: 5, .. })
+        ));
+    }
+
+    #[test]
+    fn test_repeatable() {
+        let mut adapter = DefaultTestAdapter::new();
+        adapter.applied_migrations.extend([1, 2]);
+        adapter.fingerprints.insert(2, b"v1".to_vec());
+
+        let mut migrator = Migrator::new(adapter);
+        migrator
+            .register_multiple(
+                [
+                    TestMigration::new(1, HashSet::new()),
+                    TestMigration::new(2, [1].into_iter().collect())
+                        .with_fingerprint(b"v2".to_vec())
+                        .repeatable(),
+                    TestMigration::new(3, [1].into_iter().collect()).repeatable(),
+                    TestMigration::new(4, [1].into_iter().collect()),
+                ]
+                .into_iter(),
+            )
+            .unwrap();
+
+        // A repeatable migration that has changed is applied again, rather than
+        // having drifted.
+        migrator.validate().unwrap();
+        let status = migrator.status().unwrap();
+        assert!(status.drifted.is_empty());
+        assert_eq!(status.outdated, vec![2]);
+
+        // Repeatable migrations run after the others, even if registered first.
+        let plan = migrator.plan_up(None).unwrap();
+        assert_eq!(
+            plan.iter()
+                .map(|step| (step.id, step.reapply))
+                .collect::<Vec<_>>(),
+            [(4, false), (2, true), (3, false)]
+        );
+
+        migrator.up(None).unwrap();
+        assert_eq!(migrator.adapter.fingerprints[&2], b"v2".to_vec());
+        assert!(migrator.status().unwrap().outdated.is_empty());
+        assert!(migrator.plan_up(None).unwrap().is_empty());
+    }
+
+    impl AsyncAdapter<usize> for DefaultTestAdapter {
+        type MigrationType = TestMigration<usize>;
+
+        type Error = DefaultTestAdapterError;
+
+        fn applied_migrations(
+            &mut self,
+        ) -> impl Future<Output = Result<HashSet<usize>, Self::Error>> + Send {
+            future::ready(Adapter::applied_migrations(self))
+        }
+
+        fn applied_fingerprints(
+            &mut self,
+        ) -> impl Future<Output = Result<HashMap<usize, Vec<u8>>, Self::Error>> + Send {
+            future::ready(Adapter::applied_fingerprints(self))
+        }
+
+        fn apply_migration(
+            &mut self,
+            migration: &Self::MigrationType,
+        ) -> impl Future<Output = Result<(), Self::Error>> + Send {
+            future::ready(Adapter::apply_migration(self, migration))
+        }
+
+        fn revert_migration(
+            &mut self,
+            migration: &Self::MigrationType,
+        ) -> impl Future<Output = Result<(), Self::Error>> + Send {
+            future::ready(Adapter::revert_migration(self, migration))
+        }
+
+        fn mark_applied(
+            &mut self,
+            migration: &Self::MigrationType,
+        ) -> impl Future<Output = Result<(), Self::Error>> + Send {
+            future::ready(Adapter::mark_applied(self, migration))
+        }
+
+        fn unmark_applied(
+            &mut self,
+            migration: &Self::MigrationType,
+        ) -> impl Future<Output = Result<(), Self::Error>> + Send {
+            future::ready(Adapter::unmark_applied(self, migration))
+        }
+
+        fn lock(&mut self) -> impl Future<Output = Result<(), Self::Error>> + Send {
+            future::ready(Adapter::lock(self))
+        }
+
+        fn unlock(&mut self) -> impl Future<Output = Result<(), Self::Error>> + Send {
+            future::ready(Adapter::unlock(self))
+        }
+    }
+
+    /// Drive a future that never waits on anything to completion.
+    fn block_on<F: Future>(fut: F) -> F::Output {
+        struct NoopWaker;
+
+        impl Wake for NoopWaker {
+            fn wake(self: Arc<Self>) {}
+        }
+
+        let waker = Arc::new(NoopWaker).into();
+        let mut cx = Context::from_waker(&waker);
+        let mut fut = pin!(fut);
+        loop {
+            if let Poll::Ready(output) = fut.as_mut().poll(&mut cx) {
+                return output;
+            }
+        }
+    }
+
+    #[test]
+    fn test_async_migrator() {
+        // Planning is shared with the synchronous migrator.
+        let expected = chain_migrator(&[1]).plan_up(None).unwrap();
+
+        let mut adapter = DefaultTestAdapter::new();
+        adapter.applied_migrations.insert(1);
+        let mut migrator = AsyncMigrator::new(adapter);
+        migrator
+            .register_multiple(
+                [
+                    TestMigration::new(1, HashSet::new()),
+                    TestMigration::new(2, [1].into_iter().collect()),
+                    TestMigration::new(3, [2].into_iter().collect()),
+                ]
+                .into_iter(),
+            )
+            .unwrap();
+
+        let plan = block_on(migrator.plan_up(None)).unwrap();
+        assert_eq!(plan.iter().map(|m| m.id).collect::<Vec<_>>(), vec![2, 3]);
+        assert_eq!(plan, expected);
+
+        block_on(migrator.up(Some(2))).unwrap();
+        let status = block_on(migrator.status()).unwrap();
+        assert_eq!(
+            status.applied.iter().map(|m| m.id).collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+        assert_eq!(
+            status.pending.iter().map(|m| m.id).collect::<Vec<_>>(),
+            vec![3]
+        );
+
+        let plan = block_on(migrator.plan_down(None)).unwrap();
+        assert_eq!(plan.iter().map(|m| m.id).collect::<Vec<_>>(), vec![2, 1]);
+        block_on(migrator.down(None)).unwrap();
+
+        block_on(migrator.up_many([2, 3])).unwrap();
+        block_on(migrator.down_many([1])).unwrap();
+        assert_eq!(
+            migrator.into_adapter().applied_migrations,
+            [1].into_iter().collect()
+        );
+    }
+
+    #[test]
+    fn test_async_reapply_by_default() {
+        let mut adapter = DefaultTestAdapter::new();
+        adapter.applied_migrations.insert(1);
+        adapter.fingerprints.insert(1, b"v1".to_vec());
+        let mut migrator = AsyncMigrator::new(adapter);
+        migrator
+            .register(
+                TestMigration::new(1, HashSet::new())
+                    .with_fingerprint(b"v2".to_vec())
+                    .repeatable(),
+            )
+            .unwrap();
+
+        block_on(migrator.up(None)).unwrap();
+        let adapter = migrator.into_adapter();
+        assert_eq!(adapter.applied_migrations, [1].into_iter().collect());
+        assert_eq!(adapter.fingerprints[&1], b"v2".to_vec());
+    }
+
+    #[test]
+    fn test_graph_rendering() {
+        let mut migrator = chain_migrator(&[1]);
+
+        // Rendering twice must not duplicate edges.
+        migrator.to_dot(false).unwrap();
+        assert_eq!(
+            migrator.to_dot(false).unwrap(),
+            "digraph migrations {
+    m0 [label=\"1\\nTest Migration\"];
+    m1 [label=\"2\\nTest Migration\"];
+    m2 [label=\"3\\nTest Migration\"];
+    m0 -> m1;
+    m1 -> m2;
+}
+"
+        );
+        assert!(migrator
+            .to_dot(true)
+            .unwrap()
+            .contains("m0 [label=\"1\\nTest Migration\", style=filled, fillcolor=palegreen];"));
+
+        assert_eq!(
+            migrator.to_mermaid(true).unwrap(),
+            "graph TD
+    m0[\"1<br/>Test Migration\"]
+    m1[\"2<br/>Test Migration\"]
+    m2[\"3<br/>Test Migration\"]
+    m0 --> m1
+    m1 --> m2
+    classDef applied fill:#98fb98
+    classDef pending fill:#d3d3d3
+    class m0 applied
+    class m1 pending
+    class m2 pending
+"
+        );
+    }
+
+    #[test]
+    fn test_migration_graph_errors() {
+        let errors = MigrationGraph::new([
+            TestMigration::new(1, HashSet::new()),
+            TestMigration::new(1, HashSet::new()),
+            TestMigration::new(2, [1, 4].into_iter().collect()),
+            TestMigration::new(3, [2].into_iter().collect()),
+            TestMigration::new(5, [6].into_iter().collect()),
+            TestMigration::new(6, [5].into_iter().collect()),
+        ])
+        .err()
+        .unwrap()
+        .into_errors();
+
+        assert_eq!(errors.len(), 3);
+        assert!(matches!(errors[0], DependencyError::DuplicateId(1)));
+        assert!(errors
+            .iter()
+            .any(|e| matches!(e, DependencyError::UnknownId(4))));
+        assert!(errors
+            .iter()
+            .any(|e| matches!(e, DependencyError::Cycle { .. })));
+
+        let mut migrator = Migrator::new(DefaultTestAdapter::new());
+        migrator
+            .register(TestMigration::new(2, [1].into_iter().collect()))
+            .unwrap();
+        assert!(matches!(
+            migrator.up(None),
+            Err(MigratorError::Graph(ref e)) if e.errors().len() == 1
+        ));
+        // Registering the missing dependency fixes the graph.
+        migrator
+            .register(TestMigration::new(1, HashSet::new()))
+            .unwrap();
+        migrator.up(None).unwrap();
+    }
+
+    #[test]
+    fn test_shared_graph() {
+        let graph = Arc::new(
+            MigrationGraph::new([
+                TestMigration::new(1, HashSet::new()),
+                TestMigration::new(2, [1].into_iter().collect()),
+                TestMigration::new(3, [2].into_iter().collect()),
+            ])
+            .unwrap(),
+        );
+        assert_eq!(graph.len(), 3);
+        assert!(graph.contains(&2));
+
+        for _ in 0..2 {
+            let mut migrator = Migrator::with_graph(DefaultTestAdapter::new(), graph.clone());
+            for _ in 0..2 {
+                migrator.up(None).unwrap();
+                migrator.down(Some(1)).unwrap();
+            }
+            migrator.up(Some(2)).unwrap();
+            assert_eq!(
+                migrator.into_adapter().applied_migrations,
+                [1, 2].into_iter().collect()
+            );
+        }
+
+        let mut migrator = Migrator::with_graph(DefaultTestAdapter::new(), graph.clone());
+        assert!(matches!(
+            migrator.register(TestMigration::new(4, HashSet::new())),
+            Err(MigratorError::SharedGraph(4))
+        ));
+        drop(graph);
+        migrator
+            .register(TestMigration::new(4, [3].into_iter().collect()))
+            .unwrap();
+        assert_eq!(migrator.graph().unwrap().len(), 4);
+        assert_eq!(migrator.plan_up(None).unwrap().len(), 4);
+    }
+
+    #[test]
+    fn test_graph_leaves() {
+        let graph = MigrationGraph::new([
+            TestMigration::new(1, HashSet::new()),
+            TestMigration::new(2, [1].into_iter().collect()),
+            TestMigration::new(3, [1].into_iter().collect()),
+            TestMigration::new(4, [2].into_iter().collect()),
+        ])
+        .unwrap();
+        let leaves: Vec<_> = graph.leaves().map(|m| m.id()).collect();
+        assert_eq!(leaves, [3, 4]);
+
+        let empty = MigrationGraph::<usize, TestMigration<usize>>::new([]).unwrap();
+        assert_eq!(empty.leaves().count(), 0);
+    }
+
+    #[test]
+    fn test_deterministic_order() {
+        // Repeat with fresh hash sets, whose iteration order varies.
+        for _ in 0..20 {
+            let mut migrator = Migrator::new(DefaultTestAdapter::new());
+            migrator
+                .register_multiple(
+                    [
+                        TestMigration::new(5, HashSet::new()),
+                        TestMigration::new(3, HashSet::new()),
+                        TestMigration::new(4, HashSet::new()),
+                        TestMigration::new(2, [3, 4].into_iter().collect()),
+                        TestMigration::new(1, [5].into_iter().collect()),
+                    ]
+                    .into_iter(),
+                )
+                .unwrap();
+
+            let ids = |plan: Vec<PlannedMigration<usize>>| -> Vec<_> {
+                plan.into_iter().map(|m| m.id).collect()
+            };
+            assert_eq!(ids(migrator.plan_up(None).unwrap()), [5, 3, 4, 2, 1]);
+            migrator.up(None).unwrap();
+            assert_eq!(ids(migrator.plan_down(None).unwrap()), [1, 2, 4, 3, 5]);
+        }
+    }
+
+    #[test]
+    fn test_multiple_targets() {
+        let mut migrator = Migrator::new(DefaultTestAdapter::new());
+        migrator
+            .register_multiple(
+                [
+                    TestMigration::new(1, HashSet::new()),
+                    TestMigration::new(2, [1].into_iter().collect()),
+                    TestMigration::new(3, HashSet::new()),
+                    TestMigration::new(4, [3].into_iter().collect()),
+                    TestMigration::new(5, HashSet::new()),
+                    TestMigration::new(6, [2, 4].into_iter().collect()),
+                ]
+                .into_iter(),
+            )
+            .unwrap();
+
+        let ids = |plan: Vec<PlannedMigration<usize>>| -> Vec<_> {
+            plan.into_iter().map(|m| m.id).collect()
+        };
+        assert!(migrator.plan_up_many([]).unwrap().is_empty());
+        assert_eq!(ids(migrator.plan_up_many([4, 2]).unwrap()), [1, 2, 3, 4]);
+        migrator.up_many([4, 2]).unwrap();
+        assert_eq!(ids(migrator.plan_up(None).unwrap()), [5, 6]);
+
+        migrator.up(None).unwrap();
+        assert_eq!(ids(migrator.plan_down_many([2, 3]).unwrap()), [6, 4]);
+        // A target that depends on another target is reverted.
+        assert_eq!(ids(migrator.plan_down_many([1, 2]).unwrap()), [6, 2]);
+        migrator.down_many([1, 5]).unwrap();
+        assert_eq!(
+            migrator.into_adapter().applied_migrations,
+            [1, 3, 4, 5].into_iter().collect()
+        );
+
+        let mut migrator = chain_migrator(&[]);
+        assert!(matches!(
+            migrator.up_many([1, 7]),
+            Err(MigratorError::Dependency(DependencyError::UnknownId(7)))
+        ));
+    }
+
+    #[test]
+    fn test_irreversible_down() {
+        let mut adapter = DefaultTestAdapter::new();
+        adapter.applied_migrations.extend([1, 2, 3]);
+        let mut migrator = Migrator::new(adapter);
+        migrator
+            .register_multiple(
+                [
+                    TestMigration::new(1, HashSet::new()),
+                    TestMigration::new(2, [1].into_iter().collect()).irreversible(),
+                    TestMigration::new(3, [2].into_iter().collect()),
+                ]
+                .into_iter(),
+            )
+            .unwrap();
+
+        // Reverting past the irreversible migration is refused before anything
+        // is reverted.
+        assert!(matches!(
+            migrator.down(Some(1)),
+            Err(MigratorError::Irreversible { ids }) if ids == vec![2]
+        ));
+        assert!(matches!(
+            migrator.plan_down(None),
+            Err(MigratorError::Irreversible { ids }) if ids == vec![2]
+        ));
+        assert_eq!(migrator.adapter.applied_migrations.len(), 3);
+
+        // Migrations that only depend on it can still be reverted.
+        migrator.down(Some(2)).unwrap();
+        assert_eq!(
+            migrator.adapter.applied_migrations,
+            [1, 2].into_iter().collect()
+        );
+    }
+
+    #[test]
+    fn test_mark_applied() {
+        let mut migrator = Migrator::new(DefaultTestAdapter::new());
+        migrator
+            .register_multiple(
+                [
+                    TestMigration::new(1, HashSet::new()),
+                    TestMigration::new(2, [1].into_iter().collect()),
+                ]
+                .into_iter(),
+            )
+            .unwrap();
+
+        assert!(matches!(
+            migrator.mark_applied(2),
+            Err(MigratorError::Dependency(
+                DependencyError::UnappliedDependency {
+                    id: 2,
+                    dependency: 1
+                }
+            ))
+        ));
+        assert!(matches!(
+            migrator.mark_applied(3),
+            Err(MigratorError::Dependency(DependencyError::UnknownId(3)))
+        ));
+
+        migrator.mark_applied(1).unwrap();
+        migrator.mark_applied(1).unwrap();
+        migrator.mark_applied(2).unwrap();
+        assert_eq!(
+            migrator.adapter.applied_migrations,
+            [1, 2].into_iter().collect()
+        );
+        assert!(!migrator.adapter.locked);
+    }
+
+    /// Migrations 1 <- 2 <- 3 squashed into baseline 10, with 4 depending on 3.
+    fn baseline_migrator(applied: &[usize]) -> Migrator<usize, DefaultTestAdapter> {
+        let mut adapter = DefaultTestAdapter::new();
+        adapter.applied_migrations.extend(applied);
+
+        let mut migrator = Migrator::new(adapter);
+        migrator
+            .register_multiple(
+                [
+                    TestMigration::new(1, HashSet::new()),
+                    TestMigration::new(2, [1].into_iter().collect()),
+                    TestMigration::new(3, [2].into_iter().collect()),
+                    TestMigration::new(4, [3].into_iter().collect()),
+                    TestMigration::new(10, HashSet::new())
+                        .with_replaces([1, 2, 3].into_iter().collect()),
+                ]
+                .into_iter(),
+            )
+            .unwrap();
+        migrator
+    }
+
+    fn plan_steps(plan: Vec<PlannedMigration<usize>>) -> Vec<(usize, bool)> {
+        plan.into_iter()
+            .map(|step| (step.id, step.recorded_only))
+            .collect()
+    }
+
+    #[test]
+    fn test_baseline() {
+        // On an empty database only the baseline is run.
+        let mut migrator = baseline_migrator(&[]);
+        assert_eq!(
+            plan_steps(migrator.plan_up(None).unwrap()),
+            vec![(10, false), (1, true), (2, true), (3, true), (4, false)]
+        );
+        migrator.up(None).unwrap();
+        migrator.validate().unwrap();
+        assert_eq!(migrator.adapter.applied_migrations.len(), 5);
+
+        // Reverting the replaced migrations also reverts the baseline.
+        assert_eq!(
+            plan_steps(migrator.plan_down(None).unwrap()),
+            vec![(4, false), (3, false), (2, false), (1, false), (10, true)]
+        );
+        migrator.down(None).unwrap();
+        assert!(migrator.adapter.applied_migrations.is_empty());
+
+        // Databases that have some of the replaced migrations walk the
+        // original path, and the baseline is recorded once it is complete.
+        let mut migrator = baseline_migrator(&[1]);
+        assert_eq!(
+            plan_steps(migrator.plan_up(Some(2)).unwrap()),
+            vec![(2, false)]
+        );
+        assert_eq!(
+            plan_steps(migrator.plan_up(None).unwrap()),
+            vec![(2, false), (3, false), (10, true), (4, false)]
+        );
+        migrator.up(None).unwrap();
+        migrator.validate().unwrap();
+        assert_eq!(migrator.adapter.applied_migrations.len(), 5);
+
+        // Recording a baseline as applied also records what it replaces.
+        let mut migrator = baseline_migrator(&[1]);
+        migrator.mark_applied(10).unwrap();
+        assert_eq!(
+            migrator.adapter.applied_migrations,
+            [1, 2, 3, 10].into_iter().collect()
+        );
+        assert_eq!(
+            plan_steps(migrator.plan_up(None).unwrap()),
+            vec![(4, false)]
+        );
+    }
+
+    #[derive(Clone, Default)]
+    struct RecordingObserver {
+        events: Rc<RefCell<Vec<String>>>,
+    }
+
+    impl MigrationObserver<usize> for RecordingObserver {
+        fn plan_computed(&mut self, plan: &[PlannedMigration<usize>], _: Duration) {
+            let ids = plan.iter().map(|m| m.id.to_string()).collect::<Vec<_>>();
+            self.events
+                .borrow_mut()
+                .push(format!("plan [{}]", ids.join(", ")));
+        }
+
+        fn before_apply(&mut self, migration: &PlannedMigration<usize>) {
+            self.events
+                .borrow_mut()
+                .push(format!("before_apply {}", migration.id));
+        }
+
+        fn after_apply(&mut self, migration: &PlannedMigration<usize>, _: Duration) {
+            self.events
+                .borrow_mut()
+                .push(format!("after_apply {}", migration.id));
+        }
+
+        fn before_revert(&mut self, migration: &PlannedMigration<usize>) {
+            self.events
+                .borrow_mut()
+                .push(format!("before_revert {}", migration.id));
+        }
+
+        fn after_revert(&mut self, migration: &PlannedMigration<usize>, _: Duration) {
+            self.events
+                .borrow_mut()
+                .push(format!("after_revert {}", migration.id));
+        }
+
+        fn failed(
+            &mut self,
+            migration: &PlannedMigration<usize>,
+            _: Duration,
+            error: &dyn std::error::Error,
+        ) {
+            self.events
+                .borrow_mut()
+                .push(format!("failed {}: {}", migration.id, error));
+        }
+    }
+
+    #[test]
+    fn test_observer() {
+        let observer = RecordingObserver::default();
+        let mut migrator = chain_migrator(&[]).with_observer(observer.clone());
+
+        migrator.adapter.failing_migration = Some(3);
+        assert!(matches!(
+            migrator.up(None),
+            Err(MigratorError::Migration { id: 3, .. })
+        ));
+        assert!(!migrator.adapter.locked);
+        migrator.adapter.failing_migration = None;
+        migrator.down(None).unwrap();
+
+        assert_eq!(
+            *observer.events.borrow(),
+            vec![
+                "plan [1, 2, 3]",
+                "before_apply 1",
+                "after_apply 1",
+                "before_apply 2",
+                "after_apply 2",
+                "before_apply 3",
+                "failed 3: An error occurred.",
+                "plan [2, 1]",
+                "before_revert 2",
+                "after_revert 2",
+                "before_revert 1",
+                "after_revert 1",
+            ]
+        );
     }
 
     #[test]
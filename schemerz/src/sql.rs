@@ -0,0 +1,467 @@
+//! Migrations loaded from a directory of SQL files.
+//!
+//! Each migration is a file named `<uuid>_<name>.up.sql`, containing the SQL
+//! that applies it, and optionally a file named `<uuid>_<name>.down.sql`,
+//! containing the SQL that reverts it. Migrations without a `.down.sql` file
+//! are irreversible. Other files in the directory are ignored.
+//!
+//! The dependencies and description of a migration are declared in comments
+//! at the top of its `.up.sql` file:
+//!
+//! ```sql
+//! -- description: Add an index on users by email.
+//! -- depends_on: 4885e8ab-dafa-4d76-a565-2dee8b04ef60, 0b0a4ca6-4a5b-4b8e-9fd4-29b0bd4d48ee
+//! -- transactional: false
+//! CREATE INDEX CONCURRENTLY users_email ON users (email);
+//! ```
+//!
+//! or instead in a sidecar file named `<uuid>_<name>.toml`, in which case the
+//! comments are not read:
+//!
+//! ```toml
+//! description = "Add an index on users by email."
+//! depends_on = [
+//!     "4885e8ab-dafa-4d76-a565-2dee8b04ef60",
+//!     "0b0a4ca6-4a5b-4b8e-9fd4-29b0bd4d48ee",
+//! ]
+//! transactional = false
+//! ```
+//!
+//! Other comments in the header are ignored, but one that starts with an
+//! unknown lowercase `key:`, such as a misspelled `depend_on:`, is rejected,
+//! as the sidecar rejects unknown keys.
+//!
+//! Every key is optional. The description defaults to the name of the
+//! migration, and `transactional` defaults to `true`; adapters that cannot run
+//! migrations outside a transaction ignore it. Setting `repeatable` to `true`
+//! makes the migration repeatable (see [`Migration::is_repeatable`]), so that
+//! it is applied again whenever the SQL in its `.up.sql` file changes.
+//! Editing only the header comments is not a change, neither for repeatable
+//! migrations nor for drift.
+//!
+//! The adapters turn these into migrations they can run; see
+//! `schemerz_postgres::load_sql_migrations` and
+//! `schemerz_rusqlite::load_sql_migrations`. To instead embed the migrations
+//! in the binary at compile time, use `schemerz_macros::embed_migrations!`.
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::Migration;
+
+const UP_SUFFIX: &str = ".up.sql";
+const DOWN_SUFFIX: &str = ".down.sql";
+const SIDECAR_SUFFIX: &str = ".toml";
+
+/// Error resulting from loading a directory of SQL migrations.
+#[derive(Debug, Error)]
+pub enum LoadError {
+    #[error("Failed to read {}: {source}", .path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("Invalid migration file name {}: expected <uuid>_<name>.up.sql", .0.display())]
+    FileName(PathBuf),
+    #[error("Migration file {} has no matching .up.sql file", .0.display())]
+    MissingUp(PathBuf),
+    #[error("Invalid migration header in {}: {message}", .path.display())]
+    Header { path: PathBuf, message: String },
+    #[error("Invalid migration sidecar {}: {source}", .path.display())]
+    Sidecar {
+        path: PathBuf,
+        #[source]
+        source: toml::de::Error,
+    },
+}
+
+/// A migration loaded from SQL files by [`load_dir`], or embedded by
+/// `schemerz_macros::embed_migrations!`.
+#[derive(Clone, Debug)]
+pub struct SqlMigration {
+    id: Uuid,
+    name: Cow<'static, str>,
+    description: &'static str,
+    dependencies: HashSet<Uuid>,
+    transactional: bool,
+    repeatable: bool,
+    up: Cow<'static, str>,
+    down: Option<Cow<'static, str>>,
+}
+
+impl SqlMigration {
+    /// Construct a migration whose SQL is embedded in the binary.
+    ///
+    /// This is used by `schemerz_macros::embed_migrations!`, and is not part of
+    /// the public API.
+    #[doc(hidden)]
+    #[allow(clippy::too_many_arguments)]
+    pub fn embedded(
+        id: u128,
+        name: &'static str,
+        description: &'static str,
+        dependencies: &[u128],
+        transactional: bool,
+        repeatable: bool,
+        up: &'static str,
+        down: Option<&'static str>,
+    ) -> Self {
+        SqlMigration {
+            id: Uuid::from_u128(id),
+            name: name.into(),
+            description,
+            dependencies: dependencies.iter().copied().map(Uuid::from_u128).collect(),
+            transactional,
+            repeatable,
+            up: up.into(),
+            down: down.map(Cow::from),
+        }
+    }
+
+    /// Returns the name of this migration, taken from its file name.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the SQL that applies this migration.
+    pub fn up_sql(&self) -> &str {
+        &self.up
+    }
+
+    /// Returns the SQL that reverts this migration, if it is reversible.
+    pub fn down_sql(&self) -> Option<&str> {
+        self.down.as_deref()
+    }
+
+    /// Whether this migration should run inside a transaction.
+    pub fn is_transactional(&self) -> bool {
+        self.transactional
+    }
+}
+
+impl Migration<Uuid> for SqlMigration {
+    fn id(&self) -> Uuid {
+        self.id
+    }
+
+    fn dependencies(&self) -> HashSet<Uuid> {
+        self.dependencies.clone()
+    }
+
+    fn description(&self) -> &'static str {
+        self.description
+    }
+
+    /// The SHA-256 hash of the SQL that applies this migration, without the
+    /// header comments, so that editing only the header is not drift.
+    fn fingerprint(&self) -> Option<Vec<u8>> {
+        Some(Sha256::digest(split_header(&self.up).1.as_bytes()).to_vec())
+    }
+
+    fn is_reversible(&self) -> bool {
+        self.down.is_some()
+    }
+
+    fn is_repeatable(&self) -> bool {
+        self.repeatable
+    }
+}
+
+/// The metadata of a migration, declared in a sidecar file or header comments.
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Metadata {
+    description: Option<String>,
+    #[serde(default)]
+    depends_on: Vec<Uuid>,
+    transactional: Option<bool>,
+    repeatable: Option<bool>,
+}
+
+/// Load every migration in `dir`, in order of file name.
+///
+/// Descriptions are leaked, since [`Migration::description`] returns a
+/// `&'static str`, so this should be called once rather than repeatedly.
+pub fn load_dir(dir: impl AsRef<Path>) -> Result<Vec<SqlMigration>, LoadError> {
+    let dir = dir.as_ref();
+    let io_error = |path: &Path| {
+        let path = path.to_owned();
+        move |source| LoadError::Io { path, source }
+    };
+
+    // Migrations are keyed by the file name stem they share, so that they are
+    // registered in a deterministic order.
+    let mut ups = BTreeMap::new();
+    let mut downs = BTreeMap::new();
+    for entry in fs::read_dir(dir).map_err(io_error(dir))? {
+        let path = entry.map_err(io_error(dir))?.path();
+        let Some(file_name) = path.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        if let Some(stem) = file_name.strip_suffix(UP_SUFFIX) {
+            ups.insert(stem.to_owned(), path);
+        } else if let Some(stem) = file_name.strip_suffix(DOWN_SUFFIX) {
+            downs.insert(stem.to_owned(), path);
+        }
+    }
+    if let Some(path) = downs.values().find(|path| {
+        let file_name = path.file_name().and_then(|name| name.to_str()).unwrap();
+        !ups.contains_key(file_name.strip_suffix(DOWN_SUFFIX).unwrap())
+    }) {
+        return Err(LoadError::MissingUp(path.clone()));
+    }
+
+    ups.into_iter()
+        .map(|(stem, up_path)| {
+            let (id, name) = match stem.split_once('_') {
+                Some((id, name)) => (id, name),
+                None => (stem.as_str(), ""),
+            };
+            let id = Uuid::parse_str(id).map_err(|_| LoadError::FileName(up_path.clone()))?;
+
+            let up = fs::read_to_string(&up_path).map_err(io_error(&up_path))?;
+            let down = downs
+                .get(&stem)
+                .map(|path| fs::read_to_string(path).map_err(io_error(path)))
+                .transpose()?;
+
+            let sidecar_path = dir.join(format!("{}{}", stem, SIDECAR_SUFFIX));
+            let metadata = match fs::read_to_string(&sidecar_path) {
+                Ok(sidecar) => toml::from_str(&sidecar).map_err(|source| LoadError::Sidecar {
+                    path: sidecar_path,
+                    source,
+                })?,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    parse_header(&up).map_err(|message| LoadError::Header {
+                        path: up_path.clone(),
+                        message,
+                    })?
+                }
+                Err(e) => return Err(io_error(&sidecar_path)(e)),
+            };
+
+            let description = metadata.description.unwrap_or_else(|| name.to_owned());
+            Ok(SqlMigration {
+                id,
+                name: name.to_owned().into(),
+                description: Box::leak(description.into_boxed_str()),
+                dependencies: metadata.depends_on.into_iter().collect(),
+                transactional: metadata.transactional.unwrap_or(true),
+                repeatable: metadata.repeatable.unwrap_or(false),
+                up: up.into(),
+                down: down.map(Cow::from),
+            })
+        })
+        .collect()
+}
+
+/// Parse the metadata declared in the comments at the top of an `.up.sql`
+/// file. Comments that do not declare a key are ignored, and ones that declare
+/// an unknown key are rejected.
+fn parse_header(sql: &str) -> Result<Metadata, String> {
+    let mut metadata = Metadata::default();
+    for line in split_header(sql).0.lines().map(str::trim) {
+        let Some((key, value)) = line.strip_prefix("--").unwrap_or(line).split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        match key.trim() {
+            "description" => metadata.description = Some(value.to_owned()),
+            "depends_on" => {
+                for id in value.split(',').map(str::trim).filter(|id| !id.is_empty()) {
+                    metadata.depends_on.push(
+                        Uuid::parse_str(id)
+                            .map_err(|e| format!("invalid dependency {:?}: {}", id, e))?,
+                    );
+                }
+            }
+            "transactional" => metadata.transactional = Some(parse_flag("transactional", value)?),
+            "repeatable" => metadata.repeatable = Some(parse_flag("repeatable", value)?),
+            key if is_key(key) => return Err(format!("unknown key {:?}", key)),
+            _ => {}
+        }
+    }
+    Ok(metadata)
+}
+
+/// Split the SQL of an `.up.sql` file into its header, which is the comments
+/// and blank lines at the top, and the rest.
+fn split_header(sql: &str) -> (&str, &str) {
+    let mut header = 0;
+    for line in sql.split_inclusive('\n') {
+        let trimmed = line.trim();
+        if !trimmed.is_empty() && !trimmed.starts_with("--") {
+            break;
+        }
+        header += line.len();
+    }
+    sql.split_at(header)
+}
+
+/// Whether a comment starting with `key:` declares a key, which is a
+/// `snake_case` word, rather than being prose such as `Note:`.
+fn is_key(key: &str) -> bool {
+    key.starts_with(|c: char| c.is_ascii_lowercase())
+        && key
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+/// Parse the value of a boolean header key.
+fn parse_flag(key: &str, value: &str) -> Result<bool, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{} must be true or false, not {:?}", key, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Create an empty directory containing the given files.
+    fn sql_dir(name: &str, files: &[(&str, &str)]) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("schemerz-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir(&dir).unwrap();
+        for (file_name, contents) in files {
+            std::fs::write(dir.join(file_name), contents).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn test_load_dir() {
+        let id1 = "4885e8ab-dafa-4d76-a565-2dee8b04ef60";
+        let id2 = "0b0a4ca6-4a5b-4b8e-9fd4-29b0bd4d48ee";
+        let dir = sql_dir(
+            "sql-load-dir",
+            &[
+                (
+                    &format!("{}_create_users.up.sql", id1),
+                    "-- Creates the users table.\n-- description: Create users\nCREATE TABLE users (email text);",
+                ),
+                (&format!("{}_create_users.down.sql", id1), "DROP TABLE users;"),
+                (
+                    &format!("{}_index_users.up.sql", id2),
+                    "-- description: Ignored, as there is a sidecar\nCREATE INDEX CONCURRENTLY users_email ON users (email);",
+                ),
+                (
+                    &format!("{}_index_users.toml", id2),
+                    &format!(
+                        "depends_on = [\"{}\"]\ntransactional = false\nrepeatable = true\n",
+                        id1
+                    ),
+                ),
+                ("README.md", "Not a migration."),
+            ],
+        );
+
+        let migrations = load_dir(&dir).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        let id1 = Uuid::parse_str(id1).unwrap();
+        let id2 = Uuid::parse_str(id2).unwrap();
+
+        // Sorted by file name, so the dependency comes second.
+        let [index, create] = &migrations[..] else {
+            panic!("Expected two migrations, got {:?}", migrations);
+        };
+        assert_eq!(create.id(), id1);
+        assert_eq!(create.name(), "create_users");
+        assert_eq!(create.description(), "Create users");
+        assert!(create.dependencies().is_empty());
+        assert!(create.is_reversible());
+        assert!(create.is_transactional());
+        assert!(!create.is_repeatable());
+        assert_eq!(create.down_sql(), Some("DROP TABLE users;"));
+
+        assert_eq!(index.id(), id2);
+        assert_eq!(index.description(), "index_users");
+        assert_eq!(index.dependencies(), [id1].into_iter().collect());
+        assert!(!index.is_reversible());
+        assert!(!index.is_transactional());
+        assert!(index.is_repeatable());
+        assert_ne!(index.fingerprint(), create.fingerprint());
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        let migration = |up| SqlMigration::embedded(1, "name", "", &[], true, false, up, None);
+        let fingerprint = migration("-- description: One\nCREATE TABLE t (a int);\n").fingerprint();
+
+        // Editing the header does not change the fingerprint, but editing the
+        // SQL does.
+        assert_eq!(
+            migration("-- description: Two\n\n-- Creates t.\nCREATE TABLE t (a int);\n")
+                .fingerprint(),
+            fingerprint
+        );
+        assert_ne!(
+            migration("-- description: One\nCREATE TABLE t (b int);\n").fingerprint(),
+            fingerprint
+        );
+    }
+
+    #[test]
+    fn test_load_dir_errors() {
+        let id = "4885e8ab-dafa-4d76-a565-2dee8b04ef60";
+        let load = |name: &str, files: &[(&str, &str)]| {
+            let dir = sql_dir(name, files);
+            let result = load_dir(&dir);
+            std::fs::remove_dir_all(&dir).unwrap();
+            result
+        };
+
+        assert!(matches!(
+            load("sql-file-name", &[("not-a-uuid_name.up.sql", "")]),
+            Err(LoadError::FileName(_))
+        ));
+        assert!(matches!(
+            load("sql-missing-up", &[(&format!("{}_name.down.sql", id), "")]),
+            Err(LoadError::MissingUp(_))
+        ));
+        assert!(matches!(
+            load(
+                "sql-header",
+                &[(&format!("{}_name.up.sql", id), "-- depends_on: nope\n")]
+            ),
+            Err(LoadError::Header { .. })
+        ));
+        assert!(matches!(
+            load(
+                "sql-header-key",
+                &[(
+                    &format!("{}_name.up.sql", id),
+                    "-- depend_on: 0b0a4ca6-4a5b-4b8e-9fd4-29b0bd4d48ee\n"
+                )]
+            ),
+            Err(LoadError::Header { message, .. }) if message == r#"unknown key "depend_on""#
+        ));
+        assert!(load(
+            "sql-header-prose",
+            &[(
+                &format!("{}_name.up.sql", id),
+                "-- Note: users are created by the app.\n-- See https://example.com\n"
+            )]
+        )
+        .is_ok());
+        assert!(matches!(
+            load(
+                "sql-sidecar",
+                &[
+                    (&format!("{}_name.up.sql", id), ""),
+                    (&format!("{}_name.toml", id), "depends = []\n"),
+                ]
+            ),
+            Err(LoadError::Sidecar { .. })
+        ));
+    }
+}
@@ -0,0 +1,337 @@
+//! Names of the tables in which adapters record applied migrations.
+
+use std::fmt::{self, Display};
+
+use thiserror::Error;
+
+/// The longest identifier, in bytes, that PostgreSQL stores without
+/// truncating it.
+const MAX_IDENTIFIER_LEN: usize = 63;
+
+/// The number of bytes reserved at the end of a table name for the suffixes
+/// adapters append to name their other tables, such as `_history`.
+const RESERVED_SUFFIX_LEN: usize = 8;
+
+/// Error resulting from an invalid [`TableName`].
+#[derive(Debug, Error)]
+pub enum TableNameError {
+    #[error("Table and schema names must not be empty")]
+    Empty,
+    #[error("Name {name:?} is longer than {max_len} bytes")]
+    TooLong { name: String, max_len: usize },
+    #[error("Name {0:?} contains a NUL character")]
+    Nul(String),
+    #[error(
+        "Name {0:?} contains '.'; use `TableName::parse` to qualify a table name with its schema"
+    )]
+    Qualified(String),
+    #[error("Name {0:?} contains an uppercase letter, which an unquoted name would not keep; use `TableName::parse` to fold or quote it")]
+    Uppercase(String),
+    #[error("Name {0:?} is quoted; use `TableName::parse` to unquote it")]
+    Quoted(String),
+    #[error("Cannot parse {0:?} as a table name, optionally qualified by its schema")]
+    Syntax(String),
+}
+
+/// The name of a table used by an adapter to store migration metadata,
+/// optionally qualified by the schema it is in.
+///
+/// Names are validated when they are constructed, and are always quoted when
+/// they are written into SQL. Formatting a `TableName` with [`Display`]
+/// produces the quoted, qualified name, such as `"app"."_schemerz"`.
+///
+/// Adapters used to take the table name as a string that they wrote into SQL
+/// as it was, so that `app.schemerz` named a table in the `app` schema, and
+/// PostgreSQL folded `MyTable` to `mytable`. [`TableName::parse`] reads such
+/// strings the same way. [`TableName::new`] and [`TableName::with_schema`]
+/// take names as they are, and reject the ones that would be read
+/// differently, so that they never silently name another table.
+///
+/// ```rust
+/// # extern crate schemerz;
+/// use schemerz::TableName;
+///
+/// let name = TableName::new("_schemerz").unwrap().with_schema("app").unwrap();
+/// assert_eq!(name.to_string(), r#""app"."_schemerz""#);
+/// assert_eq!(TableName::parse("app._schemerz").unwrap(), name);
+/// assert_eq!(TableName::parse("MyTable").unwrap().table(), "mytable");
+/// assert!(TableName::new("MyTable").is_err());
+/// assert!(TableName::new("").is_err());
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TableName {
+    schema: Option<String>,
+    table: String,
+}
+
+impl TableName {
+    /// Construct an unqualified table name.
+    ///
+    /// Fails if `table` is empty, contains a NUL character, or is longer than
+    /// 55 bytes. This leaves room within PostgreSQL's 63-byte limit for the
+    /// suffixes adapters append to name their other tables. Also fails if
+    /// `table` contains `.` or an uppercase letter, or starts with a quote,
+    /// which [`TableName::parse`] reads differently.
+    pub fn new(table: impl Into<String>) -> Result<Self, TableNameError> {
+        let table = table.into();
+        check_literal(&table)?;
+        validate(&table, MAX_IDENTIFIER_LEN - RESERVED_SUFFIX_LEN)?;
+        Ok(TableName {
+            schema: None,
+            table,
+        })
+    }
+
+    /// Parse a table name as written in SQL, optionally qualified by its
+    /// schema, such as `app.schemerz` or `"MyTable"`.
+    ///
+    /// Unquoted names are folded to lowercase, as PostgreSQL does, and quoted
+    /// names are kept as they are, with `""` standing for a quote. Fails if
+    /// `name` is not one or two such names separated by `.`, or if either is
+    /// empty, contains a NUL character, or is too long, as for
+    /// [`TableName::new`] and [`TableName::with_schema`].
+    pub fn parse(name: &str) -> Result<Self, TableNameError> {
+        let syntax = || TableNameError::Syntax(name.into());
+        let mut parts = vec![];
+        let mut rest = name;
+        loop {
+            let (part, after) = parse_identifier(rest).ok_or_else(syntax)?;
+            parts.push(part);
+            match after.strip_prefix('.') {
+                Some(after) => rest = after,
+                None if after.is_empty() => break,
+                None => return Err(syntax()),
+            }
+        }
+
+        let mut parts = parts.into_iter();
+        let (schema, table) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(table), None, None) => (None, table),
+            (Some(schema), Some(table), None) => (Some(schema), table),
+            _ => return Err(syntax()),
+        };
+        validate(&table, MAX_IDENTIFIER_LEN - RESERVED_SUFFIX_LEN)?;
+        if let Some(schema) = &schema {
+            validate(schema, MAX_IDENTIFIER_LEN)?;
+        }
+        Ok(TableName { schema, table })
+    }
+
+    /// Qualify this table name with the schema it is in.
+    ///
+    /// Fails if `schema` is empty, contains a NUL character, or is longer than
+    /// 63 bytes, or if it contains `.` or an uppercase letter, or starts with
+    /// a quote, as for [`TableName::new`].
+    pub fn with_schema(self, schema: impl Into<String>) -> Result<Self, TableNameError> {
+        let schema = schema.into();
+        check_literal(&schema)?;
+        validate(&schema, MAX_IDENTIFIER_LEN)?;
+        Ok(TableName {
+            schema: Some(schema),
+            ..self
+        })
+    }
+
+    /// Returns the schema this table is in, if it is qualified.
+    pub fn schema(&self) -> Option<&str> {
+        self.schema.as_deref()
+    }
+
+    /// Returns the unqualified name of this table.
+    pub fn table(&self) -> &str {
+        &self.table
+    }
+
+    /// Returns the name of the table in the same schema named by appending
+    /// `suffix`, which should be at most 8 bytes long.
+    pub fn with_suffix(&self, suffix: &str) -> TableName {
+        TableName {
+            schema: self.schema.clone(),
+            table: format!("{}{}", self.table, suffix),
+        }
+    }
+
+    /// Returns the quoted schema name, if this table name is qualified.
+    pub fn quoted_schema(&self) -> Option<String> {
+        self.schema.as_deref().map(quote)
+    }
+}
+
+impl Default for TableName {
+    /// The default table name, `_schemerz`.
+    fn default() -> Self {
+        TableName {
+            schema: None,
+            table: "_schemerz".into(),
+        }
+    }
+}
+
+impl Display for TableName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(schema) = &self.schema {
+            write!(f, "{}.", quote(schema))?;
+        }
+        write!(f, "{}", quote(&self.table))
+    }
+}
+
+fn validate(name: &str, max_len: usize) -> Result<(), TableNameError> {
+    if name.is_empty() {
+        Err(TableNameError::Empty)
+    } else if name.contains('\0') {
+        Err(TableNameError::Nul(name.into()))
+    } else if name.len() > max_len {
+        Err(TableNameError::TooLong {
+            name: name.into(),
+            max_len,
+        })
+    } else {
+        Ok(())
+    }
+}
+
+/// Reject names that [`TableName::parse`] would read as another name, so that
+/// passing the string an adapter used to take to [`TableName::new`] fails
+/// rather than naming another table.
+fn check_literal(name: &str) -> Result<(), TableNameError> {
+    if name.contains('.') {
+        Err(TableNameError::Qualified(name.into()))
+    } else if name.chars().any(|c| c.is_ascii_uppercase()) {
+        Err(TableNameError::Uppercase(name.into()))
+    } else if name.starts_with('"') {
+        Err(TableNameError::Quoted(name.into()))
+    } else {
+        Ok(())
+    }
+}
+
+/// Parse an identifier as written in SQL from the start of `s`, returning it
+/// and the rest of `s`, or `None` if `s` does not start with one.
+fn parse_identifier(s: &str) -> Option<(String, &str)> {
+    if let Some(mut rest) = s.strip_prefix('"') {
+        let mut identifier = String::new();
+        loop {
+            let end = rest.find('"')?;
+            identifier.push_str(&rest[..end]);
+            rest = &rest[end + 1..];
+            match rest.strip_prefix('"') {
+                Some(after) => {
+                    identifier.push('"');
+                    rest = after;
+                }
+                None => return Some((identifier, rest)),
+            }
+        }
+    }
+
+    let end = s
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '$'))
+        .unwrap_or(s.len());
+    let (identifier, rest) = s.split_at(end);
+    if identifier.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        Some((identifier.to_ascii_lowercase(), rest))
+    } else {
+        None
+    }
+}
+
+/// Quote an SQL identifier, doubling any quotes within it.
+fn quote(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new() {
+        let name = TableName::new(r#"odd "name""#).unwrap();
+        assert_eq!(name.to_string(), r#""odd ""name""""#);
+        assert_eq!(
+            name.with_suffix("_history").to_string(),
+            r#""odd ""name""_history""#
+        );
+        assert_eq!(TableName::default().to_string(), r#""_schemerz""#);
+
+        let name = TableName::default().with_schema("app").unwrap();
+        assert_eq!(name.quoted_schema().as_deref(), Some(r#""app""#));
+        assert_eq!(
+            name.with_suffix("_history").to_string(),
+            r#""app"."_schemerz_history""#
+        );
+
+        assert!(matches!(TableName::new(""), Err(TableNameError::Empty)));
+        assert!(matches!(
+            TableName::new("a\0b"),
+            Err(TableNameError::Nul(_))
+        ));
+        assert!(matches!(
+            TableName::new("a".repeat(56)),
+            Err(TableNameError::TooLong { max_len: 55, .. })
+        ));
+        assert!(TableName::default().with_schema("a".repeat(63)).is_ok());
+
+        // Names that adapters used to read as SQL are rejected rather than
+        // naming another table.
+        assert!(matches!(
+            TableName::new("app.schemerz"),
+            Err(TableNameError::Qualified(_))
+        ));
+        assert!(matches!(
+            TableName::new("MyTable"),
+            Err(TableNameError::Uppercase(_))
+        ));
+        assert!(matches!(
+            TableName::new(r#""my_table""#),
+            Err(TableNameError::Quoted(_))
+        ));
+        assert!(matches!(
+            TableName::default().with_schema("App"),
+            Err(TableNameError::Uppercase(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse() {
+        let name = TableName::parse("MyTable").unwrap();
+        assert_eq!((name.schema(), name.table()), (None, "mytable"));
+
+        let name = TableName::parse("app.Schemerz_1$").unwrap();
+        assert_eq!((name.schema(), name.table()), (Some("app"), "schemerz_1$"));
+
+        let name = TableName::parse(r#""My.App"."odd ""name""""#).unwrap();
+        assert_eq!(
+            (name.schema(), name.table()),
+            (Some("My.App"), r#"odd "name""#)
+        );
+        assert_eq!(name.to_string(), r#""My.App"."odd ""name""""#);
+
+        for invalid in [
+            "",
+            "a.b.c",
+            "a.",
+            ".a",
+            "1table",
+            "my table",
+            "a;DROP TABLE b",
+            r#""unterminated"#,
+            r#""a"b"#,
+        ] {
+            assert!(
+                matches!(TableName::parse(invalid), Err(TableNameError::Syntax(_))),
+                "{:?}",
+                invalid
+            );
+        }
+        assert!(matches!(
+            TableName::parse(r#""""#),
+            Err(TableNameError::Empty)
+        ));
+        assert!(matches!(
+            TableName::parse(&"a".repeat(56)),
+            Err(TableNameError::TooLong { max_len: 55, .. })
+        ));
+    }
+}
@@ -12,6 +12,20 @@ pub trait TestAdapter<I>: Adapter<I> {
     /// For convenience adapters can implement their migration traits on
     /// `TestMigration` and construct those here.
     fn mock(id: I, dependencies: HashSet<I>) -> Self::MigrationType;
+
+    /// Construct a mock, no-op baseline replacing the given migrations, or
+    /// `None` to skip the tests for baselines.
+    ///
+    /// The default implementation returns `None`. Adapters whose migrations
+    /// can replace others should return a `TestMigration` built with
+    /// [`TestMigration::with_replaces`].
+    fn mock_baseline(
+        _id: I,
+        _dependencies: HashSet<I>,
+        _replaces: HashSet<I>,
+    ) -> Option<Self::MigrationType> {
+        None
+    }
 }
 
 /// A trivial struct implementing `Migration` on which adapters can build their
@@ -19,11 +33,46 @@ pub trait TestAdapter<I>: Adapter<I> {
 pub struct TestMigration<I> {
     id: I,
     dependencies: HashSet<I>,
+    fingerprint: Option<Vec<u8>>,
+    reversible: bool,
+    replaces: HashSet<I>,
+    repeatable: bool,
 }
 
 impl<I> TestMigration<I> {
     pub fn new(id: I, dependencies: HashSet<I>) -> Self {
-        TestMigration { id, dependencies }
+        TestMigration {
+            id,
+            dependencies,
+            fingerprint: None,
+            reversible: true,
+            replaces: HashSet::new(),
+            repeatable: false,
+        }
+    }
+
+    /// Set the fingerprint of this migration.
+    pub fn with_fingerprint(mut self, fingerprint: Vec<u8>) -> Self {
+        self.fingerprint = Some(fingerprint);
+        self
+    }
+
+    /// Mark this migration as irreversible.
+    pub fn irreversible(mut self) -> Self {
+        self.reversible = false;
+        self
+    }
+
+    /// Make this migration a baseline that replaces the given migrations.
+    pub fn with_replaces(mut self, replaces: HashSet<I>) -> Self {
+        self.replaces = replaces;
+        self
+    }
+
+    /// Mark this migration as repeatable.
+    pub fn repeatable(mut self) -> Self {
+        self.repeatable = true;
+        self
     }
 }
 
@@ -39,6 +88,22 @@ impl<I: Clone> Migration<I> for TestMigration<I> {
     fn description(&self) -> &'static str {
         "Test Migration"
     }
+
+    fn fingerprint(&self) -> Option<Vec<u8>> {
+        self.fingerprint.clone()
+    }
+
+    fn is_reversible(&self) -> bool {
+        self.reversible
+    }
+
+    fn replaces(&self) -> HashSet<I> {
+        self.replaces.clone()
+    }
+
+    fn is_repeatable(&self) -> bool {
+        self.repeatable
+    }
 }
 
 /// Test an `Adapter` with the generic test suite.
@@ -63,13 +128,14 @@ macro_rules! test_schemerz_adapter {
     ($constructor:expr, $id_ter:expr) => {
         test_schemerz_adapter!({}, $constructor, $id_ter);
     };
-    ($setup:stmt, $constructor:expr, $id_ter:expr) => {
+    ($setup:stmt, $constructor:expr, $id_ter:expr $(,)?) => {
         test_schemerz_adapter!($setup, $constructor, $id_ter,
             test_single_migration,
             test_migration_chain,
             test_multi_component_dag,
             test_branching_dag,
             test_migration_chain_reversed,
+            test_migration_plan,
         );
     };
     ($setup:stmt, $constructor:expr, $id_ter:expr, $($test_fn:ident),* $(,)*) => {
@@ -379,3 +445,232 @@ where
         assert!(!applied.contains(&uuid3));
     }
 }
+
+/// Test that planning reports the migrations `up` and `down` would run,
+/// without applying or reverting any of them.
+pub fn test_migration_plan<I, A, T>(adapter: A, mut id_iter: T)
+where
+    I: Clone + FromStr + Debug + Display + Hash + Eq,
+    I::Err: Debug,
+    A: TestAdapter<I>,
+    T: Iterator<Item = I>,
+{
+    let migration1 = A::mock(id_iter.next().unwrap(), HashSet::new());
+    let migration2 = A::mock(
+        id_iter.next().unwrap(),
+        vec![migration1.id()].into_iter().collect(),
+    );
+    let migration3 = A::mock(
+        id_iter.next().unwrap(),
+        vec![migration2.id()].into_iter().collect(),
+    );
+
+    let uuid1 = migration1.id();
+    let uuid2 = migration2.id();
+    let uuid3 = migration3.id();
+
+    let mut migrator = Migrator::new(adapter);
+
+    migrator
+        .register_multiple(vec![migration1, migration2, migration3].into_iter())
+        .expect("Migration registration failed");
+
+    let plan = migrator
+        .plan_up(Some(uuid2.clone()))
+        .expect("Up planning failed");
+    assert_eq!(
+        plan.iter().map(|m| m.id.clone()).collect::<Vec<_>>(),
+        vec![uuid1.clone(), uuid2.clone()]
+    );
+    assert!(plan.iter().all(|m| m.direction == MigrationDirection::Up));
+    assert!(migrator.adapter.applied_migrations().unwrap().is_empty());
+
+    migrator
+        .up(Some(uuid2.clone()))
+        .expect("Up migration failed");
+
+    let plan = migrator.plan_up(None).expect("Up planning failed");
+    assert_eq!(
+        plan.iter().map(|m| m.id.clone()).collect::<Vec<_>>(),
+        vec![uuid3.clone()]
+    );
+
+    let plan = migrator
+        .plan_down(Some(uuid1.clone()))
+        .expect("Down planning failed");
+    assert_eq!(
+        plan.iter().map(|m| m.id.clone()).collect::<Vec<_>>(),
+        vec![uuid2.clone()]
+    );
+    assert!(plan.iter().all(|m| m.direction == MigrationDirection::Down));
+
+    let plan = migrator.plan_down(None).expect("Down planning failed");
+    assert_eq!(
+        plan.iter().map(|m| m.id.clone()).collect::<Vec<_>>(),
+        vec![uuid2.clone(), uuid1.clone()]
+    );
+
+    {
+        let applied = migrator.adapter.applied_migrations().unwrap();
+        assert!(applied.contains(&uuid1));
+        assert!(applied.contains(&uuid2));
+        assert!(!applied.contains(&uuid3));
+    }
+
+    // A baseline replacing migrations 4 <- 5, on which 6 depends.
+    let uuid4 = id_iter.next().unwrap();
+    let uuid5 = id_iter.next().unwrap();
+    let uuid6 = id_iter.next().unwrap();
+    let baseline_uuid = id_iter.next().unwrap();
+    let Some(baseline) = A::mock_baseline(
+        baseline_uuid.clone(),
+        HashSet::new(),
+        vec![uuid4.clone(), uuid5.clone()].into_iter().collect(),
+    ) else {
+        return;
+    };
+    migrator
+        .register_multiple(
+            vec![
+                A::mock(uuid4.clone(), HashSet::new()),
+                A::mock(uuid5.clone(), vec![uuid4.clone()].into_iter().collect()),
+                A::mock(uuid6.clone(), vec![uuid5.clone()].into_iter().collect()),
+                baseline,
+            ]
+            .into_iter(),
+        )
+        .expect("Migration registration failed");
+
+    // The baseline is only run if all of the migrations it replaces are being
+    // applied, so it is not recorded along with migrations beyond the target.
+    let plan = migrator
+        .plan_up(Some(uuid5.clone()))
+        .expect("Up planning failed");
+    assert_eq!(
+        plan.iter()
+            .map(|m| (m.id.clone(), m.recorded_only))
+            .collect::<Vec<_>>(),
+        vec![
+            (baseline_uuid.clone(), false),
+            (uuid4.clone(), true),
+            (uuid5.clone(), true),
+        ]
+    );
+    let plan = migrator
+        .plan_up(Some(uuid4.clone()))
+        .expect("Up planning failed");
+    assert_eq!(
+        plan.iter()
+            .map(|m| (m.id.clone(), m.recorded_only))
+            .collect::<Vec<_>>(),
+        vec![(uuid4.clone(), false)]
+    );
+
+    migrator
+        .up(Some(uuid4.clone()))
+        .expect("Up migration failed");
+    {
+        let applied = migrator.adapter.applied_migrations().unwrap();
+        assert!(applied.contains(&uuid4));
+        assert!(!applied.contains(&uuid5));
+        assert!(!applied.contains(&baseline_uuid));
+    }
+
+    // Once all of the migrations it replaces are applied, the baseline is
+    // recorded as applied.
+    migrator.up(None).expect("Up migration failed");
+    assert!(migrator
+        .adapter
+        .applied_migrations()
+        .unwrap()
+        .contains(&baseline_uuid));
+
+    // Reverting any of the migrations a baseline replaces reverts the
+    // baseline after them.
+    let plan = migrator
+        .plan_down(Some(uuid4.clone()))
+        .expect("Down planning failed");
+    assert_eq!(
+        plan.iter()
+            .map(|m| (m.id.clone(), m.recorded_only))
+            .collect::<Vec<_>>(),
+        vec![
+            (uuid6.clone(), false),
+            (uuid5.clone(), false),
+            (baseline_uuid.clone(), true),
+        ]
+    );
+
+    migrator
+        .down(Some(uuid4.clone()))
+        .expect("Down migration failed");
+    {
+        let applied = migrator.adapter.applied_migrations().unwrap();
+        assert!(applied.contains(&uuid4));
+        assert!(!applied.contains(&uuid5));
+        assert!(!applied.contains(&uuid6));
+        assert!(!applied.contains(&baseline_uuid));
+    }
+
+    // Targeting a baseline also targets the migrations it replaces.
+    let plan = migrator
+        .plan_up(Some(baseline_uuid.clone()))
+        .expect("Up planning failed");
+    assert_eq!(
+        plan.iter()
+            .map(|m| (m.id.clone(), m.recorded_only))
+            .collect::<Vec<_>>(),
+        vec![(uuid5.clone(), false), (baseline_uuid.clone(), true)]
+    );
+    migrator
+        .up(Some(baseline_uuid.clone()))
+        .expect("Up migration failed");
+    {
+        let applied = migrator.adapter.applied_migrations().unwrap();
+        assert!(applied.contains(&uuid5));
+        assert!(!applied.contains(&uuid6));
+        assert!(applied.contains(&baseline_uuid));
+    }
+
+    // The migrations a baseline replaces do not depend on it, so reverting to
+    // the baseline leaves them applied.
+    let plan = migrator
+        .plan_down(Some(baseline_uuid.clone()))
+        .expect("Down planning failed");
+    assert!(plan.is_empty());
+    migrator
+        .down(Some(baseline_uuid.clone()))
+        .expect("Down migration failed");
+    {
+        let applied = migrator.adapter.applied_migrations().unwrap();
+        assert!(applied.contains(&uuid4));
+        assert!(applied.contains(&uuid5));
+        assert!(applied.contains(&baseline_uuid));
+    }
+
+    migrator.down(None).expect("Down migration failed");
+    assert!(migrator.adapter.applied_migrations().unwrap().is_empty());
+    let plan = migrator
+        .plan_up(Some(baseline_uuid.clone()))
+        .expect("Up planning failed");
+    assert_eq!(
+        plan.iter()
+            .map(|m| (m.id.clone(), m.recorded_only))
+            .collect::<Vec<_>>(),
+        vec![
+            (baseline_uuid.clone(), false),
+            (uuid4.clone(), true),
+            (uuid5.clone(), true),
+        ]
+    );
+    migrator
+        .up(Some(baseline_uuid.clone()))
+        .expect("Up migration failed");
+    {
+        let applied = migrator.adapter.applied_migrations().unwrap();
+        assert!(applied.contains(&uuid4));
+        assert!(applied.contains(&uuid5));
+        assert!(!applied.contains(&uuid6));
+        assert!(applied.contains(&baseline_uuid));
+    }
+}